@@ -0,0 +1,22 @@
+create table if not exists page (
+    page_id    text    not null primary key
+  , algorithm  text    not null
+  , params     text    not null default '{}'
+  , created_at integer not null default (unixepoch('subsec') * 1000)
+) strict;
+
+-- Pages for the built-in algorithms. Their ids match the page names that were
+-- recorded on vote events before pages could be defined by clients.
+insert into page (page_id, algorithm) values
+    ('newest', 'newest')
+  , ('hacker_news', 'hacker_news')
+  , ('quality_news', 'quality_news');
+
+create table if not exists page_rank_history (
+    page_id     text    not null references page(page_id) on delete cascade
+  , sample_time integer not null
+  , item_id     integer not null references item(item_id)
+  , rank        integer not null
+  , upvotes     integer
+  , primary key(page_id, sample_time, item_id)
+) strict;
@@ -0,0 +1,12 @@
+create table if not exists upvote_share_model (
+    model_version integer not null primary key autoincrement
+  , created_at    integer not null default (unixepoch('subsec') * 1000)
+) strict;
+
+create table if not exists upvote_share_model_coefficients (
+    model_version        integer not null references upvote_share_model(model_version)
+  , page_id              text    not null
+  , intercept            real    not null
+  , log_rank_coefficient real    not null
+  , primary key(model_version, page_id)
+) strict;
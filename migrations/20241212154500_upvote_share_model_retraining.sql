@@ -0,0 +1,23 @@
+alter table upvote_share_model add column training_start integer not null default 0;
+alter table upvote_share_model add column training_end   integer not null default 0;
+
+-- Models trained so far used all data up to their creation
+update upvote_share_model set training_end = created_at;
+
+alter table upvote_share_model_coefficients add column n_observations integer not null default 0;
+alter table upvote_share_model_coefficients add column deviance       real    not null default 0.0;
+alter table upvote_share_model_coefficients add column null_deviance  real    not null default 0.0;
+
+create table if not exists active_upvote_share_model (
+    singleton     integer not null primary key check (singleton = 1)
+  , model_version integer not null references upvote_share_model(model_version)
+  , activated_at  integer not null
+) strict;
+
+insert into active_upvote_share_model (singleton, model_version, activated_at)
+select
+      1
+    , max(model_version)
+    , unixepoch('subsec') * 1000
+from upvote_share_model
+having max(model_version) is not null;
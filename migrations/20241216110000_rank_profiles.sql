@@ -0,0 +1,18 @@
+-- Ranks are now sampled several times per interval
+alter table rank_history add column sample_time integer not null default 0;
+
+update rank_history set sample_time = (
+    select start_time
+    from qn_sample_interval i
+    where i.interval_id = rank_history.interval_id
+);
+
+-- Time in milliseconds each item spent at each combination of ranks during
+-- an interval
+create table if not exists rank_profile (
+    item_id     integer not null references item(item_id)
+  , interval_id integer not null references qn_sample_interval(interval_id)
+  , rank_top    integer
+  , rank_new    integer
+  , duration    integer not null
+) strict;
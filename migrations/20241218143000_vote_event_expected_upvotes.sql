@@ -0,0 +1,32 @@
+-- Expected upvotes estimated from the rank and page recorded on vote events,
+-- for pages whose ranks the service never observes
+alter table stats_history add column vote_event_expected_upvotes      real not null default 0.0;
+alter table stats_history add column vote_event_expected_upvote_share real not null default 0.0;
+
+alter table stats add column cumulative_vote_event_expected_upvotes real not null default 0.0;
+
+drop trigger after_insert_stats_history;
+
+create trigger after_insert_stats_history
+after insert on stats_history
+begin
+  insert into stats (
+      item_id
+    , updated_at
+    , cumulative_upvotes
+    , cumulative_expected_upvotes
+    , cumulative_vote_event_expected_upvotes
+  )
+  values (
+      new.item_id
+    , unixepoch('subsec') * 1000
+    , new.upvotes
+    , new.expected_upvotes
+    , new.vote_event_expected_upvotes
+  )
+  on conflict (item_id) do update set
+      updated_at                             = unixepoch('subsec') * 1000
+    , cumulative_upvotes                     = stats.cumulative_upvotes + new.upvotes
+    , cumulative_expected_upvotes            = stats.cumulative_expected_upvotes + new.expected_upvotes
+    , cumulative_vote_event_expected_upvotes = stats.cumulative_vote_event_expected_upvotes + new.vote_event_expected_upvotes;
+end;
@@ -0,0 +1,44 @@
+-- Impressions reported by clients, aggregated per second
+create table if not exists impression (
+    page_id     text    not null references page(page_id) on delete cascade
+  , item_id     integer not null references item(item_id)
+  , rank        integer not null
+  , bucket_time integer not null
+  , impressions integer not null
+  , primary key(page_id, item_id, rank, bucket_time)
+) strict, without rowid;
+
+create index if not exists impression_bucket_time on impression(bucket_time);
+
+alter table stats_history add column impression_expected_upvotes real not null default 0.0;
+
+alter table stats add column cumulative_impression_expected_upvotes real not null default 0.0;
+
+drop trigger after_insert_stats_history;
+
+create trigger after_insert_stats_history
+after insert on stats_history
+begin
+  insert into stats (
+      item_id
+    , updated_at
+    , cumulative_upvotes
+    , cumulative_expected_upvotes
+    , cumulative_vote_event_expected_upvotes
+    , cumulative_impression_expected_upvotes
+  )
+  values (
+      new.item_id
+    , unixepoch('subsec') * 1000
+    , new.upvotes
+    , new.expected_upvotes
+    , new.vote_event_expected_upvotes
+    , new.impression_expected_upvotes
+  )
+  on conflict (item_id) do update set
+      updated_at                             = unixepoch('subsec') * 1000
+    , cumulative_upvotes                     = stats.cumulative_upvotes + new.upvotes
+    , cumulative_expected_upvotes            = stats.cumulative_expected_upvotes + new.expected_upvotes
+    , cumulative_vote_event_expected_upvotes = stats.cumulative_vote_event_expected_upvotes + new.vote_event_expected_upvotes
+    , cumulative_impression_expected_upvotes = stats.cumulative_impression_expected_upvotes + new.impression_expected_upvotes;
+end;
@@ -0,0 +1,7 @@
+-- Latest snapshot of each ranking, persisted if RANKING_SNAPSHOT_PERSIST is set
+create table if not exists ranking_snapshot (
+    ranking     text    not null primary key
+  , snapshot_id integer not null
+  , computed_at integer not null
+  , items       text    not null
+) strict;
@@ -0,0 +1,12 @@
+-- A/B experiments. Each arm is a page, so that the votes and impressions of
+-- the users assigned to an arm are attributed to it by their page.
+create table if not exists experiment (
+    experiment_id text    not null primary key
+  , arms          text    not null
+  , created_at    integer not null
+  , ended_at      integer
+) strict;
+
+-- At most one experiment runs at a time
+create unique index if not exists experiment_running
+on experiment((ended_at is null)) where ended_at is null;
@@ -0,0 +1,3 @@
+-- Page for the best algorithm, ranking by all votes
+insert into page (page_id, algorithm) values ('best', 'best')
+on conflict do nothing;
@@ -0,0 +1,3 @@
+-- Page for the top algorithm, ranking by all upvotes
+insert into page (page_id, algorithm) values ('top', 'top')
+on conflict do nothing;
@@ -0,0 +1,3 @@
+-- Models activated by hand stay active when the model is retrained on
+-- schedule, until a model is trained by hand
+alter table active_upvote_share_model add column pinned integer not null default 0;
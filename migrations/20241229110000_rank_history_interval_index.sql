@@ -0,0 +1,2 @@
+-- Rank profiles are aggregated from the observations of an interval
+create index if not exists rank_history_interval_id on rank_history(interval_id);
@@ -0,0 +1,89 @@
+-- Votes are -1, 0 or 1. Rankings counted other values as neither an upvote
+-- nor a downvote, which storing them as 0 keeps.
+--
+-- SQLite can't add a check constraint to a table, so the vote tables are
+-- rebuilt. Migrations run in a transaction with foreign keys on, so the tables
+-- referencing vote_event are rebuilt with it rather than left pointing at a
+-- dropped table.
+create table vote_event_new (
+    vote_event_id integer not null primary key autoincrement
+  , item_id       integer not null references item(item_id)
+  , user_id       text    not null
+  , vote          integer not null check (vote in (-1, 0, 1))
+  , rank          integer
+  , page          text
+  , created_at    integer not null default (unixepoch('subsec') * 1000)
+) strict;
+
+insert into vote_event_new
+select
+    vote_event_id
+  , item_id
+  , user_id
+  , case when vote in (-1, 0, 1) then vote else 0 end
+  , rank
+  , page
+  , created_at
+from vote_event;
+
+create table vote_new (
+    vote_event_id integer not null references vote_event_new(vote_event_id)
+  , item_id       integer not null references item(item_id)
+  , user_id       text    not null
+  , vote          integer not null check (vote in (-1, 0, 1))
+  , created_at    integer not null
+  , primary key(user_id, item_id)
+) strict;
+
+insert into vote_new
+select
+    vote_event_id
+  , item_id
+  , user_id
+  , case when vote in (-1, 0, 1) then vote else 0 end
+  , created_at
+from vote;
+
+create table interleaving_credit_new (
+    vote_event_id   integer not null primary key references vote_event_new(vote_event_id)
+  , interleaving_id text    not null references interleaving(interleaving_id) on delete cascade
+  , user_id         text    not null
+  , served_at       integer not null
+  , algorithm       text    not null
+) strict;
+
+insert into interleaving_credit_new select * from interleaving_credit;
+
+drop table interleaving_credit;
+drop table vote;
+drop table vote_event;
+
+-- Renaming vote_event_new also renames the references to it
+alter table vote_event_new rename to vote_event;
+alter table vote_new rename to vote;
+alter table interleaving_credit_new rename to interleaving_credit;
+
+create index if not exists vote_event_created_at on vote_event(created_at);
+create index if not exists interleaving_credit_interleaving on interleaving_credit(interleaving_id);
+
+create trigger after_insert_vote_event
+after insert on vote_event
+begin
+  insert into vote (
+      vote_event_id
+    , item_id
+    , user_id
+    , vote
+    , created_at
+  )
+  values (
+      new.vote_event_id
+    , new.item_id
+    , new.user_id
+    , new.vote
+    , new.created_at
+  ) on conflict(user_id, item_id) do update set
+      vote          = new.vote
+    , vote_event_id = new.vote_event_id
+    , created_at    = new.created_at;
+end;
@@ -0,0 +1,199 @@
+-- Schema of the PostgreSQL backend, equivalent to the SQLite migrations up to
+-- 20241221090000_ranking_snapshots.sql. Times are milliseconds since the epoch.
+
+create table if not exists item (
+    item_id    integer not null primary key
+  , parent_id  integer          references item(item_id)
+  , author_id  text    not null
+  , created_at bigint  not null default (extract(epoch from now()) * 1000)::bigint
+);
+
+create table if not exists vote_event (
+    vote_event_id integer not null primary key
+  , item_id       integer not null references item(item_id)
+  , user_id       text    not null
+  , vote          integer not null
+  , rank          integer
+  , page          text
+  , created_at    bigint  not null default (extract(epoch from now()) * 1000)::bigint
+);
+
+create table if not exists vote (
+    vote_event_id integer not null references vote_event(vote_event_id)
+  , item_id       integer not null references item(item_id)
+  , user_id       text    not null
+  , vote          integer not null
+  , created_at    bigint  not null
+  , primary key(user_id, item_id)
+);
+
+create function after_insert_vote_event() returns trigger as $$
+begin
+  insert into vote (
+      vote_event_id
+    , item_id
+    , user_id
+    , vote
+    , created_at
+  )
+  values (
+      new.vote_event_id
+    , new.item_id
+    , new.user_id
+    , new.vote
+    , new.created_at
+  ) on conflict(user_id, item_id) do update set
+      vote          = excluded.vote
+    , vote_event_id = excluded.vote_event_id
+    , created_at    = excluded.created_at;
+  return new;
+end;
+$$ language plpgsql;
+
+create trigger after_insert_vote_event
+after insert on vote_event
+for each row execute function after_insert_vote_event();
+
+create table if not exists qn_sample_interval (
+    interval_id integer not null primary key generated by default as identity
+  , start_time  bigint  not null
+);
+
+create table if not exists stats_history (
+    item_id                          integer not null references item(item_id)
+  , interval_id                      integer not null references qn_sample_interval(interval_id)
+  , upvotes                          integer not null
+  , upvote_share                     real    not null
+  , expected_upvotes                 real    not null
+  , expected_upvote_share            real    not null
+  , vote_event_expected_upvotes      real    not null default 0.0
+  , vote_event_expected_upvote_share real    not null default 0.0
+  , impression_expected_upvotes      real    not null default 0.0
+);
+
+create table if not exists stats (
+    item_id                                integer not null primary key references item(item_id)
+  , updated_at                             bigint  not null
+  , cumulative_upvotes                     integer not null
+  , cumulative_expected_upvotes            real    not null
+  , cumulative_vote_event_expected_upvotes real    not null default 0.0
+  , cumulative_impression_expected_upvotes real    not null default 0.0
+);
+
+create function after_insert_stats_history() returns trigger as $$
+begin
+  insert into stats (
+      item_id
+    , updated_at
+    , cumulative_upvotes
+    , cumulative_expected_upvotes
+    , cumulative_vote_event_expected_upvotes
+    , cumulative_impression_expected_upvotes
+  )
+  values (
+      new.item_id
+    , (extract(epoch from now()) * 1000)::bigint
+    , new.upvotes
+    , new.expected_upvotes
+    , new.vote_event_expected_upvotes
+    , new.impression_expected_upvotes
+  )
+  on conflict (item_id) do update set
+      updated_at                             = excluded.updated_at
+    , cumulative_upvotes                     = stats.cumulative_upvotes + excluded.cumulative_upvotes
+    , cumulative_expected_upvotes            = stats.cumulative_expected_upvotes + excluded.cumulative_expected_upvotes
+    , cumulative_vote_event_expected_upvotes = stats.cumulative_vote_event_expected_upvotes + excluded.cumulative_vote_event_expected_upvotes
+    , cumulative_impression_expected_upvotes = stats.cumulative_impression_expected_upvotes + excluded.cumulative_impression_expected_upvotes;
+  return new;
+end;
+$$ language plpgsql;
+
+create trigger after_insert_stats_history
+after insert on stats_history
+for each row execute function after_insert_stats_history();
+
+create table if not exists rank_history (
+    item_id     integer not null
+  , interval_id integer not null references qn_sample_interval(interval_id)
+  , rank_top    integer
+  , rank_new    integer
+  , sample_time bigint  not null
+);
+
+create index if not exists rank_history_interval_id on rank_history(interval_id);
+
+-- Time in milliseconds each item spent at each combination of ranks during
+-- an interval
+create table if not exists rank_profile (
+    item_id     integer not null references item(item_id)
+  , interval_id integer not null references qn_sample_interval(interval_id)
+  , rank_top    integer
+  , rank_new    integer
+  , duration    bigint  not null
+);
+
+create table if not exists page (
+    page_id    text   not null primary key
+  , algorithm  text   not null
+  , params     jsonb  not null default '{}'
+  , created_at bigint not null default (extract(epoch from now()) * 1000)::bigint
+);
+
+-- Pages for the built-in algorithms
+insert into page (page_id, algorithm) values
+    ('newest', 'newest')
+  , ('hacker_news', 'hacker_news')
+  , ('quality_news', 'quality_news');
+
+create table if not exists page_rank_history (
+    page_id     text    not null references page(page_id) on delete cascade
+  , sample_time bigint  not null
+  , item_id     integer not null references item(item_id)
+  , rank        integer not null
+  , upvotes     integer
+  , primary key(page_id, sample_time, item_id)
+);
+
+create table if not exists upvote_share_model (
+    model_version  bigint not null primary key generated by default as identity
+  , created_at     bigint not null default (extract(epoch from now()) * 1000)::bigint
+  , training_start bigint not null default 0
+  , training_end   bigint not null default 0
+);
+
+create table if not exists upvote_share_model_coefficients (
+    model_version        bigint           not null references upvote_share_model(model_version)
+  , page_id              text             not null
+  , intercept            double precision not null
+  , log_rank_coefficient double precision not null
+  , n_observations       bigint           not null default 0
+  , deviance             double precision not null default 0.0
+  , null_deviance        double precision not null default 0.0
+  , primary key(model_version, page_id)
+);
+
+create table if not exists active_upvote_share_model (
+    singleton     integer not null primary key check (singleton = 1)
+  , model_version bigint  not null references upvote_share_model(model_version)
+  , activated_at  bigint  not null
+);
+
+-- Impressions reported by clients, aggregated per second
+create table if not exists impression (
+    page_id     text    not null references page(page_id) on delete cascade
+  , item_id     integer not null references item(item_id)
+  , rank        integer not null
+  , bucket_time bigint  not null
+  , impressions integer not null
+  , primary key(page_id, item_id, rank, bucket_time)
+);
+
+create index if not exists impression_bucket_time on impression(bucket_time);
+
+-- Latest snapshot of each ranking, persisted if RANKING_SNAPSHOT_PERSIST is set
+create table if not exists ranking_snapshot (
+    ranking     text   not null primary key
+  , snapshot_id bigint not null
+  , computed_at bigint not null
+  , items       text   not null
+);
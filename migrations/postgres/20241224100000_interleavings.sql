@@ -0,0 +1,29 @@
+-- Team-draft interleavings of two algorithms. Votes with an interleaving as
+-- their page are credited to the algorithm that contributed the voted rank.
+create table if not exists interleaving (
+    interleaving_id text   not null primary key
+  , algorithm_a     text   not null
+  , algorithm_b     text   not null
+  , created_at      bigint not null
+);
+
+-- The interleaved lists served to each user
+create table if not exists interleaved_position (
+    interleaving_id text    not null references interleaving(interleaving_id) on delete cascade
+  , user_id         text    not null
+  , served_at       bigint  not null
+  , rank            integer not null
+  , item_id         integer not null references item(item_id)
+  , algorithm       text    not null
+  , primary key(interleaving_id, user_id, served_at, rank)
+);
+
+create table if not exists interleaving_credit (
+    vote_event_id   integer not null primary key references vote_event(vote_event_id)
+  , interleaving_id text    not null references interleaving(interleaving_id) on delete cascade
+  , user_id         text    not null
+  , served_at       bigint  not null
+  , algorithm       text    not null
+);
+
+create index if not exists interleaving_credit_interleaving on interleaving_credit(interleaving_id);
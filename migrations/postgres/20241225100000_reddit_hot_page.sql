@@ -0,0 +1,3 @@
+-- Page for the reddit_hot algorithm, the first one that counts downvotes
+insert into page (page_id, algorithm) values ('reddit_hot', 'reddit_hot')
+on conflict do nothing;
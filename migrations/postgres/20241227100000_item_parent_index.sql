@@ -0,0 +1,2 @@
+-- Replies are looked up by their parent to build comment threads
+create index if not exists item_parent_id on item(parent_id);
@@ -0,0 +1,2 @@
+-- Windowed rankings read the vote events within their window
+create index if not exists vote_event_created_at on vote_event(created_at);
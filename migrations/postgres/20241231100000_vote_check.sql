@@ -0,0 +1,7 @@
+-- Votes are -1, 0 or 1. Rankings counted other values as neither an upvote
+-- nor a downvote, which storing them as 0 keeps.
+update vote_event set vote = 0 where vote not in (-1, 0, 1);
+update vote set vote = 0 where vote not in (-1, 0, 1);
+
+alter table vote_event add constraint vote_event_vote check (vote in (-1, 0, 1));
+alter table vote add constraint vote_vote check (vote in (-1, 0, 1));
@@ -0,0 +1,254 @@
+use crate::algs::registry::{with_params, AggregateScorer, RankingAlgorithm};
+use crate::common::{
+    error::AppError,
+    model::{ItemAggregate, ScoredItem},
+    time,
+};
+use crate::storage::StorageTransaction;
+use async_trait::async_trait;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+#[cfg(feature = "sqlx")]
+use sqlx::FromRow;
+use statrs::distribution::{ContinuousCDF, Normal};
+use std::sync::Arc;
+
+/// Ranks items and comments by the lower bound of the Wilson score interval
+/// of their share of upvotes among upvotes and downvotes. Unlike the other
+/// algorithms the ranking doesn't decay with age, so it suits "best of" lists.
+/// Only items with votes in the window are ranked.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Best {
+    /// Confidence level of the interval. Higher levels rank items with few
+    /// votes lower.
+    pub confidence: f64,
+    /// Only counts votes cast within this duration before the ranking, e.g.
+    /// `7d`. All votes count without a window.
+    pub window: Option<String>,
+}
+
+impl Default for Best {
+    fn default() -> Self {
+        Self {
+            confidence: 0.95,
+            window: None,
+        }
+    }
+}
+
+impl Best {
+    fn validate(&self) -> Result<(), AppError> {
+        if !(self.confidence > 0.0 && self.confidence < 1.0) {
+            return Err(AppError::Validation(format!(
+                "Confidence must be between 0 and 1: {}",
+                self.confidence
+            )));
+        }
+        self.window_millis()?;
+
+        Ok(())
+    }
+
+    fn window_millis(&self) -> Result<Option<i64>, AppError> {
+        self.window
+            .as_deref()
+            .map(|window| {
+                time::parse_duration(window)
+                    .map_err(|_| AppError::Validation(format!("Invalid window: {}", window)))
+            })
+            .transpose()
+    }
+
+    /// The standard normal quantile of the upper end of the interval.
+    pub fn z(&self) -> f64 {
+        Normal::standard().inverse_cdf(1.0 - (1.0 - self.confidence) / 2.0)
+    }
+}
+
+/// Query parameters of a best list, with the parameters of `Pagination`.
+/// Without a window, the list ranks all votes.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct BestQuery {
+    pub window: Option<String>,
+    pub confidence: Option<f64>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    pub cursor: Option<String>,
+}
+
+impl BestQuery {
+    /// Overrides of the algorithm's parameters, `None` for the defaults.
+    pub fn params(&self) -> Option<Value> {
+        let mut params = serde_json::Map::new();
+        if let Some(window) = &self.window {
+            params.insert("window".to_string(), Value::from(window.as_str()));
+        }
+        if let Some(confidence) = self.confidence {
+            params.insert("confidence".to_string(), Value::from(confidence));
+        }
+
+        (!params.is_empty()).then_some(Value::Object(params))
+    }
+}
+
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BestStats {
+    pub item_id: i32,
+    pub upvotes: i32,
+    pub downvotes: i32,
+}
+
+impl BestStats {
+    pub fn score(&self, z: f64) -> f32 {
+        wilson_lower_bound(self.upvotes, self.downvotes, z) as f32
+    }
+}
+
+/// Lower bound of the Wilson score interval of the share of upvotes, with
+/// `z` the standard normal quantile of the interval's upper end. 0 without
+/// votes.
+pub fn wilson_lower_bound(upvotes: i32, downvotes: i32, z: f64) -> f64 {
+    let n = (upvotes + downvotes) as f64;
+    if n <= 0.0 {
+        return 0.0;
+    }
+    let p = upvotes as f64 / n;
+    let z2 = z * z;
+
+    (p + z2 / (2.0 * n) - z * ((p * (1.0 - p) + z2 / (4.0 * n)) / n).sqrt()) / (1.0 + z2 / n)
+}
+
+#[async_trait]
+impl RankingAlgorithm for Best {
+    fn name(&self) -> &'static str {
+        "best"
+    }
+
+    fn params(&self) -> Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+
+    fn configure(&self, params: &Value) -> Result<Arc<dyn RankingAlgorithm>, AppError> {
+        let configured = with_params(self, params)?;
+        configured.validate()?;
+
+        Ok(Arc::new(configured))
+    }
+
+    async fn rank(
+        &self,
+        tx: &mut dyn StorageTransaction,
+        now: i64,
+    ) -> Result<Vec<ScoredItem>, AppError> {
+        let from = self
+            .window_millis()?
+            .map_or(i64::MIN, |window| now.saturating_sub(window));
+        let stats = tx.get_best_stats(from, now).await?;
+        let z = self.z();
+
+        let scored_items: Vec<ScoredItem> = stats
+            .into_iter()
+            .map(|stat| (stat.score(z), stat))
+            .sorted_by(|(a, _), (b, _)| {
+                a.partial_cmp(b)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+                    .reverse()
+            })
+            .enumerate()
+            .map(|(i, (score, stat))| ScoredItem {
+                item_id: stat.item_id,
+                rank: i as i32 + 1,
+                page: self.name().to_string(),
+                score,
+                upvote_rate: None,
+            })
+            .collect();
+
+        Ok(scored_items)
+    }
+
+    /// Only the all-time ranking is kept up to date as votes arrive, since the
+    /// aggregates don't tell when their votes were cast.
+    fn aggregate_scorer(
+        &self,
+        _pool: &[&ItemAggregate],
+        _at: i64,
+    ) -> Option<Box<dyn AggregateScorer>> {
+        if self.window.is_some() {
+            return None;
+        }
+
+        Some(Box::new(BestScorer {
+            page: self.name().to_string(),
+            z: self.z(),
+        }))
+    }
+}
+
+struct BestScorer {
+    page: String,
+    z: f64,
+}
+
+impl AggregateScorer for BestScorer {
+    fn score(&self, item: &ItemAggregate) -> Option<ScoredItem> {
+        if item.upvotes + item.downvotes == 0 {
+            return None;
+        }
+        let stats = BestStats {
+            item_id: item.item_id,
+            upvotes: item.upvotes,
+            downvotes: item.downvotes,
+        };
+        Some(ScoredItem {
+            item_id: item.item_id,
+            rank: 0,
+            page: self.page.clone(),
+            score: stats.score(self.z),
+            upvote_rate: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn z() -> f64 {
+        Best::default().z()
+    }
+
+    #[test]
+    fn z_of_the_default_confidence() {
+        assert!((z() - 1.959964).abs() < 1e-6);
+    }
+
+    #[test]
+    fn wilson_lower_bound_of_known_values() {
+        for (upvotes, downvotes, expected) in
+            [(1, 0, 0.206549), (8, 2, 0.490162), (90, 10, 0.825634)]
+        {
+            let bound = wilson_lower_bound(upvotes, downvotes, z());
+            assert!((bound - expected).abs() < 1e-6, "{}/{}", upvotes, downvotes);
+        }
+    }
+
+    #[test]
+    fn wilson_lower_bound_without_votes_is_zero() {
+        assert_eq!(wilson_lower_bound(0, 0, z()), 0.0);
+    }
+
+    #[test]
+    fn wilson_lower_bound_of_only_downvotes_is_zero() {
+        assert!(wilson_lower_bound(0, 10, z()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn more_votes_of_the_same_share_rank_higher() {
+        assert!(wilson_lower_bound(90, 10, z()) > wilson_lower_bound(9, 1, z()));
+    }
+}
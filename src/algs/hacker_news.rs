@@ -1,13 +1,37 @@
-use crate::common::time::now_utc_millis;
+use crate::algs::registry::{with_params, AggregateScorer, RankingAlgorithm};
 use crate::common::{
     error::AppError,
-    model::{RankingPage, Score, ScoredItem},
+    model::{ItemAggregate, ScoredItem},
 };
+use crate::storage::StorageTransaction;
+use async_trait::async_trait;
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, Sqlite, Transaction};
+use serde_json::Value;
+#[cfg(feature = "sqlx")]
+use sqlx::FromRow;
+use std::sync::Arc;
 
-#[derive(FromRow, Debug, Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct HackerNews {
+    pub pool_size: i32,
+    pub vote_exponent: f64,
+    pub gravity: f64,
+}
+
+impl Default for HackerNews {
+    fn default() -> Self {
+        Self {
+            pool_size: 1500,
+            vote_exponent: 0.8,
+            gravity: 1.8,
+        }
+    }
+}
+
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct HnStats {
     pub item_id: i32,
     pub sample_time: i64,
@@ -15,72 +39,91 @@ pub struct HnStats {
     pub upvotes: i32,
 }
 
-impl Score for HnStats {
-    fn score(&self) -> f32 {
-        let age_hours = (self.sample_time - self.submission_time) as f32 / 60.0 / 60.0;
-        (self.upvotes as f32).powf(0.8) / (age_hours + 2.0).powf(1.8)
+impl HnStats {
+    pub fn score(&self, params: &HackerNews) -> f32 {
+        let age_hours = (self.sample_time - self.submission_time) as f32 / 1000.0 / 60.0 / 60.0;
+        (self.upvotes as f32).powf(params.vote_exponent as f32)
+            / (age_hours + 2.0).powf(params.gravity as f32)
     }
 }
 
-pub async fn get_ranking(tx: &mut Transaction<'_, Sqlite>) -> Result<Vec<ScoredItem>, AppError> {
-    let sample_time = now_utc_millis();
+#[async_trait]
+impl RankingAlgorithm for HackerNews {
+    fn name(&self) -> &'static str {
+        "hacker_news"
+    }
 
-    let current_stats: Vec<HnStats> = sqlx::query_as::<_, HnStats>(
-        "
-        with newest_items as (
-            select *
-            from item
-            order by created_at desc
-            limit 1500
-        )
-        , upvote_counts as (
-          select
-              item_id
-            , count(*) as upvotes
-          from vote
-          where vote = 1
-          group by item_id
-        )
-        select
-            ni.item_id
-          , ? as sample_time
-          , ni.created_at as submission_time
-          , coalesce(uc.upvotes, 0) as upvotes
-        from newest_items ni
-        left outer join upvote_counts uc
-        on ni.item_id = uc.item_id
-        ",
-    )
-    .bind(sample_time)
-    .fetch_all(&mut **tx)
-    .await?;
+    fn params(&self) -> Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
 
-    let stats: Vec<HnStats> = current_stats
-        .into_iter()
-        .map(|stat| HnStats {
-            item_id: stat.item_id,
-            sample_time,
-            submission_time: stat.submission_time,
-            upvotes: stat.upvotes,
-        })
-        .collect();
+    fn configure(&self, params: &Value) -> Result<Arc<dyn RankingAlgorithm>, AppError> {
+        Ok(Arc::new(with_params(self, params)?))
+    }
 
-    let scored_items: Vec<ScoredItem> = stats
-        .into_iter()
-        .enumerate()
-        .map(|(i, stat)| ScoredItem {
-            item_id: stat.item_id,
-            rank: i as i32 + 1,
-            page: RankingPage::HackerNews,
-            score: stat.score(),
-        })
-        .sorted_by(|a, b| {
-            a.score
-                .partial_cmp(&b.score)
-                .unwrap_or(std::cmp::Ordering::Equal)
-                .reverse()
+    async fn rank(
+        &self,
+        tx: &mut dyn StorageTransaction,
+        now: i64,
+    ) -> Result<Vec<ScoredItem>, AppError> {
+        let stats = tx.get_hn_stats(self.pool_size, now).await?;
+
+        let scored_items: Vec<ScoredItem> = stats
+            .into_iter()
+            .map(|stat| (stat.score(self), stat))
+            .sorted_by(|(a, _), (b, _)| {
+                a.partial_cmp(b)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+                    .reverse()
+            })
+            .enumerate()
+            .map(|(i, (score, stat))| ScoredItem {
+                item_id: stat.item_id,
+                rank: i as i32 + 1,
+                page: self.name().to_string(),
+                score,
+                upvote_rate: None,
+            })
+            .collect();
+
+        Ok(scored_items)
+    }
+    fn aggregate_scorer(
+        &self,
+        _pool: &[&ItemAggregate],
+        at: i64,
+    ) -> Option<Box<dyn AggregateScorer>> {
+        Some(Box::new(HnScorer {
+            params: self.clone(),
+            sample_time: at,
+        }))
+    }
+}
+
+struct HnScorer {
+    params: HackerNews,
+    sample_time: i64,
+}
+
+impl AggregateScorer for HnScorer {
+    fn score(&self, item: &ItemAggregate) -> Option<ScoredItem> {
+        let stats = HnStats {
+            item_id: item.item_id,
+            sample_time: self.sample_time,
+            submission_time: item.submission_time,
+            upvotes: item.upvotes,
+        };
+
+        Some(ScoredItem {
+            item_id: item.item_id,
+            rank: 0,
+            page: self.params.name().to_string(),
+            score: stats.score(&self.params),
+            upvote_rate: None,
         })
-        .collect();
+    }
 
-    Ok(scored_items)
+    fn pool_size(&self) -> Option<usize> {
+        Some(self.params.pool_size.max(0) as usize)
+    }
 }
@@ -1,57 +1,120 @@
+use crate::algs::registry::{with_params, AggregateScorer, RankingAlgorithm};
 use crate::common::{
     error::AppError,
-    model::{RankingPage, Score, ScoredItem},
-    time::now_utc_millis,
+    model::{ItemAggregate, Score, ScoredItem},
 };
+use crate::storage::StorageTransaction;
+use async_trait::async_trait;
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
-use sqlx::{query_as, FromRow, Sqlite, Transaction};
+use serde_json::Value;
+#[cfg(feature = "sqlx")]
+use sqlx::FromRow;
+use std::sync::Arc;
 
-#[derive(FromRow, Serialize, Deserialize, Debug, Clone)]
-struct NewestStats {
-    item_id: i32,
-    sample_time: i64,
-    submission_time: i64,
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Newest {
+    pub pool_size: i32,
+}
+
+impl Default for Newest {
+    fn default() -> Self {
+        Self { pool_size: 1500 }
+    }
+}
+
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NewestStats {
+    pub item_id: i32,
+    pub sample_time: i64,
+    pub submission_time: i64,
 }
 
 impl Score for NewestStats {
     fn score(&self) -> f32 {
-        let age_hours = (self.sample_time - self.submission_time) as f32 / 60.0 / 60.0;
+        let age_hours = (self.sample_time - self.submission_time) as f32 / 1000.0 / 60.0 / 60.0;
         1.0 / age_hours
     }
 }
 
-pub async fn get_ranking(tx: &mut Transaction<'_, Sqlite>) -> Result<Vec<ScoredItem>, AppError> {
-    let sample_time = now_utc_millis();
-    let scored_items: Vec<ScoredItem> = query_as::<_, NewestStats>(
-        "
-        select
-              item_id
-            , ? as sample_time
-            , created_at as submission_time
-        from item
-        order by created_at desc
-        limit 1500
-        ",
-    )
-    .bind(sample_time)
-    .fetch_all(&mut **tx)
-    .await?
-    .iter()
-    .enumerate()
-    .map(|(i, stat)| ScoredItem {
-        item_id: stat.item_id,
-        rank: i as i32 + 1,
-        page: RankingPage::Newest,
-        score: stat.score(),
-    })
-    .sorted_by(|a, b| {
-        a.score
-            .partial_cmp(&b.score)
-            .unwrap_or(std::cmp::Ordering::Equal)
-            .reverse()
-    })
-    .collect();
-
-    Ok(scored_items)
+#[async_trait]
+impl RankingAlgorithm for Newest {
+    fn name(&self) -> &'static str {
+        "newest"
+    }
+
+    fn params(&self) -> Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+
+    fn configure(&self, params: &Value) -> Result<Arc<dyn RankingAlgorithm>, AppError> {
+        Ok(Arc::new(with_params(self, params)?))
+    }
+
+    async fn rank(
+        &self,
+        tx: &mut dyn StorageTransaction,
+        now: i64,
+    ) -> Result<Vec<ScoredItem>, AppError> {
+        let scored_items: Vec<ScoredItem> = tx
+            .get_newest_stats(self.pool_size, now)
+            .await?
+            .iter()
+            .sorted_by(|a, b| {
+                a.score()
+                    .partial_cmp(&b.score())
+                    .unwrap_or(std::cmp::Ordering::Equal)
+                    .reverse()
+            })
+            .enumerate()
+            .map(|(i, stat)| ScoredItem {
+                item_id: stat.item_id,
+                rank: i as i32 + 1,
+                page: self.name().to_string(),
+                score: stat.score(),
+                upvote_rate: None,
+            })
+            .collect();
+
+        Ok(scored_items)
+    }
+    fn aggregate_scorer(
+        &self,
+        _pool: &[&ItemAggregate],
+        at: i64,
+    ) -> Option<Box<dyn AggregateScorer>> {
+        Some(Box::new(NewestScorer {
+            pool_size: self.pool_size,
+            sample_time: at,
+        }))
+    }
+}
+
+struct NewestScorer {
+    pool_size: i32,
+    sample_time: i64,
+}
+
+impl AggregateScorer for NewestScorer {
+    fn score(&self, item: &ItemAggregate) -> Option<ScoredItem> {
+        let stats = NewestStats {
+            item_id: item.item_id,
+            sample_time: self.sample_time,
+            submission_time: item.submission_time,
+        };
+
+        Some(ScoredItem {
+            item_id: item.item_id,
+            rank: 0,
+            page: "newest".to_string(),
+            score: stats.score(),
+            upvote_rate: None,
+        })
+    }
+
+    fn pool_size(&self) -> Option<usize> {
+        Some(self.pool_size.max(0) as usize)
+    }
 }
@@ -1,120 +1,488 @@
+use crate::algs::registry::{with_params, AggregateScorer, RankingAlgorithm};
 use crate::common::{
     error::AppError,
-    model::{RankingPage, Score, ScoredItem},
-    time::now_utc_millis,
+    model::{ItemAggregate, ScoredItem},
 };
+use crate::storage::StorageTransaction;
+use crate::upvote_share_model::{RankExposure, UpvoteShareModel};
 use anyhow::Result;
-use itertools::Itertools;
-use model::{ItemWithRanks, QnSample, QnSampleWithPrediction, QnStats};
-use sqlx::{Sqlite, Transaction};
+use async_trait::async_trait;
+use itertools::{izip, Itertools};
+use model::{
+    ItemWithRanks, QnSample, QnSampleWithPrediction, QnStats, RankProfileEntry, UpvoteRatePrior,
+    VoteEventRank,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
 use tracing::info;
 
-mod model;
-mod repository;
+pub mod model;
 
-pub async fn get_ranking(tx: &mut Transaction<'_, Sqlite>) -> Result<Vec<ScoredItem>, AppError> {
-    repository::check_sampling_initialized(tx).await?;
+/// Number of newest top-level items quality news samples and ranks, as in the
+/// SQL backends.
+pub const POOL_SIZE: usize = 1500;
 
-    let now = now_utc_millis();
-    let stats = repository::get_stats(tx, now).await?;
+/// Lowest rank on a page whose items count as shown when an upvote is cast on
+/// it.
+const VOTE_EVENT_MAX_RANK: i32 = 90;
 
-    let scored_items: Vec<ScoredItem> = stats
-        .iter()
-        .sorted_by(|a, b| {
-            a.score()
-                .partial_cmp(&b.score())
-                .unwrap_or(std::cmp::Ordering::Equal)
-                .reverse()
-        })
-        .enumerate()
-        .map(|(rank, stat)| ScoredItem {
-            item_id: stat.item_id,
-            rank: rank as i32 + 1,
-            page: RankingPage::QualityNews,
-            score: stat.score(),
-        })
-        .collect();
+/// Source of the expected upvotes an item's upvote rate is based on.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExpectedUpvotes {
+    /// Rank profiles observed by the service on the quality news and newest
+    /// pages.
+    #[default]
+    RankProfile,
+    /// Ranks and pages recorded on vote events, with the items shown around
+    /// the upvoted one taken from the page's rank samples.
+    VoteEvents,
+    /// Impressions reported by clients, falling back to rank profiles for
+    /// items without any in an interval.
+    Impressions,
+}
+
+impl ExpectedUpvotes {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExpectedUpvotes::RankProfile => "rank_profile",
+            ExpectedUpvotes::VoteEvents => "vote_events",
+            ExpectedUpvotes::Impressions => "impressions",
+        }
+    }
+}
 
-    Ok(scored_items)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct QualityNews {
+    pub vote_exponent: f64,
+    pub gravity: f64,
+    pub expected_upvotes: ExpectedUpvotes,
+    /// Weight of the sitewide upvote rate prior, in expected upvotes.
+    pub prior_strength: f64,
+    /// Level of the credible interval reported for each item's upvote rate.
+    pub credible_level: f64,
+    /// Cron expression for observing ranks. Ranks are observed several times
+    /// per sampling interval to build a rank profile for each item.
+    pub sample_schedule: String,
+    pub interval_seconds: i64,
+    /// Period over which the upvotes per impression at each page and rank are
+    /// estimated.
+    pub impression_lookback_seconds: i64,
 }
 
-pub async fn record_sample(
-    tx: &mut Transaction<'_, Sqlite>,
-) -> Result<axum::http::StatusCode, AppError> {
-    if let Err(_) = repository::check_items_exist(tx).await {
-        info!("Waiting for items to rank - Skipping...");
-        return Ok(axum::http::StatusCode::OK);
+impl Default for QualityNews {
+    fn default() -> Self {
+        Self {
+            vote_exponent: 0.8,
+            gravity: 1.8,
+            expected_upvotes: ExpectedUpvotes::default(),
+            prior_strength: 5.0,
+            credible_level: 0.95,
+            sample_schedule: "0 * * * * *".to_string(),
+            interval_seconds: 5 * 60,
+            impression_lookback_seconds: 24 * 60 * 60,
+        }
     }
+}
 
-    let sample_time = now_utc_millis();
-    info!("Recording quality news sample at: {:?}", sample_time);
+#[async_trait]
+impl RankingAlgorithm for QualityNews {
+    fn name(&self) -> &'static str {
+        "quality_news"
+    }
 
-    if let Err(_) = repository::check_sampling_initialized(tx).await {
-        info!("Initializing quality news sampling...");
-        let initial_stats = repository::get_stats(tx, sample_time).await?;
-        let next_sampling_interval = repository::insert_sample_interval(tx, sample_time).await?;
-        let ranks = calc_ranks(&initial_stats);
-        repository::insert_rank_observations(tx, &ranks, &next_sampling_interval).await?;
+    fn params(&self) -> Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
 
-        return Ok(axum::http::StatusCode::OK);
+    fn configure(&self, params: &Value) -> Result<Arc<dyn RankingAlgorithm>, AppError> {
+        Ok(Arc::new(with_params(self, params)?))
     }
 
-    let sampling_interval = repository::get_latest_sample_interval(tx).await?;
+    async fn rank(
+        &self,
+        tx: &mut dyn StorageTransaction,
+        now: i64,
+    ) -> Result<Vec<ScoredItem>, AppError> {
+        if !tx.is_sampling_initialized().await? {
+            return Err(AppError::SamplingNotInitialized);
+        }
+
+        let stats = tx.get_qn_stats(now, self.expected_upvotes).await?;
+        let prior = UpvoteRatePrior::from_stats(&stats, self.prior_strength);
 
-    // Evaluate stats in current sampling interval
-    let sitewide_upvotes =
-        repository::get_sitewide_upvotes_in_interval(tx, sample_time, sampling_interval.start_time)
+        let scored_items: Vec<ScoredItem> = stats
+            .iter()
+            .map(|stat| (stat.score(self, &prior), stat))
+            .sorted_by(|(a, _), (b, _)| {
+                a.partial_cmp(b)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+                    .reverse()
+            })
+            .enumerate()
+            .map(|(rank, (score, stat))| ScoredItem {
+                item_id: stat.item_id,
+                rank: rank as i32 + 1,
+                page: self.name().to_string(),
+                score,
+                upvote_rate: Some(prior.estimate(stat, self.credible_level)),
+            })
+            .collect();
+
+        Ok(scored_items)
+    }
+
+    fn sample_schedule(&self) -> Option<String> {
+        Some(self.sample_schedule.clone())
+    }
+
+    async fn record_sample(
+        &self,
+        tx: &mut dyn StorageTransaction,
+        sample_time: i64,
+    ) -> Result<(), AppError> {
+        if !tx.has_items().await? {
+            info!("Waiting for items to rank - Skipping...");
+            return Ok(());
+        }
+
+        if !tx.is_sampling_initialized().await? {
+            info!("Initializing quality news sampling...");
+            let initial_stats = tx.get_qn_stats(sample_time, self.expected_upvotes).await?;
+            let next_sampling_interval = tx.insert_sample_interval(sample_time).await?;
+            let ranks = self.calc_ranks(&initial_stats);
+            tx.insert_rank_observations(&ranks, &next_sampling_interval, sample_time)
+                .await?;
+
+            return Ok(());
+        }
+
+        let sampling_interval = tx.get_latest_sample_interval().await?;
+
+        // Only observe ranks until the current sampling interval is over
+        if sample_time - sampling_interval.start_time < self.interval_seconds * 1000 {
+            let stats = tx.get_qn_stats(sample_time, self.expected_upvotes).await?;
+            let ranks = self.calc_ranks(&stats);
+            tx.insert_rank_observations(&ranks, &sampling_interval, sample_time)
+                .await?;
+
+            return Ok(());
+        }
+
+        info!("Recording quality news sample at: {:?}", sample_time);
+
+        // Evaluate stats in current sampling interval
+        let sitewide_upvotes = tx
+            .get_sitewide_upvotes_in_interval(sample_time, sampling_interval.start_time)
             .await?;
-    let sample = repository::get_sample_in_interval(tx, &sampling_interval, sample_time).await?;
-    let sample_with_predictions = calc_expected_upvote_shares(&sample, sitewide_upvotes).await?;
-    for s in &sample_with_predictions {
-        repository::insert_sample(tx, s).await?;
+        let sample = tx
+            .get_sample_in_interval(&sampling_interval, sample_time)
+            .await?;
+        let rank_profiles = tx
+            .get_rank_profiles(&sampling_interval, sample_time)
+            .await?;
+        tx.insert_rank_profiles(&rank_profiles).await?;
+        let vote_event_ranks = tx
+            .get_vote_event_ranks_in_interval(&sampling_interval, sample_time, VOTE_EVENT_MAX_RANK)
+            .await?;
+        let impression_expected_upvotes = tx
+            .get_impression_expected_upvotes_in_interval(
+                &sampling_interval,
+                sample_time,
+                sample_time - self.impression_lookback_seconds * 1000,
+            )
+            .await?;
+        let impression_expected_upvotes: HashMap<i32, f32> = impression_expected_upvotes
+            .into_iter()
+            .map(|i| (i.item_id, i.expected_upvotes))
+            .collect();
+        let model = tx.get_active_model().await?;
+        let expected_upvote_shares = calc_expected_upvote_shares(
+            &sample,
+            &rank_profiles,
+            sample_time - sampling_interval.start_time,
+            model.as_ref(),
+        );
+        let vote_event_expected_upvote_shares =
+            calc_vote_event_expected_upvote_shares(&sample, &vote_event_ranks, model.as_ref());
+        let sample_with_predictions: Vec<QnSampleWithPrediction> = izip!(
+            &sample,
+            expected_upvote_shares,
+            vote_event_expected_upvote_shares
+        )
+        .map(
+            |(s, expected_upvote_share, vote_event_expected_upvote_share)| {
+                let expected_upvotes = sitewide_upvotes as f32 * expected_upvote_share;
+                QnSampleWithPrediction {
+                    sample: s.clone(),
+                    expected_upvotes,
+                    expected_upvote_share,
+                    vote_event_expected_upvotes: sitewide_upvotes as f32
+                        * vote_event_expected_upvote_share,
+                    vote_event_expected_upvote_share,
+                    impression_expected_upvotes: impression_expected_upvotes
+                        .get(&s.item_id)
+                        .copied()
+                        .unwrap_or(expected_upvotes),
+                }
+            },
+        )
+        .collect();
+        for s in &sample_with_predictions {
+            tx.insert_sample(s).await?;
+        }
+        let updated_stats = tx.get_qn_stats(sample_time, self.expected_upvotes).await?;
+
+        // Initialize next sampling interval
+        let next_ranks = self.calc_ranks(&updated_stats);
+        let next_sampling_interval = tx.insert_sample_interval(sample_time).await?;
+        tx.insert_rank_observations(&next_ranks, &next_sampling_interval, sample_time)
+            .await?;
+
+        Ok(())
     }
-    let updated_stats = repository::get_stats(tx, sample_time).await?;
 
-    // Initialize next sampling interval
-    let next_ranks = calc_ranks(&updated_stats);
-    let next_sampling_interval = repository::insert_sample_interval(tx, sample_time).await?;
-    repository::insert_rank_observations(tx, &next_ranks, &next_sampling_interval).await?;
+    fn aggregate_scorer(
+        &self,
+        pool: &[&ItemAggregate],
+        at: i64,
+    ) -> Option<Box<dyn AggregateScorer>> {
+        let stats: Vec<QnStats> = pool
+            .iter()
+            .filter(|item| item.parent_id.is_none())
+            .map(|item| QnStats::from_aggregate(item, self.expected_upvotes, at))
+            .collect();
 
-    Ok(axum::http::StatusCode::OK)
+        Some(Box::new(QnScorer {
+            prior: UpvoteRatePrior::from_stats(&stats, self.prior_strength),
+            params: self.clone(),
+            sample_time: at,
+        }))
+    }
+}
+
+/// Scores items against the sitewide prior as of the scorer's creation, so
+/// that scores of items updated later remain comparable.
+struct QnScorer {
+    params: QualityNews,
+    prior: UpvoteRatePrior,
+    sample_time: i64,
 }
 
-async fn calc_expected_upvote_shares(
-    stats: &Vec<QnSample>,
-    sitewide_upvotes: i32,
-) -> Result<Vec<QnSampleWithPrediction>, AppError> {
-    let stats_with_predictions: Vec<QnSampleWithPrediction> = stats
+impl AggregateScorer for QnScorer {
+    fn score(&self, item: &ItemAggregate) -> Option<ScoredItem> {
+        if item.parent_id.is_some() {
+            return None;
+        }
+        let stats = QnStats::from_aggregate(item, self.params.expected_upvotes, self.sample_time);
+
+        Some(ScoredItem {
+            item_id: item.item_id,
+            rank: 0,
+            page: self.params.name().to_string(),
+            score: stats.score(&self.params, &self.prior),
+            upvote_rate: Some(self.prior.estimate(&stats, self.params.credible_level)),
+        })
+    }
+
+    fn pool_size(&self) -> Option<usize> {
+        Some(POOL_SIZE)
+    }
+}
+
+impl QualityNews {
+    fn calc_ranks(&self, stats: &[QnStats]) -> Vec<ItemWithRanks> {
+        let prior = UpvoteRatePrior::from_stats(stats, self.prior_strength);
+        stats
+            .iter()
+            .sorted_by(|a, b| {
+                a.score(self, &prior)
+                    .partial_cmp(&b.score(self, &prior))
+                    .unwrap_or(std::cmp::Ordering::Equal)
+                    .reverse()
+            })
+            .enumerate()
+            .sorted_by(|(_, a), (_, b)| a.submission_time.cmp(&b.submission_time).reverse())
+            .enumerate()
+            .map(|(rank_new, (rank_top, obs))| ItemWithRanks {
+                item_id: obs.item_id,
+                rank_top: rank_top as i32 + 1,
+                rank_new: rank_new as i32 + 1,
+            })
+            .collect()
+    }
+}
+
+/// Predicts each item's share of the sitewide upvotes in the interval from its
+/// rank profile, i.e. the time it spent at each rank on the quality news and
+/// newest pages. Falls back to a uniform share until an upvote share model
+/// covering these pages has been trained.
+fn calc_expected_upvote_shares(
+    sample: &[QnSample],
+    rank_profiles: &[RankProfileEntry],
+    interval_duration: i64,
+    model: Option<&UpvoteShareModel>,
+) -> Vec<f32> {
+    let profiles_by_item = rank_profiles.iter().into_group_map_by(|p| p.item_id);
+    let exposures: Vec<Vec<RankExposure>> = sample
         .iter()
-        .map(|r| {
-            let expected_upvote_share = repository::get_expected_upvote_share(stats.len() as i32);
-            QnSampleWithPrediction {
-                sample: r.clone(),
-                expected_upvotes: sitewide_upvotes as f32 * expected_upvote_share,
-                expected_upvote_share,
-            }
+        .map(|s| {
+            profiles_by_item
+                .get(&s.item_id)
+                .into_iter()
+                .flatten()
+                .flat_map(|p| {
+                    let weight = p.duration as f64 / interval_duration.max(1) as f64;
+                    [("quality_news", p.rank_top), ("newest", p.rank_new)]
+                        .into_iter()
+                        .filter_map(move |(page_id, rank)| {
+                            Some(RankExposure {
+                                page_id,
+                                rank: rank?,
+                                weight,
+                            })
+                        })
+                })
+                .collect()
         })
         .collect();
 
-    Ok(stats_with_predictions)
+    model
+        .and_then(|m| m.predict_upvote_shares(&exposures))
+        .unwrap_or_else(|| vec![get_expected_upvote_share(sample.len() as i32); sample.len()])
 }
 
-fn calc_ranks(stats: &Vec<QnStats>) -> Vec<ItemWithRanks> {
-    stats
-        .into_iter()
-        .sorted_by(|a, b| {
-            a.score()
-                .partial_cmp(&b.score())
-                .unwrap_or(std::cmp::Ordering::Equal)
-                .reverse()
+/// Predicts each item's share of the sitewide upvotes in the interval from the
+/// pages and ranks at which it was shown whenever an upvote was cast, so that
+/// items shown without being upvoted are expected upvotes too. Each upvote
+/// counts as one exposure of every item shown on its page. Items that weren't
+/// shown at any upvote get no share. Falls back to a uniform share among the
+/// shown items if the model doesn't cover their pages.
+fn calc_vote_event_expected_upvote_shares(
+    sample: &[QnSample],
+    vote_event_ranks: &[VoteEventRank],
+    model: Option<&UpvoteShareModel>,
+) -> Vec<f32> {
+    let ranks_by_item = vote_event_ranks.iter().into_group_map_by(|r| r.item_id);
+    let exposures: Vec<Vec<RankExposure>> = sample
+        .iter()
+        .map(|s| {
+            ranks_by_item
+                .get(&s.item_id)
+                .map_or(&[][..], |r| &r[..])
+                .iter()
+                .map(|r| RankExposure {
+                    page_id: &r.page_id,
+                    rank: r.rank,
+                    weight: 1.0,
+                })
+                .collect()
         })
-        .enumerate()
-        .sorted_by(|(_, a), (_, b)| a.submission_time.cmp(&b.submission_time).reverse())
-        .enumerate()
-        .map(|(rank_new, (rank_top, obs))| ItemWithRanks {
-            item_id: obs.item_id,
-            rank_top: rank_top as i32 + 1,
-            rank_new: rank_new as i32 + 1,
+        .collect();
+
+    model
+        .and_then(|m| m.predict_upvote_shares(&exposures))
+        .unwrap_or_else(|| {
+            let n_shown = exposures.iter().filter(|e| !e.is_empty()).count();
+            exposures
+                .iter()
+                .map(|e| {
+                    if e.is_empty() {
+                        0.0
+                    } else {
+                        1.0 / n_shown as f32
+                    }
+                })
+                .collect()
         })
-        .collect()
+}
+
+/// Uniform upvote share, used as long as no upvote share model is available.
+fn get_expected_upvote_share(n_items: i32) -> f32 {
+    1.0 / n_items as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::model::VoteEvent;
+    use crate::common::time::{Clock, ManualClock};
+    use crate::storage::memory::{
+        fixtures::{self, item, vote_event},
+        MemoryStorage,
+    };
+    use crate::storage::Storage;
+
+    const NOW: i64 = 1_700_000_000_000;
+    const MINUTE: i64 = 60 * 1000;
+
+    /// Storage with a few items and upvotes cast on the quality news page.
+    async fn storage(clock: &Arc<ManualClock>) -> MemoryStorage {
+        let storage = MemoryStorage::with_clock(clock.clone());
+        let items: Vec<_> = (1..=3)
+            .map(|item_id| item(item_id, NOW - (4 - item_id as i64) * 10 * MINUTE))
+            .collect();
+        let vote_events: Vec<VoteEvent> = [(1, 2), (1, 2), (2, 1), (3, 3)]
+            .into_iter()
+            .enumerate()
+            .map(|(i, (item_id, rank))| VoteEvent {
+                rank: Some(rank),
+                page: Some("quality_news".to_string()),
+                ..vote_event(
+                    i as i32 + 1,
+                    item_id,
+                    &format!("user-{}", i),
+                    1,
+                    NOW - MINUTE,
+                )
+            })
+            .collect();
+        fixtures::insert(&storage, &items, &vote_events).await;
+        storage
+    }
+
+    /// Records a sample and ranks at the clock's current time.
+    async fn sample_and_rank(storage: &MemoryStorage, clock: &ManualClock) -> Vec<(i32, f32)> {
+        let qn = QualityNews::default();
+        let mut tx = storage.begin().await.unwrap();
+        qn.record_sample(&mut *tx, clock.now_millis())
+            .await
+            .unwrap();
+        let items = qn.rank(&mut *tx, clock.now_millis()).await.unwrap();
+        tx.commit().await.unwrap();
+
+        items
+            .iter()
+            .map(|item| (item.item_id, item.score))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn rankings_are_deterministic_under_a_manual_clock() {
+        let clock = Arc::new(ManualClock::new(NOW));
+        let first = sample_and_rank(&storage(&clock).await, &clock).await;
+        let second = sample_and_rank(&storage(&clock).await, &clock).await;
+
+        assert_eq!(first.len(), 3);
+        assert_eq!(first, second);
+    }
+
+    #[tokio::test]
+    async fn advancing_the_clock_changes_the_scores() {
+        let clock = Arc::new(ManualClock::new(NOW));
+        let storage = storage(&clock).await;
+        let before = sample_and_rank(&storage, &clock).await;
+
+        clock.advance(60 * MINUTE);
+        let after = sample_and_rank(&storage, &clock).await;
+
+        assert_eq!(after.len(), 3);
+        for (item_id, score) in &after {
+            let (_, score_before) = before.iter().find(|(id, _)| id == item_id).unwrap();
+            assert_ne!(score, score_before, "{}", item_id);
+        }
+    }
 }
@@ -1,8 +1,12 @@
-use crate::common::model::Score;
+use crate::algs::quality_news::{ExpectedUpvotes, QualityNews};
+use crate::common::model::{ItemAggregate, UpvoteRateEstimate};
 use serde::{Deserialize, Serialize};
-use sqlx::{sqlite::SqliteRow, FromRow, Row};
+#[cfg(feature = "sqlx")]
+use sqlx::FromRow;
+use statrs::distribution::{ContinuousCDF, Gamma};
 
-#[derive(FromRow, Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct QnSampleInterval {
     pub interval_id: i32,
     pub start_time: i64,
@@ -14,37 +18,21 @@ pub struct QnSample {
     pub interval: QnSampleInterval,
     pub sample_time: i64,
     pub submission_time: i64,
-    pub rank_top: Option<i32>,
-    pub rank_new: Option<i32>,
     pub upvotes: i32,
     pub upvote_share: f32,
 }
 
-impl<'r> FromRow<'r, SqliteRow> for QnSample {
-    fn from_row(row: &'r SqliteRow) -> Result<Self, sqlx::Error> {
-        Ok(QnSample {
-            item_id: row.try_get("item_id")?,
-            interval: QnSampleInterval {
-                interval_id: row.try_get("interval_id")?,
-                start_time: row.try_get("start_time")?,
-            },
-            sample_time: row.try_get("sample_time")?,
-            submission_time: row.try_get("submission_time")?,
-            rank_top: row.try_get("rank_top")?,
-            rank_new: row.try_get("rank_new")?,
-            upvotes: row.try_get("upvotes")?,
-            upvote_share: row.try_get("upvote_share")?,
-        })
-    }
-}
-
 pub struct QnSampleWithPrediction {
     pub sample: QnSample,
     pub expected_upvotes: f32,
     pub expected_upvote_share: f32,
+    pub vote_event_expected_upvotes: f32,
+    pub vote_event_expected_upvote_share: f32,
+    pub impression_expected_upvotes: f32,
 }
 
-#[derive(FromRow, Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct QnStats {
     pub item_id: i32,
     pub updated_at: i64,
@@ -54,23 +42,212 @@ pub struct QnStats {
     pub cumulative_expected_upvotes: f32,
 }
 
-impl Score for QnStats {
-    fn score(&self) -> f32 {
+impl QnStats {
+    /// Stats of an item as `get_stats` would return them at `sample_time`,
+    /// built from its in-memory aggregate.
+    pub fn from_aggregate(
+        item: &ItemAggregate,
+        expected_upvotes: ExpectedUpvotes,
+        sample_time: i64,
+    ) -> Self {
+        let cumulative_expected_upvotes = match expected_upvotes {
+            ExpectedUpvotes::RankProfile => item.expected_upvotes,
+            ExpectedUpvotes::VoteEvents => item.vote_event_expected_upvotes,
+            ExpectedUpvotes::Impressions => item.impression_expected_upvotes,
+        };
+
+        Self {
+            item_id: item.item_id,
+            updated_at: sample_time,
+            sample_time,
+            submission_time: item.submission_time,
+            cumulative_upvotes: item.upvotes,
+            cumulative_expected_upvotes: cumulative_expected_upvotes.unwrap_or(item.upvotes as f32),
+        }
+    }
+
+    pub fn score(&self, params: &QualityNews, prior: &UpvoteRatePrior) -> f32 {
         let age_hours = (self.sample_time - self.submission_time) as f32 / 1000.0 / 60.0 / 60.0;
-        let estimated_upvote_rate: f32 = if self.cumulative_expected_upvotes == 0.0 {
-            // TODO: is this a sane default for 0.0 expected upvotes?
-            // TODO: use a global prior with bayesian averaging for initial guess of upvote rate
-            1.0
+        let estimated_upvote_rate = prior.posterior_mean(self) as f32;
+        (age_hours * estimated_upvote_rate).powf(params.vote_exponent as f32)
+            / (age_hours + 2.0).powf(params.gravity as f32)
+    }
+}
+
+/// Sitewide gamma prior on the upvote rate (upvotes per expected upvote). The
+/// strength is the number of expected upvotes the prior is worth, so an item's
+/// estimate is pulled towards the sitewide rate until it has gathered a
+/// comparable amount of exposure.
+#[derive(Debug, Clone)]
+pub struct UpvoteRatePrior {
+    pub mean: f64,
+    pub strength: f64,
+}
+
+impl UpvoteRatePrior {
+    pub fn from_stats(stats: &[QnStats], strength: f64) -> Self {
+        let upvotes: f64 = stats.iter().map(|s| s.cumulative_upvotes as f64).sum();
+        let expected_upvotes: f64 = stats
+            .iter()
+            .map(|s| s.cumulative_expected_upvotes as f64)
+            .sum();
+        let mean = if expected_upvotes > 0.0 {
+            upvotes / expected_upvotes
         } else {
-            self.cumulative_upvotes as f32 / self.cumulative_expected_upvotes
+            1.0
+        };
+
+        Self { mean, strength }
+    }
+
+    /// Shape and rate of the gamma posterior for the item's upvote rate.
+    fn posterior(&self, stats: &QnStats) -> (f64, f64) {
+        (
+            self.strength * self.mean + stats.cumulative_upvotes as f64,
+            self.strength + stats.cumulative_expected_upvotes as f64,
+        )
+    }
+
+    pub fn posterior_mean(&self, stats: &QnStats) -> f64 {
+        match self.posterior(stats) {
+            (shape, rate) if rate > 0.0 => shape / rate,
+            _ => self.mean,
+        }
+    }
+
+    /// Posterior mean and equal-tailed credible interval of the item's upvote
+    /// rate at the given level.
+    pub fn estimate(&self, stats: &QnStats, credible_level: f64) -> UpvoteRateEstimate {
+        let posterior_mean = self.posterior_mean(stats);
+        let (shape, rate) = self.posterior(stats);
+        let tail = (1.0 - credible_level.clamp(0.0, 1.0)) / 2.0;
+        let (lower, upper) = match Gamma::new(shape, rate) {
+            Ok(posterior) if shape > 0.0 && tail > 0.0 => (
+                posterior.inverse_cdf(tail),
+                posterior.inverse_cdf(1.0 - tail),
+            ),
+            _ => (posterior_mean, posterior_mean),
         };
-        (age_hours * estimated_upvote_rate).powf(0.8) / (age_hours + 2.0).powf(1.8)
+
+        UpvoteRateEstimate {
+            posterior_mean: posterior_mean as f32,
+            lower: lower as f32,
+            upper: upper as f32,
+        }
     }
 }
 
-#[derive(FromRow, Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct ItemWithRanks {
     pub item_id: i32,
     pub rank_top: i32,
     pub rank_new: i32,
 }
+
+/// Time in milliseconds an item spent at a combination of ranks during an
+/// interval.
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RankProfileEntry {
+    pub item_id: i32,
+    pub interval_id: i32,
+    pub rank_top: Option<i32>,
+    pub rank_new: Option<i32>,
+    pub duration: i64,
+}
+
+/// Page and rank at which an item was shown when an upvote was cast on that
+/// page, the upvoted item included.
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VoteEventRank {
+    pub vote_event_id: i32,
+    pub item_id: i32,
+    pub page_id: String,
+    pub rank: i32,
+}
+
+/// Upvotes an item was expected to receive in an interval given the
+/// impressions reported for it.
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ImpressionExpectedUpvotes {
+    pub item_id: i32,
+    pub expected_upvotes: f32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(cumulative_upvotes: i32, cumulative_expected_upvotes: f32) -> QnStats {
+        QnStats {
+            item_id: 1,
+            updated_at: 0,
+            sample_time: 0,
+            submission_time: 0,
+            cumulative_upvotes,
+            cumulative_expected_upvotes,
+        }
+    }
+
+    #[test]
+    fn prior_mean_is_the_sitewide_upvote_rate() {
+        let prior = UpvoteRatePrior::from_stats(&[stats(3, 1.0), stats(1, 3.0)], 10.0);
+        assert_eq!(prior.mean, 1.0);
+        let prior = UpvoteRatePrior::from_stats(&[stats(6, 1.0), stats(0, 3.0)], 10.0);
+        assert_eq!(prior.mean, 1.5);
+    }
+
+    #[test]
+    fn prior_without_exposure_expects_the_observed_rate() {
+        assert_eq!(UpvoteRatePrior::from_stats(&[], 10.0).mean, 1.0);
+        assert_eq!(
+            UpvoteRatePrior::from_stats(&[stats(0, 0.0)], 10.0).mean,
+            1.0
+        );
+    }
+
+    #[test]
+    fn posterior_mean_shrinks_towards_the_prior() {
+        let prior = UpvoteRatePrior {
+            mean: 1.0,
+            strength: 10.0,
+        };
+
+        assert_eq!(prior.posterior_mean(&stats(0, 0.0)), 1.0);
+        assert!((prior.posterior_mean(&stats(10, 5.0)) - 20.0 / 15.0).abs() < 1e-9);
+        // With much more exposure than the prior is worth, the observed rate
+        // dominates
+        assert!((prior.posterior_mean(&stats(20_000, 10_000.0)) - 2.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn posterior_mean_without_prior_or_exposure_is_the_prior_mean() {
+        let prior = UpvoteRatePrior {
+            mean: 1.5,
+            strength: 0.0,
+        };
+        assert_eq!(prior.posterior_mean(&stats(0, 0.0)), 1.5);
+    }
+
+    #[test]
+    fn credible_interval_narrows_with_exposure() {
+        let prior = UpvoteRatePrior {
+            mean: 1.0,
+            strength: 10.0,
+        };
+
+        let few = prior.estimate(&stats(4, 2.0), 0.9);
+        let many = prior.estimate(&stats(400, 200.0), 0.9);
+        for estimate in [&few, &many] {
+            assert!(estimate.lower < estimate.posterior_mean);
+            assert!(estimate.posterior_mean < estimate.upper);
+        }
+        assert!(many.upper - many.lower < few.upper - few.lower);
+
+        let wider = prior.estimate(&stats(4, 2.0), 0.99);
+        assert!(wider.lower < few.lower && wider.upper > few.upper);
+    }
+}
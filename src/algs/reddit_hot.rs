@@ -0,0 +1,200 @@
+use crate::algs::registry::{with_params, AggregateScorer, RankingAlgorithm};
+use crate::common::{
+    error::AppError,
+    model::{ItemAggregate, ScoredItem},
+};
+use crate::storage::StorageTransaction;
+use async_trait::async_trait;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+#[cfg(feature = "sqlx")]
+use sqlx::FromRow;
+use std::sync::Arc;
+
+/// Reddit's "hot" ranking: the order of magnitude of the net score (upvotes
+/// minus downvotes) plus the submission time, so that a tenfold score is worth
+/// as much as being submitted `decay_seconds` later.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedditHot {
+    pub pool_size: i32,
+    /// Submission times are counted in seconds from this Unix time, which
+    /// keeps the scores small enough for their precision.
+    pub epoch_seconds: i64,
+    pub decay_seconds: f64,
+}
+
+impl Default for RedditHot {
+    fn default() -> Self {
+        Self {
+            pool_size: 1500,
+            epoch_seconds: 1134028003,
+            decay_seconds: 45000.0,
+        }
+    }
+}
+
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HotStats {
+    pub item_id: i32,
+    pub submission_time: i64,
+    pub upvotes: i32,
+    pub downvotes: i32,
+}
+
+impl HotStats {
+    /// Computed in `f64`, where items submitted a second apart still differ.
+    /// Rankings are sorted by it before it's stored as an `f32`.
+    pub fn score(&self, params: &RedditHot) -> f64 {
+        let net_score = (self.upvotes - self.downvotes) as f64;
+        let order = net_score.abs().max(1.0).log10();
+        let seconds = self.submission_time as f64 / 1000.0 - params.epoch_seconds as f64;
+        net_score.signum() * order + seconds / params.decay_seconds
+    }
+}
+
+#[async_trait]
+impl RankingAlgorithm for RedditHot {
+    fn name(&self) -> &'static str {
+        "reddit_hot"
+    }
+
+    fn params(&self) -> Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+
+    fn configure(&self, params: &Value) -> Result<Arc<dyn RankingAlgorithm>, AppError> {
+        Ok(Arc::new(with_params(self, params)?))
+    }
+
+    async fn rank(
+        &self,
+        tx: &mut dyn StorageTransaction,
+        _now: i64,
+    ) -> Result<Vec<ScoredItem>, AppError> {
+        let stats = tx.get_hot_stats(self.pool_size).await?;
+
+        let scored_items: Vec<ScoredItem> = stats
+            .into_iter()
+            .map(|stat| (stat.score(self), stat))
+            .sorted_by(|(a, _), (b, _)| {
+                a.partial_cmp(b)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+                    .reverse()
+            })
+            .enumerate()
+            .map(|(i, (score, stat))| ScoredItem {
+                item_id: stat.item_id,
+                rank: i as i32 + 1,
+                page: self.name().to_string(),
+                score: score as f32,
+                upvote_rate: None,
+            })
+            .collect();
+
+        Ok(scored_items)
+    }
+
+    fn aggregate_scorer(
+        &self,
+        _pool: &[&ItemAggregate],
+        _at: i64,
+    ) -> Option<Box<dyn AggregateScorer>> {
+        Some(Box::new(HotScorer {
+            params: self.clone(),
+        }))
+    }
+}
+
+struct HotScorer {
+    params: RedditHot,
+}
+
+impl AggregateScorer for HotScorer {
+    fn score(&self, item: &ItemAggregate) -> Option<ScoredItem> {
+        let stats = HotStats {
+            item_id: item.item_id,
+            submission_time: item.submission_time,
+            upvotes: item.upvotes,
+            downvotes: item.downvotes,
+        };
+
+        Some(ScoredItem {
+            item_id: item.item_id,
+            rank: 0,
+            page: self.params.name().to_string(),
+            score: stats.score(&self.params) as f32,
+            upvote_rate: None,
+        })
+    }
+
+    fn pool_size(&self) -> Option<usize> {
+        Some(self.params.pool_size.max(0) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::{
+        fixtures::{self, item},
+        MemoryStorage,
+    };
+    use crate::storage::Storage;
+
+    const NOW: i64 = 1_700_000_000_000;
+
+    fn stats(submission_time: i64, upvotes: i32, downvotes: i32) -> HotStats {
+        HotStats {
+            item_id: 1,
+            submission_time,
+            upvotes,
+            downvotes,
+        }
+    }
+
+    #[test]
+    fn tenfold_net_score_is_worth_one_decay_period() {
+        let params = RedditHot::default();
+        let decay_millis = (params.decay_seconds * 1000.0) as i64;
+        let older = stats(NOW - decay_millis - 60_000, 11, 1).score(&params);
+
+        assert!(older > stats(NOW - 120_000, 1, 0).score(&params));
+        assert!(older < stats(NOW, 1, 0).score(&params));
+    }
+
+    #[test]
+    fn downvotes_rank_below_items_without_votes() {
+        let params = RedditHot::default();
+        let unvoted = stats(NOW, 0, 0).score(&params);
+
+        assert!(stats(NOW, 1, 3).score(&params) < unvoted);
+        assert!(stats(NOW, 3, 1).score(&params) > unvoted);
+        assert_eq!(stats(NOW, 2, 2).score(&params), unvoted);
+    }
+
+    #[test]
+    fn scores_count_from_the_fixed_epoch() {
+        let params = RedditHot::default();
+        let epoch = params.epoch_seconds * 1000;
+        let decay_millis = (params.decay_seconds * 1000.0) as i64;
+
+        assert_eq!(stats(epoch, 1, 0).score(&params), 0.0);
+        assert_eq!(stats(epoch + decay_millis, 10, 0).score(&params), 2.0);
+    }
+
+    #[tokio::test]
+    async fn items_submitted_a_second_apart_are_ranked_in_order() {
+        let storage = MemoryStorage::new();
+        let submitted = NOW - 24 * 60 * 60 * 1000;
+        let items = [item(1, submitted), item(2, submitted + 1000)];
+        fixtures::insert(&storage, &items, &[]).await;
+        let mut tx = storage.begin().await.unwrap();
+
+        let ranked = RedditHot::default().rank(&mut *tx, NOW).await.unwrap();
+        let ids: Vec<i32> = ranked.iter().map(|item| item.item_id).collect();
+        assert_eq!(ids, [2, 1]);
+    }
+}
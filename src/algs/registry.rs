@@ -0,0 +1,145 @@
+use crate::algs::{
+    best::Best, hacker_news::HackerNews, newest::Newest, quality_news::QualityNews,
+    reddit_hot::RedditHot, top::Top,
+};
+use crate::common::{
+    error::AppError,
+    model::{ItemAggregate, ScoredItem},
+};
+use crate::storage::StorageTransaction;
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+#[async_trait]
+pub trait RankingAlgorithm: Send + Sync {
+    /// Unique name of the algorithm, used to serve it under `/rankings/{name}`.
+    fn name(&self) -> &'static str;
+
+    /// Parameters the algorithm is currently configured with.
+    fn params(&self) -> Value;
+
+    /// Creates a copy of the algorithm with the given parameters overriding
+    /// the current ones. Used to serve pages bound to custom parameters.
+    fn configure(&self, params: &Value) -> Result<Arc<dyn RankingAlgorithm>, AppError>;
+
+    async fn rank(
+        &self,
+        tx: &mut dyn StorageTransaction,
+        now: i64,
+    ) -> Result<Vec<ScoredItem>, AppError>;
+
+    /// Cron expression for the algorithm's sampling job. Algorithms that don't
+    /// need to record samples return `None` and are skipped by the scheduler.
+    fn sample_schedule(&self) -> Option<String> {
+        None
+    }
+
+    async fn record_sample(
+        &self,
+        _tx: &mut dyn StorageTransaction,
+        _now: i64,
+    ) -> Result<(), AppError> {
+        Ok(())
+    }
+
+    /// Scores items from their in-memory aggregates as of `at`, for algorithms
+    /// whose ranking can be kept up to date as votes arrive without querying
+    /// the database. `pool` holds the aggregates of the items in the
+    /// algorithm's last ranking.
+    fn aggregate_scorer(
+        &self,
+        _pool: &[&ItemAggregate],
+        _at: i64,
+    ) -> Option<Box<dyn AggregateScorer>> {
+        None
+    }
+}
+
+pub trait AggregateScorer: Send + Sync {
+    /// Scores an item the same way `RankingAlgorithm::rank` would at the
+    /// scorer's point in time, or returns `None` if the item isn't eligible.
+    /// The rank of the returned item is left at 0.
+    fn score(&self, item: &ItemAggregate) -> Option<ScoredItem>;
+
+    /// Number of the newest eligible items the ranking is limited to, like the
+    /// pool `RankingAlgorithm::rank` draws from, or `None` if items are
+    /// eligible regardless of their age.
+    fn pool_size(&self) -> Option<usize> {
+        None
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct AlgorithmRegistry {
+    algorithms: BTreeMap<&'static str, Arc<dyn RankingAlgorithm>>,
+}
+
+impl AlgorithmRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registry containing all algorithms shipped with the service.
+    pub fn with_defaults() -> Self {
+        let mut registry = Self::new();
+        registry.register(Newest::default());
+        registry.register(HackerNews::default());
+        registry.register(QualityNews::default());
+        registry.register(RedditHot::default());
+        registry.register(Best::default());
+        registry.register(Top::default());
+        registry
+    }
+
+    /// Registers an algorithm, replacing any algorithm with the same name.
+    pub fn register<A: RankingAlgorithm + 'static>(&mut self, algorithm: A) -> &mut Self {
+        self.algorithms
+            .insert(algorithm.name(), Arc::new(algorithm));
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn RankingAlgorithm>> {
+        self.algorithms.get(name).cloned()
+    }
+
+    /// Looks up an algorithm and applies the given parameter overrides.
+    pub fn configure(
+        &self,
+        name: &str,
+        params: &Value,
+    ) -> Result<Arc<dyn RankingAlgorithm>, AppError> {
+        self.get(name)
+            .ok_or_else(|| AppError::NotFound(format!("Unknown ranking algorithm: {}", name)))?
+            .configure(params)
+    }
+
+    pub fn algorithms(&self) -> impl Iterator<Item = &Arc<dyn RankingAlgorithm>> {
+        self.algorithms.values()
+    }
+}
+
+/// Merges `params` into the serialized parameters of `algorithm` and
+/// deserializes the result. `null` leaves all parameters unchanged.
+pub fn with_params<T: Serialize + DeserializeOwned>(
+    algorithm: &T,
+    params: &Value,
+) -> Result<T, AppError> {
+    let mut merged = serde_json::to_value(algorithm)?;
+    match (&mut merged, params) {
+        (_, Value::Null) => {}
+        (Value::Object(merged), Value::Object(params)) => {
+            merged.extend(params.clone());
+        }
+        _ => {
+            return Err(AppError::Validation(
+                "Algorithm parameters must be a JSON object".to_string(),
+            ))
+        }
+    }
+
+    serde_json::from_value(merged)
+        .map_err(|e| AppError::Validation(format!("Invalid algorithm parameters: {}", e)))
+}
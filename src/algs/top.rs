@@ -0,0 +1,399 @@
+use crate::algs::{
+    quality_news::{
+        model::{QnStats, UpvoteRatePrior},
+        ExpectedUpvotes, QualityNews,
+    },
+    registry::{with_params, AggregateScorer, RankingAlgorithm},
+};
+use crate::common::{
+    error::AppError,
+    model::{ItemAggregate, ScoredItem},
+    time,
+};
+use crate::storage::StorageTransaction;
+use async_trait::async_trait;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+#[cfg(feature = "sqlx")]
+use sqlx::FromRow;
+use std::sync::Arc;
+
+/// Ranks items by the upvotes they received within a time window, e.g. the
+/// top of the past week. Without a window, all upvotes count. Only items
+/// upvoted in the window are ranked.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Top {
+    /// Number of the most upvoted items in the window that are ranked.
+    pub pool_size: i32,
+    /// Duration before the ranking that upvotes count in, e.g. `7d`.
+    pub window: Option<String>,
+    /// Start and end of a fixed window instead, in milliseconds since the
+    /// epoch. Either may be left open.
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    /// Ranks by the upvote rate estimated as in quality news, i.e. upvotes
+    /// per expected upvote within the window, instead of raw upvotes. Items
+    /// without expected upvotes in the window count as expected.
+    pub normalize: bool,
+    pub expected_upvotes: ExpectedUpvotes,
+}
+
+impl Default for Top {
+    fn default() -> Self {
+        Self {
+            pool_size: 1500,
+            window: None,
+            from: None,
+            to: None,
+            normalize: false,
+            expected_upvotes: ExpectedUpvotes::default(),
+        }
+    }
+}
+
+impl Top {
+    fn validate(&self) -> Result<(), AppError> {
+        if self.window.is_some() && (self.from.is_some() || self.to.is_some()) {
+            return Err(AppError::Validation(
+                "A window can't be combined with from or to".to_string(),
+            ));
+        }
+        if let (Some(from), Some(to)) = (self.from, self.to) {
+            if from >= to {
+                return Err(AppError::Validation(format!(
+                    "from must be before to: {} >= {}",
+                    from, to
+                )));
+            }
+        }
+        self.window_millis()?;
+
+        Ok(())
+    }
+
+    fn window_millis(&self) -> Result<Option<i64>, AppError> {
+        self.window
+            .as_deref()
+            .map(|window| {
+                time::parse_duration(window)
+                    .map_err(|_| AppError::Validation(format!("Invalid window: {}", window)))
+            })
+            .transpose()
+    }
+
+    /// Upvotes count after the start of the range until its end.
+    fn range(&self, now: i64) -> Result<(i64, i64), AppError> {
+        let until = self.to.map_or(now, |to| to.min(now));
+        let from = match self.window_millis()? {
+            Some(window) => until.saturating_sub(window),
+            None => self.from.unwrap_or(i64::MIN),
+        };
+
+        Ok((from, until))
+    }
+
+    fn is_windowed(&self) -> bool {
+        self.window.is_some() || self.from.is_some() || self.to.is_some()
+    }
+}
+
+/// Query parameters of a top list, with the parameters of `Pagination`.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct TopQuery {
+    pub window: Option<String>,
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    pub normalize: Option<bool>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    pub cursor: Option<String>,
+}
+
+impl TopQuery {
+    /// Overrides of the algorithm's parameters, `None` for the defaults.
+    pub fn params(&self) -> Option<Value> {
+        let mut params = serde_json::Map::new();
+        if let Some(window) = &self.window {
+            params.insert("window".to_string(), Value::from(window.as_str()));
+        }
+        if let Some(from) = self.from {
+            params.insert("from".to_string(), Value::from(from));
+        }
+        if let Some(to) = self.to {
+            params.insert("to".to_string(), Value::from(to));
+        }
+        if let Some(normalize) = self.normalize {
+            params.insert("normalize".to_string(), Value::from(normalize));
+        }
+
+        (!params.is_empty()).then_some(Value::Object(params))
+    }
+}
+
+/// Upvotes of an item within a window, with the expected upvotes of the
+/// quality news samples taken in it. The expected upvotes are `None` for
+/// items that weren't sampled.
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TopStats {
+    pub item_id: i32,
+    pub submission_time: i64,
+    pub upvotes: i32,
+    pub expected_upvotes: Option<f32>,
+    pub vote_event_expected_upvotes: Option<f32>,
+    pub impression_expected_upvotes: Option<f32>,
+}
+
+impl TopStats {
+    /// Stats of the window as `QnStats::from_aggregate` builds them for all
+    /// votes.
+    pub fn qn_stats(&self, expected_upvotes: ExpectedUpvotes, sample_time: i64) -> QnStats {
+        let window_expected_upvotes = match expected_upvotes {
+            ExpectedUpvotes::RankProfile => self.expected_upvotes,
+            ExpectedUpvotes::VoteEvents => self.vote_event_expected_upvotes,
+            ExpectedUpvotes::Impressions => self.impression_expected_upvotes,
+        };
+
+        QnStats {
+            item_id: self.item_id,
+            updated_at: sample_time,
+            sample_time,
+            submission_time: self.submission_time,
+            cumulative_upvotes: self.upvotes,
+            cumulative_expected_upvotes: window_expected_upvotes.unwrap_or(self.upvotes as f32),
+        }
+    }
+}
+
+#[async_trait]
+impl RankingAlgorithm for Top {
+    fn name(&self) -> &'static str {
+        "top"
+    }
+
+    fn params(&self) -> Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+
+    fn configure(&self, params: &Value) -> Result<Arc<dyn RankingAlgorithm>, AppError> {
+        let configured = with_params(self, params)?;
+        configured.validate()?;
+
+        Ok(Arc::new(configured))
+    }
+
+    async fn rank(
+        &self,
+        tx: &mut dyn StorageTransaction,
+        now: i64,
+    ) -> Result<Vec<ScoredItem>, AppError> {
+        let (from, until) = self.range(now)?;
+        let stats: Vec<QnStats> = tx
+            .get_top_stats(from, until, self.pool_size)
+            .await?
+            .iter()
+            .map(|stat| stat.qn_stats(self.expected_upvotes, until))
+            .collect();
+        let scorer = TopScorer::new(self, &stats, until);
+
+        let scored_items: Vec<ScoredItem> = stats
+            .iter()
+            .map(|stat| scorer.score_stats(stat))
+            .sorted_by(|a, b| {
+                a.score
+                    .partial_cmp(&b.score)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+                    .reverse()
+            })
+            .enumerate()
+            .map(|(i, item)| ScoredItem {
+                rank: i as i32 + 1,
+                ..item
+            })
+            .collect();
+
+        Ok(scored_items)
+    }
+
+    /// Only the all-time ranking is kept up to date as votes arrive, since the
+    /// aggregates don't tell when their votes were cast.
+    fn aggregate_scorer(
+        &self,
+        pool: &[&ItemAggregate],
+        at: i64,
+    ) -> Option<Box<dyn AggregateScorer>> {
+        if self.is_windowed() {
+            return None;
+        }
+
+        let stats: Vec<QnStats> = pool
+            .iter()
+            .map(|item| QnStats::from_aggregate(item, self.expected_upvotes, at))
+            .collect();
+
+        Some(Box::new(TopScorer::new(self, &stats, at)))
+    }
+}
+
+struct TopScorer {
+    params: Top,
+    prior: UpvoteRatePrior,
+    credible_level: f64,
+    sample_time: i64,
+}
+
+impl TopScorer {
+    /// Scorer whose upvote rate prior is based on the stats of all ranked
+    /// items, with the prior strength and credible level of quality news.
+    fn new(params: &Top, stats: &[QnStats], sample_time: i64) -> Self {
+        let qn_params = QualityNews::default();
+        Self {
+            params: params.clone(),
+            prior: UpvoteRatePrior::from_stats(stats, qn_params.prior_strength),
+            credible_level: qn_params.credible_level,
+            sample_time,
+        }
+    }
+
+    fn score_stats(&self, stats: &QnStats) -> ScoredItem {
+        let (score, upvote_rate) = if self.params.normalize {
+            let estimate = self.prior.estimate(stats, self.credible_level);
+            (estimate.posterior_mean, Some(estimate))
+        } else {
+            (stats.cumulative_upvotes as f32, None)
+        };
+
+        ScoredItem {
+            item_id: stats.item_id,
+            rank: 0,
+            page: self.params.name().to_string(),
+            score,
+            upvote_rate,
+        }
+    }
+}
+
+impl AggregateScorer for TopScorer {
+    fn score(&self, item: &ItemAggregate) -> Option<ScoredItem> {
+        if item.upvotes <= 0 {
+            return None;
+        }
+        let stats = QnStats::from_aggregate(item, self.params.expected_upvotes, self.sample_time);
+        Some(self.score_stats(&stats))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::model::{Item, VoteEvent};
+    use crate::storage::memory::{
+        fixtures::{self, item, vote_event},
+        MemoryStorage,
+    };
+    use crate::storage::Storage;
+
+    const NOW: i64 = 1_700_000_000_000;
+    const HOUR: i64 = 60 * 60 * 1000;
+
+    fn stats(item_id: i32, upvotes: i32, expected_upvotes: Option<f32>) -> TopStats {
+        TopStats {
+            item_id,
+            submission_time: NOW - HOUR,
+            upvotes,
+            expected_upvotes,
+            vote_event_expected_upvotes: None,
+            impression_expected_upvotes: Some(1.0),
+        }
+    }
+
+    fn ranked_ids(top: &Top, stats: &[TopStats]) -> Vec<i32> {
+        let stats: Vec<QnStats> = stats
+            .iter()
+            .map(|stat| stat.qn_stats(top.expected_upvotes, NOW))
+            .collect();
+        let scorer = TopScorer::new(top, &stats, NOW);
+
+        stats
+            .iter()
+            .map(|stat| scorer.score_stats(stat))
+            .sorted_by(|a, b| b.score.total_cmp(&a.score))
+            .map(|item| item.item_id)
+            .collect()
+    }
+
+    #[test]
+    fn normalization_ranks_by_upvotes_per_expected_upvote() {
+        let stats = [stats(1, 10, Some(20.0)), stats(2, 6, Some(2.0))];
+
+        assert_eq!(ranked_ids(&Top::default(), &stats), vec![1, 2]);
+        let normalized = Top {
+            normalize: true,
+            ..Top::default()
+        };
+        assert_eq!(ranked_ids(&normalized, &stats), vec![2, 1]);
+    }
+
+    #[test]
+    fn window_stats_use_the_configured_expected_upvotes() {
+        let stats = stats(1, 4, Some(8.0));
+
+        let qn_stats = stats.qn_stats(ExpectedUpvotes::RankProfile, NOW);
+        assert_eq!(qn_stats.cumulative_expected_upvotes, 8.0);
+        let qn_stats = stats.qn_stats(ExpectedUpvotes::Impressions, NOW);
+        assert_eq!(qn_stats.cumulative_expected_upvotes, 1.0);
+        // Items that weren't sampled in the window count as expected
+        let qn_stats = stats.qn_stats(ExpectedUpvotes::VoteEvents, NOW);
+        assert_eq!(qn_stats.cumulative_expected_upvotes, 4.0);
+    }
+
+    #[tokio::test]
+    async fn window_counts_each_users_latest_upvote_within_it() {
+        let storage = MemoryStorage::new();
+        let items: Vec<Item> = (1..=3)
+            .map(|item_id| item(item_id, NOW - 3 * HOUR))
+            .collect();
+        let votes = [
+            (1, "a", 1, NOW - 2 * HOUR),
+            (2, "a", 1, NOW - HOUR / 2),
+            (2, "b", 1, NOW - HOUR / 2),
+            (3, "c", 1, NOW - HOUR / 2),
+            (3, "c", 0, NOW - HOUR / 4),
+        ];
+        let vote_events: Vec<VoteEvent> = votes
+            .into_iter()
+            .enumerate()
+            .map(|(i, (item_id, user_id, vote, created_at))| {
+                vote_event(i as i32 + 1, item_id, user_id, vote, created_at)
+            })
+            .collect();
+        fixtures::insert(&storage, &items, &vote_events).await;
+        let mut tx = storage.begin().await.unwrap();
+
+        let scores = |items: Vec<ScoredItem>| -> Vec<(i32, f32)> {
+            items
+                .iter()
+                .map(|item| (item.item_id, item.score))
+                .collect()
+        };
+        let past_hour = Top {
+            window: Some("1h".to_string()),
+            ..Top::default()
+        };
+        let items = past_hour.rank(&mut *tx, NOW).await.unwrap();
+        assert_eq!(scores(items), vec![(2, 2.0)]);
+
+        let items = Top::default().rank(&mut *tx, NOW).await.unwrap();
+        assert_eq!(scores(items), vec![(2, 2.0), (1, 1.0)]);
+
+        let most_upvoted = Top {
+            pool_size: 1,
+            ..Top::default()
+        };
+        let items = most_upvoted.rank(&mut *tx, NOW).await.unwrap();
+        assert_eq!(scores(items), vec![(2, 2.0)]);
+    }
+}
@@ -1,96 +1,802 @@
-use crate::algs::{hacker_news, newest, quality_news};
+use crate::algs::{
+    best::BestQuery,
+    registry::{AlgorithmRegistry, RankingAlgorithm},
+    top::TopQuery,
+};
 use crate::common::{
     error::AppError,
-    model::{Item, ScoredItem, VoteEvent},
+    extract::{Json, Path, Query},
+    model::{
+        AlgorithmInfo, Impression, Item, ItemAggregate, Page, PageDefinition, RankUpvoteShare,
+        RankingPage, RankingResponse, ScoredItem, VoteEvent, IMPRESSION_BUCKET_MILLIS,
+    },
+    time::{self, Clock},
+};
+use crate::evaluation::{self, Evaluation, PageEvaluationRequest};
+use crate::experiments::{
+    self, Experiment, ExperimentDefinition, ExperimentRankingQuery, ExperimentResults,
+    ExperimentResultsQuery,
+};
+use crate::interleaving::{
+    self, InterleavedRanking, InterleavedRankingQuery, Interleaving, InterleavingDefinition,
+    InterleavingResults,
+};
+use crate::live_scores::LiveScores;
+use crate::pages;
+use crate::snapshots::{self, Pagination, RankingSnapshot, SnapshotStore};
+use crate::storage::{Storage, StorageTransaction};
+use crate::threads::{self, ThreadNode, ThreadQuery};
+use crate::upvote_share_model::{
+    self, RetrainingConfig, UpvoteShareModel, UpvoteShareModelVersion,
 };
 use anyhow::Result;
-use axum::{extract::State, response::IntoResponse, Json};
-use sqlx::{query, sqlite::SqlitePool, Sqlite, Transaction};
+use axum::{
+    extract::{RawQuery, State},
+    response::{IntoResponse, Redirect},
+};
+use itertools::Itertools;
+use serde_json::Value;
+use std::sync::Arc;
 
 pub async fn health_check() -> Result<axum::http::StatusCode, AppError> {
     Ok(axum::http::StatusCode::OK)
 }
 
 pub async fn register_item(
-    State(pool): State<SqlitePool>,
+    State(storage): State<Arc<dyn Storage>>,
+    State(live_scores): State<Arc<LiveScores>>,
     Json(payload): Json<Item>,
 ) -> Result<impl IntoResponse, AppError> {
-    query(
-        "
-        insert into item (
-              item_id
-            , parent_id
-            , author_id
-            , created_at
-        ) values (?, ?, ?, ?)
-        ",
-    )
-    .bind(payload.item_id)
-    .bind(payload.parent_id)
-    .bind(payload.author_id)
-    .bind(payload.created_at)
-    .execute(&pool)
-    .await?;
+    let mut tx = storage.begin().await?;
+    tx.insert_item(&payload).await?;
+    tx.commit().await?;
+    live_scores.record_item(ItemAggregate::new(&payload));
 
     Ok(axum::http::StatusCode::OK)
 }
 
+pub async fn get_item_thread(
+    State(storage): State<Arc<dyn Storage>>,
+    State(clock): State<Arc<dyn Clock>>,
+    Path(item_id): Path<i32>,
+    Query(query): Query<ThreadQuery>,
+) -> Result<Json<ThreadNode>, AppError> {
+    let mut tx = storage.begin().await?;
+    let thread = threads::thread(&mut *tx, item_id, &query, clock.now_millis()).await?;
+    tx.commit().await?;
+
+    Ok(Json(thread))
+}
+
 pub async fn register_vote_event(
-    State(pool): State<SqlitePool>,
+    State(storage): State<Arc<dyn Storage>>,
+    State(live_scores): State<Arc<LiveScores>>,
     Json(payload): Json<VoteEvent>,
 ) -> Result<impl IntoResponse, AppError> {
-    query(
-        "
-        insert into vote_event (
-              vote_event_id
-            , item_id
-            , user_id
-            , vote
-            , rank
-            , page
-            , created_at
-        ) values (?, ?, ?, ?, ?, ?, ?)
-        ",
+    if !(-1..=1).contains(&payload.vote) {
+        return Err(AppError::Validation(format!(
+            "Vote must be -1, 0 or 1: {}",
+            payload.vote
+        )));
+    }
+
+    let mut tx = storage.begin().await?;
+
+    // Votes on interleaved lists report the interleaving as their page
+    if let Some(page_id) = &payload.page {
+        if tx.get_page(page_id).await?.is_none() && tx.get_interleaving(page_id).await?.is_none() {
+            return Err(AppError::NotFound(format!("Unknown page: {}", page_id)));
+        }
+    }
+
+    let previous_vote = tx
+        .get_current_vote(&payload.user_id, payload.item_id)
+        .await?;
+
+    tx.insert_vote_event(&payload).await?;
+    interleaving::credit_vote_event(&mut *tx, &payload).await?;
+
+    tx.commit().await?;
+
+    let upvote_delta = (payload.vote == 1) as i32 - (previous_vote == Some(1)) as i32;
+    let downvote_delta = (payload.vote == -1) as i32 - (previous_vote == Some(-1)) as i32;
+    live_scores.record_vote(
+        payload.item_id,
+        upvote_delta,
+        downvote_delta,
+        payload.created_at,
+    );
+
+    Ok(axum::http::StatusCode::OK)
+}
+
+/// Stores a batch of impressions as counts per page, item, rank and time
+/// bucket. Repeated impressions of an item by the same user within a bucket are
+/// counted once per batch. Users aren't stored, so an impression reported in
+/// several batches is counted in each of them.
+pub async fn register_impressions(
+    State(storage): State<Arc<dyn Storage>>,
+    Json(payload): Json<Vec<Impression>>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut tx = storage.begin().await?;
+
+    let page_ids: Vec<String> = payload.iter().map(|i| i.page.clone()).unique().collect();
+    for page_id in page_ids {
+        tx.get_page(&page_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("Unknown page: {}", page_id)))?;
+    }
+
+    let impressions = payload
+        .iter()
+        .map(|i| {
+            (
+                i,
+                i.created_at - i.created_at.rem_euclid(IMPRESSION_BUCKET_MILLIS),
+            )
+        })
+        .unique_by(|(i, bucket_time)| (&i.user_id, &i.page, i.item_id, i.rank, *bucket_time))
+        .collect::<Vec<_>>();
+
+    for (impression, bucket_time) in impressions {
+        tx.record_impression(
+            &impression.page,
+            impression.item_id,
+            impression.rank,
+            bucket_time,
+        )
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(axum::http::StatusCode::OK)
+}
+
+pub async fn get_algorithms(
+    State(registry): State<Arc<AlgorithmRegistry>>,
+) -> Result<Json<Vec<AlgorithmInfo>>, AppError> {
+    let algorithms = registry
+        .algorithms()
+        .map(|algorithm| AlgorithmInfo {
+            name: algorithm.name().to_string(),
+            params: algorithm.params(),
+        })
+        .collect();
+
+    Ok(Json(algorithms))
+}
+
+pub async fn get_ranking(
+    State(storage): State<Arc<dyn Storage>>,
+    State(registry): State<Arc<AlgorithmRegistry>>,
+    State(snapshots): State<Arc<SnapshotStore>>,
+    State(clock): State<Arc<dyn Clock>>,
+    Path(algorithm): Path<String>,
+    Query(pagination): Query<Pagination>,
+) -> Result<Json<RankingResponse>, AppError> {
+    let algorithm = registry
+        .get(&algorithm)
+        .ok_or_else(|| AppError::NotFound(format!("Unknown ranking algorithm: {}", algorithm)))?;
+    let ranking = snapshots::algorithm_ranking(algorithm.name());
+    if let Some(page) = snapshots.resume(&ranking, &pagination)? {
+        return Ok(Json(pagination.response(page)));
+    }
+
+    let snapshot =
+        algorithm_snapshot(&*storage, &snapshots, &*clock, &*algorithm, &ranking).await?;
+    let page = snapshot.page(pagination.offset.unwrap_or(0), pagination.limit);
+
+    Ok(Json(pagination.response(page)))
+}
+
+/// Permanently redirects a ranking route that predates the algorithm registry
+/// to the route of its algorithm, keeping the query.
+pub async fn redirect_ranking(algorithm: &str, RawQuery(query): RawQuery) -> Redirect {
+    let path = format!("/rankings/{}", algorithm);
+    match query {
+        Some(query) => Redirect::permanent(&format!("{}?{}", path, query)),
+        None => Redirect::permanent(&path),
+    }
+}
+
+/// Serves the best list of all votes or of the votes within a window, with a
+/// custom confidence if requested. Lists with custom parameters are cached
+/// until the next snapshot refresh.
+pub async fn get_best_ranking(
+    State(storage): State<Arc<dyn Storage>>,
+    State(registry): State<Arc<AlgorithmRegistry>>,
+    State(snapshots): State<Arc<SnapshotStore>>,
+    State(clock): State<Arc<dyn Clock>>,
+    Query(query): Query<BestQuery>,
+) -> Result<Json<RankingResponse>, AppError> {
+    let params = query.params();
+    let pagination = Pagination {
+        limit: query.limit,
+        offset: query.offset,
+        cursor: query.cursor,
+    };
+    let page = configured_ranking_page(
+        &*storage,
+        &registry,
+        &snapshots,
+        &*clock,
+        "best",
+        params,
+        &pagination,
+    )
+    .await?;
+
+    Ok(Json(pagination.response(page)))
+}
+
+/// Serves the top list of the upvotes within a window, either the duration
+/// before now or between `from` and `to`, optionally normalized by expected
+/// upvotes. Lists of each window are cached until the next snapshot refresh.
+pub async fn get_top_ranking(
+    State(storage): State<Arc<dyn Storage>>,
+    State(registry): State<Arc<AlgorithmRegistry>>,
+    State(snapshots): State<Arc<SnapshotStore>>,
+    State(clock): State<Arc<dyn Clock>>,
+    Query(query): Query<TopQuery>,
+) -> Result<Json<RankingResponse>, AppError> {
+    let params = query.params();
+    let pagination = Pagination {
+        limit: query.limit,
+        offset: query.offset,
+        cursor: query.cursor,
+    };
+    let page = configured_ranking_page(
+        &*storage,
+        &registry,
+        &snapshots,
+        &*clock,
+        "top",
+        params,
+        &pagination,
     )
-    .bind(payload.vote_event_id)
-    .bind(payload.item_id)
-    .bind(payload.user_id)
-    .bind(payload.vote)
-    .bind(payload.rank)
-    .bind(payload.page.as_ref().map(|p| p.to_string()))
-    .bind(payload.created_at)
-    .execute(&pool)
     .await?;
 
+    Ok(Json(pagination.response(page)))
+}
+
+/// A page of the algorithm's ranking with the given parameter overrides,
+/// cached under its own key unless the defaults are used.
+async fn configured_ranking_page(
+    storage: &dyn Storage,
+    registry: &AlgorithmRegistry,
+    snapshots: &SnapshotStore,
+    clock: &dyn Clock,
+    name: &str,
+    params: Option<Value>,
+    pagination: &Pagination,
+) -> Result<RankingPage, AppError> {
+    let algorithm = registry.configure(name, params.as_ref().unwrap_or(&Value::Null))?;
+    let ranking = match &params {
+        Some(params) => snapshots::configured_ranking(algorithm.name(), params),
+        None => snapshots::algorithm_ranking(algorithm.name()),
+    };
+    if let Some(page) = snapshots.resume(&ranking, pagination)? {
+        return Ok(page);
+    }
+
+    let snapshot = if params.is_none() {
+        algorithm_snapshot(storage, snapshots, clock, &*algorithm, &ranking).await?
+    } else if let Some(snapshot) = snapshots.latest_configured(&ranking) {
+        snapshot
+    } else {
+        let (now, scored_items) = rank_now(storage, clock, &*algorithm).await?;
+        snapshots.publish_configured(&ranking, now, scored_items)
+    };
+
+    Ok(snapshot.page(pagination.offset.unwrap_or(0), pagination.limit))
+}
+
+/// The latest snapshot of the algorithm's ranking under the given key,
+/// computed if there is none.
+async fn algorithm_snapshot(
+    storage: &dyn Storage,
+    snapshots: &SnapshotStore,
+    clock: &dyn Clock,
+    algorithm: &dyn RankingAlgorithm,
+    ranking: &str,
+) -> Result<Arc<RankingSnapshot>, AppError> {
+    if let Some(snapshot) = snapshots.latest(ranking) {
+        return Ok(snapshot);
+    }
+
+    let (now, scored_items) = rank_now(storage, clock, algorithm).await?;
+
+    Ok(snapshots.publish(ranking, now, scored_items))
+}
+
+/// Ranks the items with the algorithm as of now.
+async fn rank_now(
+    storage: &dyn Storage,
+    clock: &dyn Clock,
+    algorithm: &dyn RankingAlgorithm,
+) -> Result<(i64, Vec<ScoredItem>), AppError> {
+    let now = clock.now_millis();
+    let mut tx = storage.begin().await?;
+    let scored_items = algorithm.rank(&mut *tx, now).await?;
+    tx.commit().await?;
+
+    Ok((now, scored_items))
+}
+
+pub async fn create_page(
+    State(storage): State<Arc<dyn Storage>>,
+    State(registry): State<Arc<AlgorithmRegistry>>,
+    Json(payload): Json<PageDefinition>,
+) -> Result<Json<Page>, AppError> {
+    // Fail early if the page's parameters don't fit its algorithm
+    registry.configure(&payload.algorithm, &payload.params)?;
+
+    let mut tx = storage.begin().await?;
+    if tx.get_interleaving(&payload.page_id).await?.is_some() {
+        return Err(AppError::Conflict(format!(
+            "Page id {} is used by an interleaving",
+            payload.page_id
+        )));
+    }
+    let page = tx.insert_page(&payload).await?;
+    tx.commit().await?;
+
+    Ok(Json(page))
+}
+
+pub async fn get_pages(
+    State(storage): State<Arc<dyn Storage>>,
+) -> Result<Json<Vec<Page>>, AppError> {
+    let mut tx = storage.begin().await?;
+    let pages = tx.get_pages().await?;
+    tx.commit().await?;
+
+    Ok(Json(pages))
+}
+
+pub async fn delete_page(
+    State(storage): State<Arc<dyn Storage>>,
+    State(snapshots): State<Arc<SnapshotStore>>,
+    Path(page_id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut tx = storage.begin().await?;
+    if let Some(experiment) = tx.get_running_experiment().await? {
+        if experiment.arms.iter().any(|arm| arm.page_id == page_id) {
+            return Err(AppError::Conflict(format!(
+                "Page {} is an arm of the running experiment {}",
+                page_id, experiment.experiment_id
+            )));
+        }
+    }
+    if tx.delete_page(&page_id).await? == 0 {
+        return Err(AppError::NotFound(format!("Unknown page: {}", page_id)));
+    }
+    tx.commit().await?;
+    snapshots.remove(&snapshots::page_ranking(&page_id));
+
     Ok(axum::http::StatusCode::OK)
 }
 
-pub async fn get_hacker_news_ranking(
-    State(pool): State<SqlitePool>,
-) -> Result<Json<Vec<ScoredItem>>, AppError> {
-    let mut tx: Transaction<'_, Sqlite> = pool.begin().await?;
-    let scored_items = hacker_news::get_ranking(&mut tx).await?;
+pub async fn get_page_ranking(
+    State(storage): State<Arc<dyn Storage>>,
+    State(registry): State<Arc<AlgorithmRegistry>>,
+    State(snapshots): State<Arc<SnapshotStore>>,
+    State(clock): State<Arc<dyn Clock>>,
+    Path(page_id): Path<String>,
+    Query(pagination): Query<Pagination>,
+) -> Result<Json<RankingResponse>, AppError> {
+    let page = serve_page_ranking(
+        storage.as_ref(),
+        &registry,
+        &snapshots,
+        clock.as_ref(),
+        &page_id,
+        &pagination,
+    )
+    .await?;
+
+    Ok(Json(pagination.response(page)))
+}
+
+/// A page of the page's ranking, from the latest snapshot or the one the
+/// cursor points into.
+async fn serve_page_ranking(
+    storage: &dyn Storage,
+    registry: &AlgorithmRegistry,
+    snapshots: &SnapshotStore,
+    clock: &dyn Clock,
+    page_id: &str,
+    pagination: &Pagination,
+) -> Result<RankingPage, AppError> {
+    let ranking = snapshots::page_ranking(page_id);
+    if let Some(page) = snapshots.resume(&ranking, pagination)? {
+        return Ok(page);
+    }
+
+    let snapshot = match snapshots.latest(&ranking) {
+        Some(snapshot) => snapshot,
+        None => {
+            let now = clock.now_millis();
+            let mut tx = storage.begin().await?;
+            let page = tx
+                .get_page(page_id)
+                .await?
+                .ok_or_else(|| AppError::NotFound(format!("Unknown page: {}", page_id)))?;
+            let scored_items = pages::get_page_ranking(&mut *tx, registry, &page, now).await?;
+            tx.commit().await?;
+            snapshots.publish(&ranking, now, scored_items)
+        }
+    };
+
+    Ok(snapshot.page(pagination.offset.unwrap_or(0), pagination.limit))
+}
+
+/// Serves the ranking of the page the user is assigned to in the running
+/// experiment. The items carry the page, which clients report with the user's
+/// impressions and votes so they count towards the arm.
+pub async fn get_experiment_ranking(
+    State(storage): State<Arc<dyn Storage>>,
+    State(registry): State<Arc<AlgorithmRegistry>>,
+    State(snapshots): State<Arc<SnapshotStore>>,
+    State(clock): State<Arc<dyn Clock>>,
+    Query(query): Query<ExperimentRankingQuery>,
+) -> Result<Json<RankingResponse>, AppError> {
+    let mut tx = storage.begin().await?;
+    let experiment = tx
+        .get_running_experiment()
+        .await?
+        .ok_or_else(|| AppError::NotFound("No experiment is running".to_string()))?;
+    tx.commit().await?;
+    let arm = experiment.assign(&query.user_id);
+    let pagination = Pagination {
+        limit: query.limit,
+        offset: query.offset,
+        cursor: query.cursor,
+    };
+    let page = serve_page_ranking(
+        storage.as_ref(),
+        &registry,
+        &snapshots,
+        clock.as_ref(),
+        &arm.page_id,
+        &pagination,
+    )
+    .await?;
+
+    Ok(Json(pagination.response(page)))
+}
+
+pub async fn get_page_upvote_shares(
+    State(storage): State<Arc<dyn Storage>>,
+    Path(page_id): Path<String>,
+) -> Result<Json<Vec<RankUpvoteShare>>, AppError> {
+    let mut tx = storage.begin().await?;
+    let shares = tx.get_upvote_shares_by_rank(&page_id).await?;
+    tx.commit().await?;
+
+    Ok(Json(shares))
+}
+
+/// Evaluates the page's rankings sampled within the requested window: churn,
+/// time to front page and exposure concentration, plus NDCG and Kendall tau if
+/// relevance judgements are given.
+pub async fn evaluate_page(
+    State(storage): State<Arc<dyn Storage>>,
+    State(clock): State<Arc<dyn Clock>>,
+    Path(page_id): Path<String>,
+    Json(payload): Json<PageEvaluationRequest>,
+) -> Result<Json<Evaluation>, AppError> {
+    let window = payload.window.as_deref().unwrap_or("1d");
+    let window = time::parse_duration(window)
+        .map_err(|_| AppError::Validation(format!("Invalid window: {}", window)))?;
+    let now = clock.now_millis();
+
+    let mut tx = storage.begin().await?;
+    tx.get_page(&page_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Unknown page: {}", page_id)))?;
+    let page_ranks = tx
+        .get_page_rank_history(&page_id, now - window, now)
+        .await?;
+    let item_ids: Vec<i32> = page_ranks.iter().map(|r| r.item_id).unique().collect();
+    let submission_times = tx
+        .get_item_aggregates(&item_ids)
+        .await?
+        .into_iter()
+        .map(|item| (item.item_id, item.submission_time))
+        .collect();
+    tx.commit().await?;
+
+    Ok(Json(evaluation::evaluate_page_ranks(
+        &page_ranks,
+        payload.top.unwrap_or(evaluation::DEFAULT_TOP),
+        payload.relevance.as_ref(),
+        &submission_times,
+    )))
+}
+
+pub async fn create_experiment(
+    State(storage): State<Arc<dyn Storage>>,
+    State(clock): State<Arc<dyn Clock>>,
+    Json(payload): Json<ExperimentDefinition>,
+) -> Result<Json<Experiment>, AppError> {
+    payload.validate()?;
+
+    let mut tx = storage.begin().await?;
+    for arm in &payload.arms {
+        tx.get_page(&arm.page_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("Unknown page: {}", arm.page_id)))?;
+    }
+    if let Some(running) = tx.get_running_experiment().await? {
+        return Err(AppError::Conflict(format!(
+            "Experiment {} is still running",
+            running.experiment_id
+        )));
+    }
+    let experiment = Experiment {
+        experiment_id: payload.experiment_id,
+        arms: payload.arms,
+        created_at: clock.now_millis(),
+        ended_at: None,
+    };
+    tx.insert_experiment(&experiment).await?;
+    tx.commit().await?;
+
+    Ok(Json(experiment))
+}
+
+pub async fn get_experiments(
+    State(storage): State<Arc<dyn Storage>>,
+) -> Result<Json<Vec<Experiment>>, AppError> {
+    let mut tx = storage.begin().await?;
+    let experiments = tx.get_experiments().await?;
+    tx.commit().await?;
+
+    Ok(Json(experiments))
+}
+
+pub async fn get_experiment(
+    State(storage): State<Arc<dyn Storage>>,
+    Path(experiment_id): Path<String>,
+) -> Result<Json<Experiment>, AppError> {
+    let mut tx = storage.begin().await?;
+    let experiment = find_experiment(&mut *tx, &experiment_id).await?;
+    tx.commit().await?;
+
+    Ok(Json(experiment))
+}
+
+pub async fn end_experiment(
+    State(storage): State<Arc<dyn Storage>>,
+    State(clock): State<Arc<dyn Clock>>,
+    Path(experiment_id): Path<String>,
+) -> Result<Json<Experiment>, AppError> {
+    let mut tx = storage.begin().await?;
+    if tx
+        .end_experiment(&experiment_id, clock.now_millis())
+        .await?
+        == 0
+    {
+        find_experiment(&mut *tx, &experiment_id).await?;
+        return Err(AppError::Conflict(format!(
+            "Experiment {} has already ended",
+            experiment_id
+        )));
+    }
+    let experiment = find_experiment(&mut *tx, &experiment_id).await?;
+    tx.commit().await?;
+
+    Ok(Json(experiment))
+}
+
+pub async fn get_experiment_results(
+    State(storage): State<Arc<dyn Storage>>,
+    State(clock): State<Arc<dyn Clock>>,
+    Path(experiment_id): Path<String>,
+    Query(query): Query<ExperimentResultsQuery>,
+) -> Result<Json<ExperimentResults>, AppError> {
+    let mut tx = storage.begin().await?;
+    let experiment = find_experiment(&mut *tx, &experiment_id).await?;
+    let results = experiments::results(
+        &mut *tx,
+        &experiment,
+        clock.now_millis(),
+        query.confidence.unwrap_or(experiments::DEFAULT_CONFIDENCE),
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(Json(results))
+}
+
+async fn find_experiment(
+    tx: &mut dyn StorageTransaction,
+    experiment_id: &str,
+) -> Result<Experiment, AppError> {
+    tx.get_experiment(experiment_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Unknown experiment: {}", experiment_id)))
+}
+
+pub async fn create_interleaving(
+    State(storage): State<Arc<dyn Storage>>,
+    State(registry): State<Arc<AlgorithmRegistry>>,
+    State(clock): State<Arc<dyn Clock>>,
+    Json(payload): Json<InterleavingDefinition>,
+) -> Result<Json<Interleaving>, AppError> {
+    payload.validate()?;
+    for algorithm in [&payload.algorithm_a, &payload.algorithm_b] {
+        registry.get(algorithm).ok_or_else(|| {
+            AppError::NotFound(format!("Unknown ranking algorithm: {}", algorithm))
+        })?;
+    }
+
+    let mut tx = storage.begin().await?;
+    if tx.get_page(&payload.interleaving_id).await?.is_some() {
+        return Err(AppError::Conflict(format!(
+            "Interleaving id {} is used by a page",
+            payload.interleaving_id
+        )));
+    }
+    let interleaving = Interleaving {
+        interleaving_id: payload.interleaving_id,
+        algorithm_a: payload.algorithm_a,
+        algorithm_b: payload.algorithm_b,
+        created_at: clock.now_millis(),
+    };
+    tx.insert_interleaving(&interleaving).await?;
+    tx.commit().await?;
+
+    Ok(Json(interleaving))
+}
+
+pub async fn get_interleavings(
+    State(storage): State<Arc<dyn Storage>>,
+) -> Result<Json<Vec<Interleaving>>, AppError> {
+    let mut tx = storage.begin().await?;
+    let interleavings = tx.get_interleavings().await?;
+    tx.commit().await?;
+
+    Ok(Json(interleavings))
+}
+
+pub async fn get_interleaving(
+    State(storage): State<Arc<dyn Storage>>,
+    Path(interleaving_id): Path<String>,
+) -> Result<Json<Interleaving>, AppError> {
+    let mut tx = storage.begin().await?;
+    let interleaving = find_interleaving(&mut *tx, &interleaving_id).await?;
+    tx.commit().await?;
+
+    Ok(Json(interleaving))
+}
+
+/// Serves the user a list interleaving the latest rankings of the two
+/// algorithms. Votes on it are credited when reported with the interleaving
+/// as their page and the rank shown.
+pub async fn get_interleaved_ranking(
+    State(storage): State<Arc<dyn Storage>>,
+    State(registry): State<Arc<AlgorithmRegistry>>,
+    State(snapshots): State<Arc<SnapshotStore>>,
+    State(clock): State<Arc<dyn Clock>>,
+    Path(interleaving_id): Path<String>,
+    Query(query): Query<InterleavedRankingQuery>,
+) -> Result<Json<InterleavedRanking>, AppError> {
+    let mut tx = storage.begin().await?;
+    let interleaving = find_interleaving(&mut *tx, &interleaving_id).await?;
+    tx.commit().await?;
+
+    let mut rankings = Vec::with_capacity(2);
+    for name in [&interleaving.algorithm_a, &interleaving.algorithm_b] {
+        let algorithm = registry
+            .get(name)
+            .ok_or_else(|| AppError::NotFound(format!("Unknown ranking algorithm: {}", name)))?;
+        let ranking = snapshots::algorithm_ranking(algorithm.name());
+        rankings
+            .push(algorithm_snapshot(&*storage, &snapshots, &*clock, &*algorithm, &ranking).await?);
+    }
+
+    let mut tx = storage.begin().await?;
+    let ranking = interleaving::serve(
+        &mut *tx,
+        &interleaving,
+        &query.user_id,
+        &rankings[0],
+        &rankings[1],
+        query.length.unwrap_or(interleaving::DEFAULT_LENGTH),
+        clock.now_millis(),
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(Json(ranking))
+}
+
+pub async fn get_interleaving_results(
+    State(storage): State<Arc<dyn Storage>>,
+    Path(interleaving_id): Path<String>,
+) -> Result<Json<InterleavingResults>, AppError> {
+    let mut tx = storage.begin().await?;
+    let interleaving = find_interleaving(&mut *tx, &interleaving_id).await?;
+    let results = interleaving::results(&mut *tx, &interleaving).await?;
+    tx.commit().await?;
+
+    Ok(Json(results))
+}
+
+async fn find_interleaving(
+    tx: &mut dyn StorageTransaction,
+    interleaving_id: &str,
+) -> Result<Interleaving, AppError> {
+    tx.get_interleaving(interleaving_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Unknown interleaving: {}", interleaving_id)))
+}
+
+pub async fn train_upvote_share_model(
+    State(storage): State<Arc<dyn Storage>>,
+    State(config): State<Arc<RetrainingConfig>>,
+    State(clock): State<Arc<dyn Clock>>,
+) -> Result<Json<UpvoteShareModel>, AppError> {
+    let mut tx = storage.begin().await?;
+    let model = upvote_share_model::train(&mut *tx, config.training_window, clock.now_millis())
+        .await?
+        .ok_or_else(|| {
+            AppError::Conflict("Not enough observations to train an upvote share model".to_string())
+        })?;
+    tx.commit().await?;
+
+    Ok(Json(model))
+}
+
+pub async fn get_upvote_share_model_versions(
+    State(storage): State<Arc<dyn Storage>>,
+) -> Result<Json<Vec<UpvoteShareModelVersion>>, AppError> {
+    let mut tx = storage.begin().await?;
+    let versions = tx.get_model_versions().await?;
+    tx.commit().await?;
+
+    Ok(Json(versions))
+}
+
+pub async fn get_active_upvote_share_model(
+    State(storage): State<Arc<dyn Storage>>,
+) -> Result<Json<UpvoteShareModel>, AppError> {
+    let mut tx = storage.begin().await?;
+    let model = tx.get_active_model().await?.ok_or_else(|| {
+        AppError::NotFound("No upvote share model has been trained yet".to_string())
+    })?;
     tx.commit().await?;
 
-    Ok(Json(scored_items))
+    Ok(Json(model))
 }
 
-pub async fn get_ranking_quality_news(
-    State(pool): State<SqlitePool>,
-) -> Result<Json<Vec<ScoredItem>>, AppError> {
-    let mut tx: Transaction<'_, Sqlite> = pool.begin().await?;
-    let scored_items = quality_news::get_ranking(&mut tx).await?;
+pub async fn get_upvote_share_model(
+    State(storage): State<Arc<dyn Storage>>,
+    Path(model_version): Path<i64>,
+) -> Result<Json<UpvoteShareModel>, AppError> {
+    let mut tx = storage.begin().await?;
+    let model = tx.get_model(model_version).await?.ok_or_else(|| {
+        AppError::NotFound(format!(
+            "Unknown upvote share model version: {}",
+            model_version
+        ))
+    })?;
     tx.commit().await?;
 
-    Ok(Json(scored_items))
+    Ok(Json(model))
 }
 
-pub async fn get_ranking_newest(
-    State(pool): State<SqlitePool>,
-) -> Result<Json<Vec<ScoredItem>>, AppError> {
-    let mut tx: Transaction<'_, Sqlite> = pool.begin().await?;
-    let scored_items = newest::get_ranking(&mut tx).await?;
+pub async fn activate_upvote_share_model(
+    State(storage): State<Arc<dyn Storage>>,
+    State(clock): State<Arc<dyn Clock>>,
+    Path(model_version): Path<i64>,
+) -> Result<Json<UpvoteShareModel>, AppError> {
+    let mut tx = storage.begin().await?;
+    let model = upvote_share_model::activate(&mut *tx, model_version, clock.now_millis()).await?;
     tx.commit().await?;
 
-    Ok(Json(scored_items))
+    Ok(Json(model))
 }
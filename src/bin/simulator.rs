@@ -0,0 +1,152 @@
+use anyhow::Context;
+use clap::Parser;
+use dotenv::dotenv;
+use ranking_service::{
+    algs::registry::AlgorithmRegistry,
+    common::{error::AppError, time::parse_duration},
+    simulation::{self, SimulationConfig},
+    upvote_share_model::RetrainingConfig,
+};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::PathBuf,
+};
+
+/// Simulates users submitting and upvoting items in simulated time and
+/// reports how well each ranking algorithm recovers the items' quality
+#[derive(Parser)]
+struct Cli {
+    /// JSON file overriding parts of the default simulation config
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Seed of the random number generator
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Simulated time to run for, e.g. `12h` or `3d`
+    #[arg(long, value_parser = parse_millis)]
+    duration: Option<i64>,
+    /// Simulated time between two rounds of ranking, e.g. `30s` or `1m`
+    #[arg(long, value_parser = parse_millis)]
+    step: Option<i64>,
+    /// File to write the simulated items and vote events to, in the log
+    /// format of `ranking-service replay`
+    #[arg(long)]
+    log: Option<PathBuf>,
+    /// File to write the quality of each item to, as the ground truth for
+    /// `ranking-service evaluate --truth`
+    #[arg(long)]
+    truth: Option<PathBuf>,
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+    /// Print the effective config and exit
+    #[arg(long)]
+    print_config: bool,
+}
+
+fn parse_millis(spec: &str) -> Result<i64, String> {
+    parse_duration(spec).map_err(|e| format!("{:?}", e))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), AppError> {
+    dotenv().ok();
+
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .init();
+
+    let cli = Cli::parse();
+    let mut config: SimulationConfig = match &cli.config {
+        Some(path) => {
+            let file =
+                File::open(path).with_context(|| format!("Couldn't open {}", path.display()))?;
+            serde_json::from_reader(BufReader::new(file))
+                .with_context(|| format!("Invalid simulation config in {}", path.display()))?
+        }
+        None => SimulationConfig::default(),
+    };
+    config.seed = cli.seed.unwrap_or(config.seed);
+    config.duration = cli.duration.unwrap_or(config.duration);
+    config.step = cli.step.unwrap_or(config.step);
+
+    if cli.print_config {
+        println!("{}", serde_json::to_string_pretty(&config)?);
+        return Ok(());
+    }
+
+    let mut log = match &cli.log {
+        Some(path) => {
+            Some(BufWriter::new(File::create(path).with_context(|| {
+                format!("Couldn't create {}", path.display())
+            })?))
+        }
+        None => None,
+    };
+    let report = simulation::simulate(
+        &config,
+        &AlgorithmRegistry::with_defaults(),
+        &RetrainingConfig::from_env()?,
+        |entry| {
+            if let Some(log) = &mut log {
+                serde_json::to_writer(&mut *log, entry)?;
+                writeln!(log)?;
+            }
+            Ok(())
+        },
+    )
+    .await?;
+    if let Some(log) = &mut log {
+        log.flush()?;
+    }
+    if let Some(path) = &cli.truth {
+        let file =
+            File::create(path).with_context(|| format!("Couldn't create {}", path.display()))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), &report.quality)?;
+    }
+
+    if cli.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    println!(
+        "{} items, {} sessions, {} impressions, {} upvotes",
+        report.items, report.sessions, report.impressions, report.vote_events
+    );
+    println!(
+        "{:<16} {:>11} {:>12} {:>12} {:>12} {:>8} {:>12} {:>8} {:>14} {:>8}",
+        "algorithm",
+        "correlation",
+        "kendall tau",
+        "top quality",
+        "ideal top",
+        "ndcg",
+        "top overlap",
+        "churn",
+        "to front page",
+        "gini"
+    );
+    let metric = |value: Option<f64>| value.map_or("-".to_string(), |v| format!("{:.3}", v));
+    for a in &report.algorithms {
+        let e = &a.evaluation;
+        println!(
+            "{:<16} {:>11.3} {:>12} {:>12.3} {:>12.3} {:>8} {:>12.3} {:>8} {:>14} {:>8}",
+            a.algorithm,
+            a.quality_correlation,
+            metric(e.kendall_tau),
+            a.top_quality,
+            a.ideal_top_quality,
+            metric(e.ndcg),
+            a.top_overlap,
+            metric(e.churn),
+            e.time_to_front_page
+                .median
+                .map_or("-".to_string(), |millis| format!("{}m", millis / 60_000)),
+            metric(e.exposure_gini),
+        );
+    }
+
+    Ok(())
+}
@@ -2,30 +2,91 @@
 // INLINED FROM: https://github.com/social-protocols/prototype-1/blob/main/src/error.rs
 // --------------------------------------------------------------------
 
+#[cfg(feature = "server")]
 use axum::{
+    extract::rejection::{JsonRejection, PathRejection, QueryRejection},
     http::StatusCode,
     response::{IntoResponse, Response},
     Json,
 };
+#[cfg(feature = "server")]
 use serde_json::json;
+#[cfg(feature = "sqlx")]
+use sqlx::error::ErrorKind;
 
 // https://github.com/tokio-rs/axum/blob/main/examples/anyhow-error-response/src/main.rs
 #[derive(Debug)]
-pub struct AppError(pub anyhow::Error);
+pub enum AppError {
+    /// The request is well-formed but its content is invalid.
+    Validation(String),
+    /// The request couldn't be parsed, e.g. malformed JSON or path parameters.
+    MalformedRequest(String),
+    /// The request conflicts with existing data, e.g. a duplicate id.
+    Conflict(String),
+    /// The requested or referenced resource doesn't exist.
+    NotFound(String),
+    /// The ranking snapshot a cursor refers to is no longer available.
+    CursorExpired,
+    /// Quality news hasn't recorded its first sample yet.
+    SamplingNotInitialized,
+    Internal(anyhow::Error),
+}
+
+impl AppError {
+    #[cfg(feature = "server")]
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::MalformedRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::CursorExpired => StatusCode::GONE,
+            AppError::SamplingNotInitialized => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Machine-readable error code returned alongside the message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::Validation(_) => "validation_error",
+            AppError::MalformedRequest(_) => "malformed_request",
+            AppError::Conflict(_) => "conflict",
+            AppError::NotFound(_) => "not_found",
+            AppError::CursorExpired => "cursor_expired",
+            AppError::SamplingNotInitialized => "sampling_not_initialized",
+            AppError::Internal(_) => "internal_error",
+        }
+    }
+}
 
 // Tell axum how to convert `AppError` into a response.
 // https://github.com/tokio-rs/axum/discussions/713
+#[cfg(feature = "server")]
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        let (status, error_message) = match self {
-            AppError(inner) => {
+        let status = self.status();
+        let code = self.code();
+        let error_message = match self {
+            AppError::Validation(message)
+            | AppError::MalformedRequest(message)
+            | AppError::Conflict(message)
+            | AppError::NotFound(message) => message,
+            AppError::CursorExpired => {
+                "Cursor has expired, start again from the first page".to_string()
+            }
+            AppError::SamplingNotInitialized => {
+                "Quality news sampling has not been initialized yet".to_string()
+            }
+            AppError::Internal(inner) => {
                 tracing::debug!("stacktrace: {}", inner.backtrace());
-                (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error")
+                "Internal Server Error".to_string()
             }
         };
 
         let body = Json(json!({
             "error": error_message,
+            "code": code,
         }));
 
         (status, body).into_response()
@@ -33,12 +94,238 @@ impl IntoResponse for AppError {
 }
 
 // This enables using `?` on functions that return `Result<_, anyhow::Error>` to turn them into
-// `Result<_, AppError>`. That way you don't need to do that manually.
+// `Result<_, AppError>`. That way you don't need to do that manually. Errors caused by the
+// request, such as extractor rejections and constraint violations, are mapped to the matching
+// variant, everything else is internal.
 impl<E> From<E> for AppError
 where
     E: Into<anyhow::Error>,
 {
     fn from(err: E) -> Self {
-        AppError(err.into())
+        let err: anyhow::Error = err.into();
+
+        #[cfg(feature = "server")]
+        {
+            if let Some(rejection) = err.downcast_ref::<JsonRejection>() {
+                return match rejection {
+                    JsonRejection::JsonDataError(_) => AppError::Validation(rejection.body_text()),
+                    _ => AppError::MalformedRequest(rejection.body_text()),
+                };
+            }
+            if let Some(rejection) = err.downcast_ref::<PathRejection>() {
+                return AppError::MalformedRequest(rejection.body_text());
+            }
+            if let Some(rejection) = err.downcast_ref::<QueryRejection>() {
+                return AppError::MalformedRequest(rejection.body_text());
+            }
+        }
+        #[cfg(feature = "sqlx")]
+        if let Some(sqlx::Error::Database(db_err)) = err.downcast_ref::<sqlx::Error>() {
+            let message = db_err.message().to_string();
+            match db_err.kind() {
+                ErrorKind::UniqueViolation => return AppError::Conflict(message),
+                ErrorKind::ForeignKeyViolation => return AppError::NotFound(message),
+                ErrorKind::NotNullViolation | ErrorKind::CheckViolation => {
+                    return AppError::Validation(message)
+                }
+                _ => {}
+            }
+        }
+
+        AppError::Internal(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn internal_errors_are_the_fallback() {
+        let err = AppError::from(anyhow::anyhow!("disk full"));
+        assert!(matches!(err, AppError::Internal(_)));
+        assert_eq!(err.code(), "internal_error");
+    }
+
+    #[cfg(feature = "server")]
+    mod server {
+        use super::*;
+        use axum::{body::Body, extract::FromRequest, http::Request};
+        use serde::Deserialize;
+
+        #[derive(Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct Payload {
+            count: i32,
+        }
+
+        async fn json_error(content_type: &str, body: &'static str) -> AppError {
+            let request = Request::builder()
+                .method("POST")
+                .header("content-type", content_type)
+                .body(Body::from(body))
+                .unwrap();
+            axum::Json::<Payload>::from_request(request, &())
+                .await
+                .unwrap_err()
+                .into()
+        }
+
+        async fn response_body(err: AppError) -> (StatusCode, serde_json::Value) {
+            let response = err.into_response();
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            (status, serde_json::from_slice(&body).unwrap())
+        }
+
+        #[test]
+        fn variants_map_to_statuses_and_codes() {
+            for (err, status, code) in [
+                (
+                    AppError::Validation(String::new()),
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "validation_error",
+                ),
+                (
+                    AppError::MalformedRequest(String::new()),
+                    StatusCode::BAD_REQUEST,
+                    "malformed_request",
+                ),
+                (
+                    AppError::Conflict(String::new()),
+                    StatusCode::CONFLICT,
+                    "conflict",
+                ),
+                (
+                    AppError::NotFound(String::new()),
+                    StatusCode::NOT_FOUND,
+                    "not_found",
+                ),
+                (AppError::CursorExpired, StatusCode::GONE, "cursor_expired"),
+                (
+                    AppError::SamplingNotInitialized,
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "sampling_not_initialized",
+                ),
+                (
+                    AppError::Internal(anyhow::anyhow!("")),
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "internal_error",
+                ),
+            ] {
+                assert_eq!((err.status(), err.code()), (status, code));
+            }
+        }
+
+        #[tokio::test]
+        async fn responses_carry_the_message_and_code() {
+            let (status, body) = response_body(AppError::NotFound("Unknown page: x".into())).await;
+            assert_eq!(status, StatusCode::NOT_FOUND);
+            assert_eq!(
+                body,
+                json!({"error": "Unknown page: x", "code": "not_found"})
+            );
+        }
+
+        #[tokio::test]
+        async fn internal_errors_hide_their_cause() {
+            let (status, body) = response_body(anyhow::anyhow!("disk full").into()).await;
+            assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+            assert_eq!(body["error"], "Internal Server Error");
+        }
+
+        #[tokio::test]
+        async fn invalid_json_content_is_a_validation_error() {
+            let err = json_error("application/json", r#"{"count": "many"}"#).await;
+            assert!(matches!(err, AppError::Validation(_)), "{:?}", err);
+        }
+
+        #[tokio::test]
+        async fn unparsable_json_is_malformed() {
+            let err = json_error("application/json", r#"{"count": "#).await;
+            assert!(matches!(err, AppError::MalformedRequest(_)), "{:?}", err);
+            let err = json_error("text/plain", r#"{"count": 1}"#).await;
+            assert!(matches!(err, AppError::MalformedRequest(_)), "{:?}", err);
+        }
+
+        #[test]
+        fn unparsable_queries_are_malformed() {
+            let uri = "/rankings?count=many".parse().unwrap();
+            let err: AppError = axum::extract::Query::<Payload>::try_from_uri(&uri)
+                .unwrap_err()
+                .into();
+            assert!(matches!(err, AppError::MalformedRequest(_)), "{:?}", err);
+        }
+    }
+
+    #[cfg(feature = "sqlite")]
+    mod sqlx_errors {
+        use super::*;
+        use sqlx::{sqlite::SqlitePoolOptions, SqlitePool};
+
+        async fn pool() -> SqlitePool {
+            // Each connection opens its own in-memory database
+            let pool = SqlitePoolOptions::new()
+                .max_connections(1)
+                .connect("sqlite::memory:")
+                .await
+                .unwrap();
+            for statement in [
+                "pragma foreign_keys = on",
+                "create table parent (id integer primary key)",
+                "create table child (
+                    id integer primary key
+                  , parent_id integer references parent(id)
+                  , name text not null
+                  , vote integer check (vote in (-1, 0, 1))
+                )",
+                "insert into parent (id) values (1)",
+            ] {
+                sqlx::query(statement).execute(&pool).await.unwrap();
+            }
+            pool
+        }
+
+        async fn insert_error(pool: &SqlitePool, values: &str) -> AppError {
+            sqlx::query(&format!(
+                "insert into child (id, parent_id, name, vote) values {}",
+                values
+            ))
+            .execute(pool)
+            .await
+            .unwrap_err()
+            .into()
+        }
+
+        #[tokio::test]
+        async fn constraint_violations_map_to_client_errors() {
+            let pool = pool().await;
+            sqlx::query("insert into child values (1, 1, 'a', 1)")
+                .execute(&pool)
+                .await
+                .unwrap();
+
+            let err = insert_error(&pool, "(1, 1, 'b', 1)").await;
+            assert!(matches!(err, AppError::Conflict(_)), "{:?}", err);
+            let err = insert_error(&pool, "(2, 2, 'b', 1)").await;
+            assert!(matches!(err, AppError::NotFound(_)), "{:?}", err);
+            let err = insert_error(&pool, "(2, 1, null, 1)").await;
+            assert!(matches!(err, AppError::Validation(_)), "{:?}", err);
+            let err = insert_error(&pool, "(2, 1, 'b', 5)").await;
+            assert!(matches!(err, AppError::Validation(_)), "{:?}", err);
+        }
+
+        #[tokio::test]
+        async fn other_database_errors_are_internal() {
+            let pool = pool().await;
+            let err: AppError = sqlx::query("select * from missing")
+                .execute(&pool)
+                .await
+                .unwrap_err()
+                .into();
+            assert!(matches!(err, AppError::Internal(_)), "{:?}", err);
+        }
     }
 }
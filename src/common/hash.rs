@@ -0,0 +1,43 @@
+/// Hashes the parts with 64-bit FNV-1a, which unlike the standard library's
+/// hashers is stable across builds, so the hash can decide things that must
+/// not change between releases. FNV-1a barely changes the high bits for
+/// inputs that only differ at the end, so the result is mixed with
+/// MurmurHash3's finalizer.
+pub fn stable_hash(parts: &[&[u8]]) -> u64 {
+    let mut hash = parts
+        .iter()
+        .enumerate()
+        .flat_map(|(i, part)| {
+            // Separates the parts, so ("ab", "c") and ("a", "bc") differ
+            let separator: &[u8] = if i > 0 { &[0] } else { &[] };
+            separator.iter().chain(part.iter())
+        })
+        .fold(0xcbf29ce484222325_u64, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^= hash >> 33;
+
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_are_stable() {
+        // Changing these values reassigns the users of running experiments
+        assert_eq!(stable_hash(&[]), 17280346270528514342);
+        assert_eq!(stable_hash(&[b"qn-vs-hn", b"user-1"]), 2674492903827598759);
+    }
+
+    #[test]
+    fn parts_are_separated() {
+        assert_ne!(stable_hash(&[b"ab", b"c"]), stable_hash(&[b"a", b"bc"]));
+        assert_ne!(stable_hash(&[b"abc"]), stable_hash(&[b"abc", b""]));
+    }
+}
@@ -1,37 +1,86 @@
-use serde::{Deserialize, Serialize};
-use sqlx::{Encode, FromRow};
-use std::fmt;
+use serde::{Deserialize, Deserializer, Serialize};
+#[cfg(feature = "sqlx")]
+use sqlx::FromRow;
 
-#[derive(Deserialize)]
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct VoteEvent {
     pub vote_event_id: i32,
     pub item_id: i32,
     pub user_id: String,
     pub vote: i32,
     pub rank: Option<i32>,
-    pub page: Option<RankingPage>,
+    #[serde(default, deserialize_with = "deserialize_page_id")]
+    pub page: Option<String>,
     pub created_at: i64,
 }
 
-#[derive(Serialize, Deserialize, Debug, Encode)]
-pub enum RankingPage {
-    Newest,
-    QualityNews,
-    HackerNews,
+/// Accepts the names of the former `RankingPage` enum as aliases of the pages
+/// of the built-in algorithms, so that existing clients keep working.
+fn deserialize_page_id<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let page = Option::<String>::deserialize(deserializer)?;
+    Ok(page.map(|page| match page.as_str() {
+        "Newest" => "newest".to_string(),
+        "QualityNews" => "quality_news".to_string(),
+        "HackerNews" => "hacker_news".to_string(),
+        _ => page,
+    }))
 }
 
-impl fmt::Display for RankingPage {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let status_str = match self {
-            RankingPage::Newest => "newest",
-            RankingPage::QualityNews => "quality_news",
-            RankingPage::HackerNews => "hacker_news",
-        };
-        write!(f, "{}", status_str)
-    }
+/// Impressions are stored as counts per page, item and rank in buckets of this
+/// many milliseconds.
+pub const IMPRESSION_BUCKET_MILLIS: i64 = 1000;
+
+/// An item shown to a user at a rank on a page.
+#[derive(Deserialize, Debug)]
+pub struct Impression {
+    pub user_id: String,
+    pub page: String,
+    pub item_id: i32,
+    pub rank: i32,
+    pub created_at: i64,
+}
+
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Page {
+    pub page_id: String,
+    pub algorithm: String,
+    #[cfg_attr(feature = "sqlx", sqlx(json))]
+    pub params: serde_json::Value,
+    pub created_at: i64,
 }
 
-#[derive(FromRow, Serialize, Deserialize, Debug)]
+#[derive(Deserialize, Debug)]
+pub struct PageDefinition {
+    pub page_id: String,
+    pub algorithm: String,
+    #[serde(default)]
+    pub params: serde_json::Value,
+}
+
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RankUpvoteShare {
+    pub rank: i32,
+    pub upvotes: i32,
+    pub upvote_share: f32,
+}
+
+/// The rank of an item on a page at a sample time.
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PageRank {
+    pub sample_time: i64,
+    pub item_id: i32,
+    pub rank: i32,
+}
+
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Item {
     pub item_id: i32,
     pub parent_id: Option<i32>,
@@ -39,12 +88,83 @@ pub struct Item {
     pub created_at: i64,
 }
 
-#[derive(FromRow, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ScoredItem {
     pub item_id: i32,
     pub rank: i32,
-    pub page: RankingPage,
+    pub page: String,
     pub score: f32,
+    #[cfg_attr(feature = "sqlx", sqlx(skip))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upvote_rate: Option<UpvoteRateEstimate>,
+}
+
+/// Vote counts of an item kept in memory, so that votes can update its scores
+/// without querying the database. Downvotes are votes of -1. The expected upvotes are the cumulative
+/// values maintained by quality news sampling, if any.
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ItemAggregate {
+    pub item_id: i32,
+    pub parent_id: Option<i32>,
+    pub submission_time: i64,
+    pub upvotes: i32,
+    pub downvotes: i32,
+    pub expected_upvotes: Option<f32>,
+    pub vote_event_expected_upvotes: Option<f32>,
+    pub impression_expected_upvotes: Option<f32>,
+    pub last_vote_at: Option<i64>,
+}
+
+impl ItemAggregate {
+    pub fn new(item: &Item) -> Self {
+        Self {
+            item_id: item.item_id,
+            parent_id: item.parent_id,
+            submission_time: item.created_at,
+            upvotes: 0,
+            downvotes: 0,
+            expected_upvotes: None,
+            vote_event_expected_upvotes: None,
+            impression_expected_upvotes: None,
+            last_vote_at: None,
+        }
+    }
+}
+
+/// A page of a ranking snapshot. `next_cursor` continues from the same
+/// snapshot and is absent on the last page.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RankingPage {
+    pub snapshot_id: u64,
+    pub computed_at: i64,
+    pub items: Vec<ScoredItem>,
+    pub next_cursor: Option<String>,
+}
+
+/// Body of the ranking routes. Requests with a `limit` or `cursor` page through
+/// a snapshot, all others get the bare list of items they always got.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum RankingResponse {
+    Items(Vec<ScoredItem>),
+    Page(RankingPage),
+}
+
+/// Estimated upvote rate of an item (upvotes per expected upvote) with the
+/// bounds of its credible interval.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpvoteRateEstimate {
+    pub posterior_mean: f32,
+    pub lower: f32,
+    pub upper: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AlgorithmInfo {
+    pub name: String,
+    pub params: serde_json::Value,
 }
 
 pub trait Score {
@@ -1,5 +1,73 @@
+use crate::common::error::AppError;
+use anyhow::{anyhow, Context};
 use chrono::Utc;
+use std::sync::atomic::{AtomicI64, Ordering};
 
 pub fn now_utc_millis() -> i64 {
     Utc::now().timestamp_millis()
 }
+
+/// Parses durations like `30s`, `30m`, `1h`, `1d` or `1w` into milliseconds.
+pub fn parse_duration(spec: &str) -> Result<i64, AppError> {
+    let spec = spec.trim();
+    let (amount, unit) = spec.split_at(spec.len().saturating_sub(1));
+    let amount: i64 = amount
+        .parse()
+        .with_context(|| format!("Invalid duration: {}", spec))?;
+    let unit_millis = match unit {
+        "s" => 1000,
+        "m" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        "d" => 24 * 60 * 60 * 1000,
+        "w" => 7 * 24 * 60 * 60 * 1000,
+        _ => return Err(anyhow!("Invalid duration unit: {}", spec).into()),
+    };
+
+    Ok(amount * unit_millis)
+}
+
+/// Source of the current time in milliseconds since the epoch. The service
+/// reads the time only through a clock, so that rankings can be computed
+/// deterministically and simulations can run faster than real time.
+pub trait Clock: Send + Sync {
+    fn now_millis(&self) -> i64;
+}
+
+/// The system's wall clock.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> i64 {
+        now_utc_millis()
+    }
+}
+
+/// A clock that only moves when told to, for tests, simulations and replays.
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    now: AtomicI64,
+}
+
+impl ManualClock {
+    pub fn new(now: i64) -> Self {
+        Self {
+            now: AtomicI64::new(now),
+        }
+    }
+
+    pub fn set(&self, now: i64) {
+        self.now.store(now, Ordering::SeqCst);
+    }
+
+    /// Moves the clock forward by `millis` and returns the new time.
+    pub fn advance(&self, millis: i64) -> i64 {
+        self.now.fetch_add(millis, Ordering::SeqCst) + millis
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_millis(&self) -> i64 {
+        self.now.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,411 @@
+use crate::common::model::PageRank;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Number of top items that count as shown unless configured otherwise.
+pub const DEFAULT_TOP: usize = 30;
+
+/// Parameters of the evaluation of a page's sampled rankings.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct PageEvaluationRequest {
+    /// How far back from now to evaluate, e.g. `6h` or `7d`. Defaults to a day.
+    pub window: Option<String>,
+    pub top: Option<usize>,
+    /// Relevance judgements by item id, e.g. the item quality written by the
+    /// simulator.
+    pub relevance: Option<HashMap<i32, f64>>,
+}
+
+/// How quickly new items reached the top of the rankings. Only items submitted
+/// between the first and the last evaluated ranking are counted, since older
+/// items may have reached the top before.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FrontPageTimes {
+    pub items: usize,
+    /// Items that were among the top items of at least one ranking.
+    pub reached: usize,
+    /// Median and mean time from submission to the first ranking that had the
+    /// item among its top items, in milliseconds.
+    pub median: Option<i64>,
+    pub mean: Option<f64>,
+}
+
+/// Metrics of a sequence of rankings. Metrics that can't be computed, e.g.
+/// NDCG without relevance judgements, are `None`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Evaluation {
+    pub rankings: usize,
+    /// Mean NDCG of the top items against the relevance judgements.
+    pub ndcg: Option<f64>,
+    /// Mean Kendall tau-b between the rankings and the ordering by relevance.
+    pub kendall_tau: Option<f64>,
+    /// Mean fraction of the top items that weren't among the top items of the
+    /// previous ranking.
+    pub churn: Option<f64>,
+    pub time_to_front_page: FrontPageTimes,
+    /// Gini coefficient of the exposure of the ranked items, where an item
+    /// gets `1 / log2(rank + 1)` per ranking that has it among its top items.
+    /// 0 if all items got the same exposure, close to 1 if a few got all of it.
+    pub exposure_gini: Option<f64>,
+}
+
+/// Evaluates a sequence of rankings as they come in, e.g. the samples of a
+/// page or the timeline of a replay, keeping only what's needed for the
+/// metrics. Rankings are lists of item ids from the top, and only their first
+/// `top` items count as shown.
+#[derive(Debug, Clone)]
+pub struct Evaluator {
+    top: usize,
+    rankings: usize,
+    first_sample_time: Option<i64>,
+    last_sample_time: Option<i64>,
+    ndcg: Vec<f64>,
+    kendall_tau: Vec<f64>,
+    churn: Vec<f64>,
+    previous_top: Option<HashSet<i32>>,
+    /// Time of the first ranking that had the item among its top items.
+    front_page_times: HashMap<i32, i64>,
+    exposure: HashMap<i32, f64>,
+}
+
+impl Evaluator {
+    pub fn new(top: usize) -> Self {
+        Self {
+            top,
+            rankings: 0,
+            first_sample_time: None,
+            last_sample_time: None,
+            ndcg: Vec::new(),
+            kendall_tau: Vec::new(),
+            churn: Vec::new(),
+            previous_top: None,
+            front_page_times: HashMap::new(),
+            exposure: HashMap::new(),
+        }
+    }
+
+    /// Adds the ranking at `sample_time`. Rankings must be added in the order
+    /// of their sample times. With `relevance`, the ranking is also compared
+    /// to the ideal ordering of its items.
+    pub fn record(
+        &mut self,
+        sample_time: i64,
+        ranking: &[i32],
+        relevance: Option<&HashMap<i32, f64>>,
+    ) {
+        self.rankings += 1;
+        self.first_sample_time.get_or_insert(sample_time);
+        self.last_sample_time = Some(sample_time);
+
+        if let Some(relevance) = relevance {
+            if let Some(ndcg) = ndcg(ranking, relevance, self.top) {
+                self.ndcg.push(ndcg);
+            }
+            if let Some(tau) = kendall_tau(ranking, relevance) {
+                self.kendall_tau.push(tau);
+            }
+        }
+
+        let top = &ranking[..self.top.min(ranking.len())];
+        if let Some(previous_top) = &self.previous_top {
+            if let Some(churn) = churn(previous_top, top) {
+                self.churn.push(churn);
+            }
+        }
+        self.previous_top = Some(top.iter().copied().collect());
+
+        for &item_id in ranking {
+            self.exposure.entry(item_id).or_insert(0.0);
+        }
+        for (i, &item_id) in top.iter().enumerate() {
+            *self.exposure.entry(item_id).or_insert(0.0) += 1.0 / (i as f64 + 2.0).log2();
+            self.front_page_times.entry(item_id).or_insert(sample_time);
+        }
+    }
+
+    /// The metrics of the rankings added so far. `submission_times` are the
+    /// submission times of the items by id, for the time to front page.
+    pub fn finish(&self, submission_times: &HashMap<i32, i64>) -> Evaluation {
+        let mean = |values: &[f64]| {
+            (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+        };
+
+        Evaluation {
+            rankings: self.rankings,
+            ndcg: mean(&self.ndcg),
+            kendall_tau: mean(&self.kendall_tau),
+            churn: mean(&self.churn),
+            time_to_front_page: self.front_page_times(submission_times),
+            exposure_gini: gini(&self.exposure.values().copied().collect_vec()),
+        }
+    }
+
+    fn front_page_times(&self, submission_times: &HashMap<i32, i64>) -> FrontPageTimes {
+        let (Some(first), Some(last)) = (self.first_sample_time, self.last_sample_time) else {
+            return FrontPageTimes::default();
+        };
+        let new_items = submission_times
+            .iter()
+            .filter(|(_, &submitted_at)| submitted_at >= first && submitted_at <= last)
+            .collect_vec();
+        let times: Vec<i64> = new_items
+            .iter()
+            .filter_map(|(item_id, &submitted_at)| {
+                Some(self.front_page_times.get(item_id)? - submitted_at)
+            })
+            .sorted()
+            .collect();
+
+        FrontPageTimes {
+            items: new_items.len(),
+            reached: times.len(),
+            median: times.get(times.len() / 2).copied(),
+            mean: (!times.is_empty())
+                .then(|| times.iter().sum::<i64>() as f64 / times.len() as f64),
+        }
+    }
+}
+
+/// Evaluates the rankings of a page's rank history, one per sample time.
+pub fn evaluate_page_ranks(
+    page_ranks: &[PageRank],
+    top: usize,
+    relevance: Option<&HashMap<i32, f64>>,
+    submission_times: &HashMap<i32, i64>,
+) -> Evaluation {
+    let mut evaluator = Evaluator::new(top);
+    for (sample_time, ranks) in &page_ranks.iter().chunk_by(|r| r.sample_time) {
+        let ranking = ranks
+            .sorted_by_key(|r| r.rank)
+            .map(|r| r.item_id)
+            .collect_vec();
+        evaluator.record(sample_time, &ranking, relevance);
+    }
+
+    evaluator.finish(submission_times)
+}
+
+/// Normalized discounted cumulative gain of the first `k` items of the ranking,
+/// with the relevance as gain. The ideal ranking orders the same items by
+/// relevance. Items without relevance count as 0. `None` if no item is
+/// relevant.
+pub fn ndcg(ranking: &[i32], relevance: &HashMap<i32, f64>, k: usize) -> Option<f64> {
+    let gains: Vec<f64> = ranking
+        .iter()
+        .map(|item_id| relevance.get(item_id).copied().unwrap_or(0.0))
+        .collect();
+    let dcg = |gains: &[f64]| -> f64 {
+        gains
+            .iter()
+            .take(k)
+            .enumerate()
+            .map(|(i, gain)| gain / (i as f64 + 2.0).log2())
+            .sum()
+    };
+    let ideal = dcg(&gains
+        .iter()
+        .copied()
+        .sorted_by(|a, b| b.total_cmp(a))
+        .collect_vec());
+
+    (ideal > 0.0).then(|| dcg(&gains) / ideal)
+}
+
+/// Kendall tau-b between the ranking and the ordering of its items by
+/// relevance, 1 if the ranking puts more relevant items first throughout.
+/// Items without relevance are left out. `None` for fewer than two items or if
+/// all have the same relevance.
+pub fn kendall_tau(ranking: &[i32], relevance: &HashMap<i32, f64>) -> Option<f64> {
+    let mut values: Vec<f64> = ranking
+        .iter()
+        .filter_map(|item_id| relevance.get(item_id).copied())
+        .collect();
+    let n = values.len() as u64;
+    let pairs = n * n.saturating_sub(1) / 2;
+    let tied_pairs: u64 = values
+        .iter()
+        .sorted_by(|a, b| a.total_cmp(b))
+        .dedup_by_with_count(|a, b| a == b)
+        .map(|(count, _)| count as u64 * (count as u64 - 1) / 2)
+        .sum();
+    if pairs == tied_pairs {
+        return None;
+    }
+
+    // Pairs where the lower ranked item is more relevant
+    let discordant = count_ascending_pairs(&mut values);
+    let concordant = pairs - tied_pairs - discordant;
+
+    Some(
+        (concordant as f64 - discordant as f64)
+            / ((pairs as f64) * ((pairs - tied_pairs) as f64)).sqrt(),
+    )
+}
+
+/// Counts the pairs `i < j` with `values[i] < values[j]` in `O(n log n)` by
+/// merge sorting the values in descending order.
+fn count_ascending_pairs(values: &mut [f64]) -> u64 {
+    if values.len() < 2 {
+        return 0;
+    }
+    let mid = values.len() / 2;
+    let mut count =
+        count_ascending_pairs(&mut values[..mid]) + count_ascending_pairs(&mut values[mid..]);
+
+    let mut merged = Vec::with_capacity(values.len());
+    let (left, right) = values.split_at(mid);
+    let (mut i, mut j) = (0, 0);
+    while i < left.len() && j < right.len() {
+        if left[i] >= right[j] {
+            merged.push(left[i]);
+            i += 1;
+        } else {
+            // The remaining left values are all less than this one
+            count += (left.len() - i) as u64;
+            merged.push(right[j]);
+            j += 1;
+        }
+    }
+    merged.extend_from_slice(&left[i..]);
+    merged.extend_from_slice(&right[j..]);
+    values.copy_from_slice(&merged);
+
+    count
+}
+
+/// Fraction of the top items that aren't among the previous top items. `None`
+/// if there are no top items.
+pub fn churn(previous_top: &HashSet<i32>, top: &[i32]) -> Option<f64> {
+    (!top.is_empty()).then(|| {
+        top.iter()
+            .filter(|item_id| !previous_top.contains(item_id))
+            .count() as f64
+            / top.len() as f64
+    })
+}
+
+/// Gini coefficient of non-negative values. `None` if there are none or all
+/// are 0.
+pub fn gini(values: &[f64]) -> Option<f64> {
+    let total: f64 = values.iter().sum();
+    if values.is_empty() || total <= 0.0 {
+        return None;
+    }
+    let n = values.len() as f64;
+    let weighted: f64 = values
+        .iter()
+        .sorted_by(|a, b| a.total_cmp(b))
+        .enumerate()
+        .map(|(i, value)| (2.0 * (i as f64 + 1.0) - n - 1.0) * value)
+        .sum();
+
+    Some(weighted / (n * total))
+}
+
+/// Spearman's rank correlation, with tied values sharing their mean rank.
+pub fn spearman(x: &[f64], y: &[f64]) -> f64 {
+    let (rx, ry) = (fractional_ranks(x), fractional_ranks(y));
+    let n = x.len() as f64;
+    let (mx, my) = (rx.iter().sum::<f64>() / n, ry.iter().sum::<f64>() / n);
+    let covariance: f64 = rx.iter().zip(&ry).map(|(a, b)| (a - mx) * (b - my)).sum();
+    let vx: f64 = rx.iter().map(|a| (a - mx).powi(2)).sum();
+    let vy: f64 = ry.iter().map(|b| (b - my).powi(2)).sum();
+    if vx == 0.0 || vy == 0.0 {
+        return 0.0;
+    }
+    covariance / (vx * vy).sqrt()
+}
+
+fn fractional_ranks(values: &[f64]) -> Vec<f64> {
+    let order: Vec<usize> = (0..values.len())
+        .sorted_by(|&a, &b| values[a].total_cmp(&values[b]))
+        .collect();
+    let mut ranks = vec![0.0; values.len()];
+    let mut i = 0;
+    while i < order.len() {
+        let mut j = i;
+        while j + 1 < order.len() && values[order[j + 1]] == values[order[i]] {
+            j += 1;
+        }
+        for &k in &order[i..=j] {
+            ranks[k] = (i + j) as f64 / 2.0 + 1.0;
+        }
+        i = j + 1;
+    }
+    ranks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn relevance(values: &[(i32, f64)]) -> HashMap<i32, f64> {
+        values.iter().copied().collect()
+    }
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.expect("Metric is defined");
+        assert!(
+            (actual - expected).abs() < 1e-6,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn ndcg_of_known_rankings() {
+        let relevance = relevance(&[(1, 3.0), (2, 2.0), (3, 1.0)]);
+
+        assert_close(ndcg(&[1, 2, 3], &relevance, 3), 1.0);
+        assert_close(ndcg(&[3, 2, 1], &relevance, 3), 0.789998);
+        assert_close(ndcg(&[4, 1, 2, 3], &relevance, 1), 0.0);
+        assert_eq!(ndcg(&[4, 5], &relevance, 2), None);
+    }
+
+    #[test]
+    fn kendall_tau_of_known_rankings() {
+        let relevance = relevance(&[(1, 3.0), (2, 2.0), (3, 2.0), (4, 1.0)]);
+
+        assert_close(kendall_tau(&[1, 2, 3, 4], &relevance), 5.0 / 30f64.sqrt());
+        assert_close(kendall_tau(&[4, 3, 2, 1], &relevance), -5.0 / 30f64.sqrt());
+        assert_eq!(kendall_tau(&[2, 3], &relevance), None);
+        assert_eq!(kendall_tau(&[1], &relevance), None);
+    }
+
+    #[test]
+    fn ascending_pairs_match_a_brute_force_count() {
+        let values = [3.0, 1.0, 4.0, 1.0, 5.0, 9.0, 2.0, 6.0, 5.0, 3.0];
+        let expected = (0..values.len())
+            .tuple_combinations()
+            .filter(|&(i, j)| values[i] < values[j])
+            .count() as u64;
+
+        assert_eq!(count_ascending_pairs(&mut values.clone()), expected);
+    }
+
+    #[test]
+    fn churn_counts_new_top_items() {
+        let previous_top = HashSet::from([1, 2]);
+
+        assert_close(churn(&previous_top, &[2, 3, 4, 5]), 0.75);
+        assert_eq!(churn(&previous_top, &[]), None);
+    }
+
+    #[test]
+    fn gini_of_known_distributions() {
+        assert_close(gini(&[1.0, 1.0, 1.0, 1.0]), 0.0);
+        assert_close(gini(&[0.0, 0.0, 0.0, 1.0]), 0.75);
+        assert_eq!(gini(&[0.0, 0.0]), None);
+        assert_eq!(gini(&[]), None);
+    }
+
+    #[test]
+    fn spearman_of_monotonic_values() {
+        assert_close(Some(spearman(&[1.0, 2.0, 3.0], &[10.0, 20.0, 40.0])), 1.0);
+        assert_close(Some(spearman(&[1.0, 2.0, 3.0], &[3.0, 2.0, 1.0])), -1.0);
+        assert_close(Some(spearman(&[1.0, 1.0, 1.0], &[3.0, 2.0, 1.0])), 0.0);
+    }
+}
@@ -0,0 +1,302 @@
+use crate::common::{error::AppError, hash::stable_hash, model::IMPRESSION_BUCKET_MILLIS};
+use crate::storage::StorageTransaction;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "sqlx")]
+use sqlx::FromRow;
+use statrs::distribution::{ContinuousCDF, Gamma};
+
+/// Level of the confidence intervals of experiment results unless requested
+/// otherwise.
+pub const DEFAULT_CONFIDENCE: f64 = 0.95;
+
+/// Query parameters of the ranking served to a user in the running
+/// experiment, with the parameters of `Pagination`.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ExperimentRankingQuery {
+    pub user_id: String,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    pub cursor: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ExperimentResultsQuery {
+    pub confidence: Option<f64>,
+}
+
+/// A page that a share of the users of an experiment are served.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExperimentArm {
+    pub page_id: String,
+    /// Share of the users assigned to the arm, relative to the other arms.
+    #[serde(default = "default_weight")]
+    pub weight: f64,
+}
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ExperimentDefinition {
+    pub experiment_id: String,
+    pub arms: Vec<ExperimentArm>,
+}
+
+impl ExperimentDefinition {
+    pub fn validate(&self) -> Result<(), AppError> {
+        if self.arms.len() < 2 {
+            return Err(AppError::Validation(
+                "An experiment needs at least two arms".to_string(),
+            ));
+        }
+        if let Some(arm) = self
+            .arms
+            .iter()
+            .find(|arm| !(arm.weight.is_finite() && arm.weight > 0.0))
+        {
+            return Err(AppError::Validation(format!(
+                "Weight of arm {} must be positive",
+                arm.page_id
+            )));
+        }
+        if let Some(page_id) = self.arms.iter().map(|arm| &arm.page_id).duplicates().next() {
+            return Err(AppError::Validation(format!(
+                "Page {} is used by more than one arm",
+                page_id
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// An A/B experiment. It runs from its creation until it's ended, and at most
+/// one experiment runs at a time.
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Experiment {
+    pub experiment_id: String,
+    #[cfg_attr(feature = "sqlx", sqlx(json))]
+    pub arms: Vec<ExperimentArm>,
+    pub created_at: i64,
+    pub ended_at: Option<i64>,
+}
+
+impl Experiment {
+    /// The arm the user is assigned to. The assignment only depends on the
+    /// experiment and user ids, so users see the same arm on every request,
+    /// but are assigned independently in each experiment.
+    pub fn assign(&self, user_id: &str) -> &ExperimentArm {
+        let total_weight: f64 = self.arms.iter().map(|arm| arm.weight).sum();
+        let mut position = unit_hash(&self.experiment_id, user_id) * total_weight;
+        for arm in &self.arms {
+            if position < arm.weight {
+                return arm;
+            }
+            position -= arm.weight;
+        }
+        // Only reached through rounding errors
+        self.arms.last().expect("Experiments have arms")
+    }
+}
+
+/// Maps the experiment and user id to `[0, 1)`.
+fn unit_hash(experiment_id: &str, user_id: &str) -> f64 {
+    let hash = stable_hash(&[experiment_id.as_bytes(), user_id.as_bytes()]);
+    (hash >> 11) as f64 / (1_u64 << 53) as f64
+}
+
+/// Impressions and upvotes on a page within a time range.
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PageActivity {
+    pub impressions: i64,
+    pub upvotes: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ArmResult {
+    pub page_id: String,
+    pub impressions: i64,
+    pub upvotes: i64,
+    /// Upvotes per impression with the bounds of its exact Poisson confidence
+    /// interval. `None` without impressions.
+    pub upvotes_per_impression: Option<f64>,
+    pub lower: Option<f64>,
+    pub upper: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExperimentResults {
+    pub experiment_id: String,
+    pub from: i64,
+    pub until: i64,
+    pub confidence: f64,
+    pub arms: Vec<ArmResult>,
+}
+
+/// Results of the experiment so far. The impressions and upvotes of each arm
+/// are those reported for its page while the experiment ran, starting with
+/// the impression bucket it was created in.
+pub async fn results(
+    tx: &mut dyn StorageTransaction,
+    experiment: &Experiment,
+    now: i64,
+    confidence: f64,
+) -> Result<ExperimentResults, AppError> {
+    if !(confidence > 0.0 && confidence < 1.0) {
+        return Err(AppError::Validation(format!(
+            "Confidence must be between 0 and 1: {}",
+            confidence
+        )));
+    }
+    let from = experiment.created_at - experiment.created_at.rem_euclid(IMPRESSION_BUCKET_MILLIS);
+    let until = experiment.ended_at.unwrap_or(now);
+
+    let mut arms = Vec::with_capacity(experiment.arms.len());
+    for arm in &experiment.arms {
+        let activity = tx.get_page_activity(&arm.page_id, from, until).await?;
+        let (lower, upper) =
+            poisson_rate_interval(activity.upvotes, activity.impressions as f64, confidence)
+                .unzip();
+        arms.push(ArmResult {
+            page_id: arm.page_id.clone(),
+            impressions: activity.impressions,
+            upvotes: activity.upvotes,
+            upvotes_per_impression: (activity.impressions > 0)
+                .then(|| activity.upvotes as f64 / activity.impressions as f64),
+            lower,
+            upper,
+        });
+    }
+
+    Ok(ExperimentResults {
+        experiment_id: experiment.experiment_id.clone(),
+        from,
+        until,
+        confidence,
+        arms,
+    })
+}
+
+/// Equal-tailed exact confidence interval of the rate of a Poisson process
+/// with `count` events in `exposure`.
+fn poisson_rate_interval(count: i64, exposure: f64, confidence: f64) -> Option<(f64, f64)> {
+    if exposure <= 0.0 {
+        return None;
+    }
+    let tail = (1.0 - confidence) / 2.0;
+    let lower = match Gamma::new(count as f64, exposure) {
+        Ok(distribution) if count > 0 => distribution.inverse_cdf(tail),
+        _ => 0.0,
+    };
+    let upper = Gamma::new(count as f64 + 1.0, exposure)
+        .ok()?
+        .inverse_cdf(1.0 - tail);
+
+    Some((lower, upper))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn experiment(experiment_id: &str, weights: &[f64]) -> Experiment {
+        Experiment {
+            experiment_id: experiment_id.to_string(),
+            arms: weights
+                .iter()
+                .enumerate()
+                .map(|(i, &weight)| ExperimentArm {
+                    page_id: format!("page-{}", i),
+                    weight,
+                })
+                .collect(),
+            created_at: 0,
+            ended_at: None,
+        }
+    }
+
+    fn share_of_first_arm(experiment: &Experiment, n_users: usize) -> f64 {
+        let first = (0..n_users)
+            .filter(|i| experiment.assign(&format!("user-{}", i)).page_id == "page-0")
+            .count();
+        first as f64 / n_users as f64
+    }
+
+    #[test]
+    fn users_keep_their_arm() {
+        let experiment = experiment("qn-vs-hn", &[1.0, 1.0]);
+        for i in 0..100 {
+            let user_id = format!("user-{}", i);
+            assert_eq!(
+                experiment.assign(&user_id).page_id,
+                experiment.assign(&user_id).page_id
+            );
+        }
+        // The assignment must not change between releases
+        assert_eq!(experiment.assign("user-1").page_id, "page-0");
+    }
+
+    #[test]
+    fn arms_get_users_in_proportion_to_their_weight() {
+        let share = share_of_first_arm(&experiment("even", &[1.0, 1.0]), 20_000);
+        assert!((share - 0.5).abs() < 0.02, "{}", share);
+        let share = share_of_first_arm(&experiment("uneven", &[1.0, 3.0]), 20_000);
+        assert!((share - 0.25).abs() < 0.02, "{}", share);
+    }
+
+    #[test]
+    fn experiments_assign_users_independently() {
+        let a = experiment("a", &[1.0, 1.0]);
+        let b = experiment("b", &[1.0, 1.0]);
+        let n_users = 20_000;
+        let same = (0..n_users)
+            .map(|i| format!("user-{}", i))
+            .filter(|user_id| a.assign(user_id).page_id == b.assign(user_id).page_id)
+            .count();
+        let share = same as f64 / n_users as f64;
+        assert!((share - 0.5).abs() < 0.02, "{}", share);
+    }
+
+    #[test]
+    fn definitions_need_distinct_positively_weighted_arms() {
+        let definition = |arms: &[(&str, f64)]| ExperimentDefinition {
+            experiment_id: "e".to_string(),
+            arms: arms
+                .iter()
+                .map(|&(page_id, weight)| ExperimentArm {
+                    page_id: page_id.to_string(),
+                    weight,
+                })
+                .collect(),
+        };
+
+        assert!(definition(&[("a", 1.0), ("b", 2.0)]).validate().is_ok());
+        for arms in [
+            &[("a", 1.0)][..],
+            &[("a", 1.0), ("b", 0.0)],
+            &[("a", 1.0), ("b", f64::NAN)],
+            &[("a", 1.0), ("a", 1.0)],
+        ] {
+            assert!(definition(arms).validate().is_err(), "{:?}", arms);
+        }
+    }
+
+    #[test]
+    fn poisson_rate_intervals_of_known_counts() {
+        let (lower, upper) = poisson_rate_interval(0, 1.0, 0.95).unwrap();
+        assert_eq!(lower, 0.0);
+        assert!((upper - 3.688879).abs() < 1e-5);
+
+        let (lower, upper) = poisson_rate_interval(10, 10.0, 0.95).unwrap();
+        assert!((lower - 0.479539).abs() < 1e-5, "{}", lower);
+        assert!((upper - 1.839036).abs() < 1e-5, "{}", upper);
+
+        assert!(poisson_rate_interval(3, 0.0, 0.95).is_none());
+    }
+}
@@ -1,23 +1,102 @@
+use crate::algs::registry::AlgorithmRegistry;
 use crate::api;
-use crate::common::error::AppError;
+use crate::common::{error::AppError, time::Clock};
+use crate::live_scores::LiveScores;
+use crate::snapshots::SnapshotStore;
+use crate::storage::Storage;
+use crate::upvote_share_model::RetrainingConfig;
 use anyhow::Result;
 use axum::{
-    routing::{get, post},
+    extract::FromRef,
+    routing::{delete, get, post},
     Router,
 };
-use sqlx::SqlitePool;
+use std::sync::Arc;
 use tower_http::trace::TraceLayer;
 
-pub async fn start_http_server(pool: SqlitePool) -> Result<(), AppError> {
+#[derive(Clone, FromRef)]
+pub struct AppState {
+    pub storage: Arc<dyn Storage>,
+    pub registry: Arc<AlgorithmRegistry>,
+    pub retraining_config: Arc<RetrainingConfig>,
+    pub snapshots: Arc<SnapshotStore>,
+    pub live_scores: Arc<LiveScores>,
+    pub clock: Arc<dyn Clock>,
+}
+
+pub async fn start_http_server(state: AppState) -> Result<(), AppError> {
     let app = Router::new()
         .route("/health_check", get(api::health_check))
         .route("/items", post(api::register_item))
+        .route("/items/:item_id/thread", get(api::get_item_thread))
         .route("/vote_events", post(api::register_vote_event))
-        .route("/rankings/hn", get(api::get_hacker_news_ranking))
-        .route("/rankings/qn", get(api::get_ranking_quality_news))
-        .route("/rankings/newest", get(api::get_ranking_newest))
+        .route("/impressions", post(api::register_impressions))
+        .route("/algorithms", get(api::get_algorithms))
+        .route("/rankings", get(api::get_experiment_ranking))
+        .route("/rankings/best", get(api::get_best_ranking))
+        .route("/rankings/top", get(api::get_top_ranking))
+        // Routes of the algorithms before the registry; newest keeps its name
+        .route(
+            "/rankings/hn",
+            get(|query| api::redirect_ranking("hacker_news", query)),
+        )
+        .route(
+            "/rankings/qn",
+            get(|query| api::redirect_ranking("quality_news", query)),
+        )
+        .route("/rankings/:algorithm", get(api::get_ranking))
+        .route("/pages", get(api::get_pages).post(api::create_page))
+        .route("/pages/:page_id", delete(api::delete_page))
+        .route("/pages/:page_id/ranking", get(api::get_page_ranking))
+        .route(
+            "/pages/:page_id/upvote_shares",
+            get(api::get_page_upvote_shares),
+        )
+        .route("/pages/:page_id/evaluation", post(api::evaluate_page))
+        .route(
+            "/experiments",
+            get(api::get_experiments).post(api::create_experiment),
+        )
+        .route("/experiments/:experiment_id", get(api::get_experiment))
+        .route("/experiments/:experiment_id/end", post(api::end_experiment))
+        .route(
+            "/experiments/:experiment_id/results",
+            get(api::get_experiment_results),
+        )
+        .route(
+            "/interleavings",
+            get(api::get_interleavings).post(api::create_interleaving),
+        )
+        .route(
+            "/interleavings/:interleaving_id",
+            get(api::get_interleaving),
+        )
+        .route(
+            "/interleavings/:interleaving_id/ranking",
+            get(api::get_interleaved_ranking),
+        )
+        .route(
+            "/interleavings/:interleaving_id/results",
+            get(api::get_interleaving_results),
+        )
+        .route(
+            "/upvote_share_models",
+            get(api::get_upvote_share_model_versions).post(api::train_upvote_share_model),
+        )
+        .route(
+            "/upvote_share_models/active",
+            get(api::get_active_upvote_share_model),
+        )
+        .route(
+            "/upvote_share_models/:model_version",
+            get(api::get_upvote_share_model),
+        )
+        .route(
+            "/upvote_share_models/:model_version/activate",
+            post(api::activate_upvote_share_model),
+        )
         .layer(TraceLayer::new_for_http())
-        .with_state(pool);
+        .with_state(state);
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await?;
 
@@ -0,0 +1,364 @@
+use crate::common::{
+    error::AppError,
+    hash::stable_hash,
+    model::{ScoredItem, VoteEvent},
+};
+use crate::snapshots::RankingSnapshot;
+use crate::storage::StorageTransaction;
+use itertools::Itertools;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "sqlx")]
+use sqlx::FromRow;
+use statrs::distribution::{Binomial, DiscreteCDF};
+use std::collections::{BTreeMap, HashSet};
+
+/// Number of items of an interleaved list unless requested otherwise.
+pub const DEFAULT_LENGTH: usize = 30;
+
+#[derive(Deserialize, Debug)]
+pub struct InterleavingDefinition {
+    pub interleaving_id: String,
+    pub algorithm_a: String,
+    pub algorithm_b: String,
+}
+
+/// A comparison of two algorithms by team-draft interleaving. The
+/// interleaving id is used as the page of the votes on its lists.
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Interleaving {
+    pub interleaving_id: String,
+    pub algorithm_a: String,
+    pub algorithm_b: String,
+    pub created_at: i64,
+}
+
+/// A rank of an interleaved list served to a user, with the algorithm that
+/// contributed the item.
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InterleavedPosition {
+    pub served_at: i64,
+    pub rank: i32,
+    pub item_id: i32,
+    pub algorithm: String,
+}
+
+/// An upvote on an interleaved list, credited to the algorithm that
+/// contributed the voted rank.
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InterleavingCredit {
+    pub vote_event_id: i32,
+    pub user_id: String,
+    pub served_at: i64,
+    pub algorithm: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct InterleavedRankingQuery {
+    pub user_id: String,
+    pub length: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InterleavedItem {
+    pub item_id: i32,
+    pub rank: i32,
+    /// The interleaving id, to be reported as the page of votes.
+    pub page: String,
+    pub algorithm: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InterleavedRanking {
+    pub interleaving_id: String,
+    pub served_at: i64,
+    pub items: Vec<InterleavedItem>,
+}
+
+/// Outcome of an interleaving so far. Each list served to a user that
+/// received upvotes is a comparison, won by the algorithm credited with more
+/// of its upvotes.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InterleavingResults {
+    pub interleaving_id: String,
+    pub algorithm_a: String,
+    pub algorithm_b: String,
+    pub upvotes_a: i64,
+    pub upvotes_b: i64,
+    pub wins_a: i64,
+    pub wins_b: i64,
+    pub ties: i64,
+    /// `(wins_a + ties / 2) / comparisons - 0.5`, positive if users prefer
+    /// algorithm A. `None` without comparisons.
+    pub preference: Option<f64>,
+    /// Two-sided sign test of the wins against no preference.
+    pub p_value: Option<f64>,
+}
+
+impl InterleavingDefinition {
+    pub fn validate(&self) -> Result<(), AppError> {
+        if self.algorithm_a == self.algorithm_b {
+            return Err(AppError::Validation(
+                "An interleaving needs two different algorithms".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Merges two rankings by team-draft interleaving: the algorithm that
+/// contributed fewer items so far, or a coin flip on a tie, picks its highest
+/// ranked item not yet in the list. Returns the item ids with whether
+/// ranking A contributed them.
+pub fn team_draft(a: &[i32], b: &[i32], length: usize, rng: &mut impl Rng) -> Vec<(i32, bool)> {
+    let mut merged = Vec::with_capacity(length);
+    let mut used = HashSet::new();
+    let (mut next_a, mut next_b) = (a.iter().peekable(), b.iter().peekable());
+    let (mut picks_a, mut picks_b) = (0, 0);
+
+    while merged.len() < length {
+        while next_a.next_if(|item_id| used.contains(*item_id)).is_some() {}
+        while next_b.next_if(|item_id| used.contains(*item_id)).is_some() {}
+        let from_a = match (next_a.peek(), next_b.peek()) {
+            (None, None) => break,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (Some(_), Some(_)) => picks_a < picks_b || (picks_a == picks_b && rng.gen_bool(0.5)),
+        };
+        let item_id = if from_a {
+            picks_a += 1;
+            *next_a.next().expect("Peeked")
+        } else {
+            picks_b += 1;
+            *next_b.next().expect("Peeked")
+        };
+        used.insert(item_id);
+        merged.push((item_id, from_a));
+    }
+
+    merged
+}
+
+/// Interleaves the rankings of the two algorithms for the user and records
+/// which algorithm contributed each rank. The coin flips only depend on the
+/// interleaving, the user and the snapshots of the rankings, so a user sees
+/// the same list until either ranking changes.
+pub async fn serve(
+    tx: &mut dyn StorageTransaction,
+    interleaving: &Interleaving,
+    user_id: &str,
+    ranking_a: &RankingSnapshot,
+    ranking_b: &RankingSnapshot,
+    length: usize,
+    now: i64,
+) -> Result<InterleavedRanking, AppError> {
+    let seed = stable_hash(&[
+        interleaving.interleaving_id.as_bytes(),
+        user_id.as_bytes(),
+        &ranking_a.snapshot_id.to_le_bytes(),
+        &ranking_b.snapshot_id.to_le_bytes(),
+    ]);
+    let item_ids = |ranking: &[ScoredItem]| ranking.iter().map(|item| item.item_id).collect_vec();
+    let merged = team_draft(
+        &item_ids(&ranking_a.items),
+        &item_ids(&ranking_b.items),
+        length,
+        &mut StdRng::seed_from_u64(seed),
+    );
+
+    let positions: Vec<InterleavedPosition> = merged
+        .into_iter()
+        .enumerate()
+        .map(|(i, (item_id, from_a))| InterleavedPosition {
+            served_at: now,
+            rank: i as i32 + 1,
+            item_id,
+            algorithm: if from_a {
+                interleaving.algorithm_a.clone()
+            } else {
+                interleaving.algorithm_b.clone()
+            },
+        })
+        .collect();
+    tx.insert_interleaved_positions(&interleaving.interleaving_id, user_id, &positions)
+        .await?;
+
+    Ok(InterleavedRanking {
+        interleaving_id: interleaving.interleaving_id.clone(),
+        served_at: now,
+        items: positions
+            .into_iter()
+            .map(|position| InterleavedItem {
+                item_id: position.item_id,
+                rank: position.rank,
+                page: interleaving.interleaving_id.clone(),
+                algorithm: position.algorithm,
+            })
+            .collect(),
+    })
+}
+
+/// Credits an upvote on an interleaved list to the algorithm that contributed
+/// the voted rank in the latest list served to the user before the vote.
+/// Votes on other pages, without a rank, or on an item that wasn't at that
+/// rank are not credited. Returns whether the vote was credited.
+pub async fn credit_vote_event(
+    tx: &mut dyn StorageTransaction,
+    vote_event: &VoteEvent,
+) -> Result<bool, AppError> {
+    let (Some(page), Some(rank), 1) = (&vote_event.page, vote_event.rank, vote_event.vote) else {
+        return Ok(false);
+    };
+    let Some(position) = tx
+        .get_interleaved_position(page, &vote_event.user_id, rank, vote_event.created_at)
+        .await?
+        .filter(|position| position.item_id == vote_event.item_id)
+    else {
+        return Ok(false);
+    };
+
+    tx.insert_interleaving_credit(
+        page,
+        &InterleavingCredit {
+            vote_event_id: vote_event.vote_event_id,
+            user_id: vote_event.user_id.clone(),
+            served_at: position.served_at,
+            algorithm: position.algorithm,
+        },
+    )
+    .await?;
+
+    Ok(true)
+}
+
+pub async fn results(
+    tx: &mut dyn StorageTransaction,
+    interleaving: &Interleaving,
+) -> Result<InterleavingResults, AppError> {
+    let credits = tx
+        .get_interleaving_credits(&interleaving.interleaving_id)
+        .await?;
+    let is_a = |credit: &InterleavingCredit| credit.algorithm == interleaving.algorithm_a;
+
+    // Upvotes of A and B on each list served
+    let comparisons: BTreeMap<(&str, i64), (i64, i64)> =
+        credits
+            .iter()
+            .fold(BTreeMap::new(), |mut comparisons, credit| {
+                let upvotes = comparisons
+                    .entry((credit.user_id.as_str(), credit.served_at))
+                    .or_insert((0, 0));
+                if is_a(credit) {
+                    upvotes.0 += 1;
+                } else {
+                    upvotes.1 += 1;
+                }
+                comparisons
+            });
+    let wins_a = comparisons.values().filter(|(a, b)| a > b).count() as i64;
+    let wins_b = comparisons.values().filter(|(a, b)| a < b).count() as i64;
+    let ties = comparisons.len() as i64 - wins_a - wins_b;
+
+    Ok(InterleavingResults {
+        interleaving_id: interleaving.interleaving_id.clone(),
+        algorithm_a: interleaving.algorithm_a.clone(),
+        algorithm_b: interleaving.algorithm_b.clone(),
+        upvotes_a: credits.iter().filter(|credit| is_a(credit)).count() as i64,
+        upvotes_b: credits.iter().filter(|credit| !is_a(credit)).count() as i64,
+        wins_a,
+        wins_b,
+        ties,
+        preference: (!comparisons.is_empty())
+            .then(|| (wins_a as f64 + ties as f64 / 2.0) / comparisons.len() as f64 - 0.5),
+        p_value: sign_test(wins_a as u64, wins_b as u64),
+    })
+}
+
+/// Two-sided p-value of `wins_a` out of `wins_a + wins_b` under a fair coin.
+fn sign_test(wins_a: u64, wins_b: u64) -> Option<f64> {
+    let n = wins_a + wins_b;
+    let binomial = Binomial::new(0.5, n).ok().filter(|_| n > 0)?;
+    let tail = binomial.cdf(wins_a.min(wins_b));
+
+    Some((2.0 * tail).min(1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draft(a: &[i32], b: &[i32], length: usize, seed: u64) -> Vec<(i32, bool)> {
+        team_draft(a, b, length, &mut StdRng::seed_from_u64(seed))
+    }
+
+    #[test]
+    fn team_draft_balances_the_picks_of_both_rankings() {
+        let a = [1, 2, 3, 4, 5, 6];
+        let b = [6, 5, 4, 3, 2, 1];
+        for seed in 0..20 {
+            let merged = draft(&a, &b, 6, seed);
+            let picks_a = merged.iter().filter(|(_, from_a)| *from_a).count();
+
+            assert_eq!(merged.len(), 6);
+            assert_eq!(
+                merged.iter().map(|(item_id, _)| item_id).unique().count(),
+                6
+            );
+            assert_eq!(picks_a, 3, "seed {}", seed);
+            // Each ranking contributes its items in its own order
+            for (ranking, from_a) in [(&a, true), (&b, false)] {
+                let picks: Vec<i32> = merged
+                    .iter()
+                    .filter(|(_, f)| *f == from_a)
+                    .map(|(item_id, _)| *item_id)
+                    .collect();
+                let positions: Vec<usize> = picks
+                    .iter()
+                    .map(|item_id| ranking.iter().position(|i| i == item_id).unwrap())
+                    .collect();
+                assert!(positions.is_sorted(), "seed {}", seed);
+            }
+        }
+    }
+
+    #[test]
+    fn team_draft_of_identical_rankings_keeps_their_order() {
+        let ranking = [3, 1, 4, 5, 9];
+        for seed in 0..20 {
+            let item_ids: Vec<i32> = draft(&ranking, &ranking, 5, seed)
+                .into_iter()
+                .map(|(item_id, _)| item_id)
+                .collect();
+            assert_eq!(item_ids, ranking);
+        }
+    }
+
+    #[test]
+    fn team_draft_fills_up_from_the_longer_ranking() {
+        let merged = draft(&[1], &[2, 3, 4], 10, 0);
+
+        assert_eq!(merged.len(), 4);
+        assert_eq!(merged.iter().filter(|(_, from_a)| *from_a).count(), 1);
+        assert_eq!(
+            merged
+                .iter()
+                .filter(|(_, from_a)| !from_a)
+                .map(|(item_id, _)| *item_id)
+                .collect_vec(),
+            vec![2, 3, 4]
+        );
+    }
+
+    #[test]
+    fn team_draft_depends_only_on_the_seed() {
+        let a = [1, 2, 3, 4, 5, 6, 7, 8];
+        let b = [8, 2, 6, 4, 7, 1, 5, 3];
+
+        assert_eq!(draft(&a, &b, 8, 42), draft(&a, &b, 8, 42));
+    }
+}
@@ -0,0 +1,34 @@
+pub mod algs {
+    pub mod best;
+    pub mod hacker_news;
+    pub mod newest;
+    pub mod quality_news;
+    pub mod reddit_hot;
+    pub mod registry;
+    pub mod top;
+}
+#[cfg(feature = "server")]
+pub mod api;
+pub mod common {
+    pub mod error;
+    #[cfg(feature = "server")]
+    pub mod extract;
+    pub mod hash;
+    pub mod model;
+    pub mod time;
+}
+pub mod evaluation;
+pub mod experiments;
+#[cfg(feature = "server")]
+pub mod http_server;
+pub mod interleaving;
+pub mod live_scores;
+pub mod pages;
+pub mod replay;
+#[cfg(feature = "server")]
+pub mod scheduler;
+pub mod simulation;
+pub mod snapshots;
+pub mod storage;
+pub mod threads;
+pub mod upvote_share_model;
@@ -0,0 +1,359 @@
+use crate::algs::registry::{AggregateScorer, RankingAlgorithm};
+use crate::common::model::{ItemAggregate, ScoredItem};
+use itertools::Itertools;
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+
+/// Position of an item in a live ranking: descending score, ties broken by
+/// the more recent item.
+#[derive(Debug, Clone, Copy)]
+struct RankKey {
+    score: f32,
+    item_id: i32,
+}
+
+impl Ord for RankKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .score
+            .total_cmp(&self.score)
+            .then_with(|| other.item_id.cmp(&self.item_id))
+    }
+}
+
+impl PartialOrd for RankKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for RankKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for RankKey {}
+
+/// A ranking kept sorted in memory. All items are scored as of the same point
+/// in time, so re-scoring a single item after a vote keeps the order
+/// consistent.
+struct LiveRanking {
+    page: String,
+    scorer: Box<dyn AggregateScorer>,
+    order: BTreeSet<RankKey>,
+    items: HashMap<i32, ScoredItem>,
+}
+
+impl LiveRanking {
+    fn upsert(&mut self, item: &ItemAggregate) {
+        self.remove(item.item_id);
+        let Some(scored_item) = self.scorer.score(item) else {
+            return;
+        };
+        self.order.insert(RankKey {
+            score: scored_item.score,
+            item_id: item.item_id,
+        });
+        self.items.insert(item.item_id, scored_item);
+    }
+
+    /// Drops the oldest items beyond the scorer's pool size, which the full
+    /// ranking wouldn't draw from anymore.
+    fn truncate_pool(&mut self, aggregates: &HashMap<i32, ItemAggregate>) {
+        let Some(pool_size) = self.scorer.pool_size() else {
+            return;
+        };
+        let excess = self.items.len().saturating_sub(pool_size);
+        if excess == 0 {
+            return;
+        }
+        let oldest: Vec<i32> = self
+            .items
+            .keys()
+            .filter_map(|item_id| aggregates.get(item_id))
+            .sorted_by_key(|item| (item.submission_time, item.item_id))
+            .take(excess)
+            .map(|item| item.item_id)
+            .collect();
+        for item_id in oldest {
+            self.remove(item_id);
+        }
+    }
+
+    fn remove(&mut self, item_id: i32) {
+        if let Some(previous) = self.items.remove(&item_id) {
+            self.order.remove(&RankKey {
+                score: previous.score,
+                item_id,
+            });
+        }
+    }
+
+    fn ranking(&self) -> Vec<ScoredItem> {
+        self.order
+            .iter()
+            .enumerate()
+            .filter_map(|(rank, key)| {
+                self.items.get(&key.item_id).map(|item| ScoredItem {
+                    rank: rank as i32 + 1,
+                    page: self.page.clone(),
+                    ..item.clone()
+                })
+            })
+            .collect()
+    }
+}
+
+/// A ranking computed from the database, to be maintained incrementally.
+pub struct RankingSeed {
+    /// Key of the ranking in the snapshot store.
+    pub ranking: String,
+    /// Value of `ScoredItem::page` in the ranking.
+    pub page: String,
+    pub algorithm: Arc<dyn RankingAlgorithm>,
+    pub items: Vec<ScoredItem>,
+}
+
+#[derive(Default)]
+struct LiveScoresState {
+    aggregates: HashMap<i32, ItemAggregate>,
+    rankings: HashMap<String, LiveRanking>,
+    /// Whether every seeded ranking can be maintained incrementally.
+    complete: bool,
+    /// Whether the rankings changed since they were last taken.
+    dirty: bool,
+}
+
+/// Per-item vote aggregates and the rankings derived from them, updated on
+/// every accepted vote. The scheduler periodically reseeds them from the
+/// database, which also advances the point in time items are scored at.
+#[derive(Default)]
+pub struct LiveScores {
+    state: Mutex<LiveScoresState>,
+}
+
+impl LiveScores {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the aggregates and rankings with freshly computed ones. Items
+    /// keep the rankings they were seeded with, plus items registered later.
+    pub fn reseed(&self, aggregates: Vec<ItemAggregate>, seeds: Vec<RankingSeed>, at: i64) {
+        let aggregates: HashMap<i32, ItemAggregate> =
+            aggregates.into_iter().map(|a| (a.item_id, a)).collect();
+        let mut complete = true;
+        let mut rankings = HashMap::new();
+
+        for seed in seeds {
+            let pool: Vec<&ItemAggregate> = seed
+                .items
+                .iter()
+                .filter_map(|item| aggregates.get(&item.item_id))
+                .collect();
+            let Some(scorer) = seed.algorithm.aggregate_scorer(&pool, at) else {
+                complete = false;
+                continue;
+            };
+            let mut ranking = LiveRanking {
+                page: seed.page,
+                scorer,
+                order: BTreeSet::new(),
+                items: HashMap::new(),
+            };
+            for item in pool {
+                ranking.upsert(item);
+            }
+            rankings.insert(seed.ranking, ranking);
+        }
+
+        *self.state.lock().unwrap() = LiveScoresState {
+            aggregates,
+            rankings,
+            complete,
+            dirty: false,
+        };
+    }
+
+    /// Adds a newly registered item to every ranking it is eligible for. In
+    /// rankings limited to the newest items, it takes the place of the oldest
+    /// one.
+    pub fn record_item(&self, item: ItemAggregate) {
+        let mut state = self.state.lock().unwrap();
+        let LiveScoresState {
+            aggregates,
+            rankings,
+            dirty,
+            ..
+        } = &mut *state;
+        aggregates.insert(item.item_id, item.clone());
+        for ranking in rankings.values_mut() {
+            ranking.upsert(&item);
+            ranking.truncate_pool(aggregates);
+        }
+        *dirty = true;
+    }
+
+    /// Applies a vote that changed the item's upvotes and downvotes by the
+    /// given deltas and moves the item to its new position in every ranking
+    /// containing it. Rankings that aren't limited to the newest items also
+    /// take in items that the vote made eligible, and drop those it made
+    /// ineligible.
+    pub fn record_vote(
+        &self,
+        item_id: i32,
+        upvote_delta: i32,
+        downvote_delta: i32,
+        created_at: i64,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        let LiveScoresState {
+            aggregates,
+            rankings,
+            dirty,
+            ..
+        } = &mut *state;
+        let Some(aggregate) = aggregates.get_mut(&item_id) else {
+            return;
+        };
+        aggregate.upvotes += upvote_delta;
+        aggregate.downvotes += downvote_delta;
+        aggregate.last_vote_at = aggregate.last_vote_at.max(Some(created_at));
+
+        for ranking in rankings.values_mut() {
+            if ranking.items.contains_key(&item_id) || ranking.scorer.pool_size().is_none() {
+                ranking.upsert(aggregate);
+            }
+        }
+        *dirty = true;
+    }
+
+    /// Whether the rankings changed since the last call, and if so, whether
+    /// all of them are maintained incrementally.
+    pub fn take_changes(&self) -> Option<bool> {
+        let mut state = self.state.lock().unwrap();
+        std::mem::take(&mut state.dirty).then_some(state.complete)
+    }
+
+    /// Current rankings by snapshot key.
+    pub fn rankings(&self) -> Vec<(String, Vec<ScoredItem>)> {
+        self.state
+            .lock()
+            .unwrap()
+            .rankings
+            .iter()
+            .map(|(key, ranking)| (key.clone(), ranking.ranking()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algs::{
+        hacker_news::HackerNews, newest::Newest, reddit_hot::RedditHot, registry::AlgorithmRegistry,
+    };
+    use crate::common::time::ManualClock;
+    use crate::snapshots::{self, SnapshotConfig, SnapshotStore};
+    use crate::storage::memory::{
+        fixtures::{self, item, vote_event},
+        MemoryStorage,
+    };
+    use crate::storage::Storage;
+
+    const NOW: i64 = 1_700_000_000_000;
+    const HOUR: i64 = 60 * 60 * 1000;
+
+    fn registry() -> AlgorithmRegistry {
+        let mut registry = AlgorithmRegistry::new();
+        registry.register(Newest { pool_size: 4 });
+        registry.register(HackerNews {
+            pool_size: 4,
+            ..HackerNews::default()
+        });
+        registry.register(RedditHot {
+            pool_size: 4,
+            ..RedditHot::default()
+        });
+        registry
+    }
+
+    async fn insert_item(storage: &MemoryStorage, live_scores: &LiveScores, item_id: i32) {
+        let item = item(item_id, NOW - (10 - item_id as i64) * HOUR);
+        fixtures::insert(storage, std::slice::from_ref(&item), &[]).await;
+        live_scores.record_item(ItemAggregate::new(&item));
+    }
+
+    async fn insert_vote(
+        storage: &MemoryStorage,
+        live_scores: &LiveScores,
+        vote_event_id: i32,
+        item_id: i32,
+        vote: i32,
+    ) {
+        let vote_event = vote_event(
+            vote_event_id,
+            item_id,
+            &format!("user-{}", vote_event_id),
+            vote,
+            NOW - 1000 + vote_event_id as i64,
+        );
+        fixtures::insert(storage, &[], std::slice::from_ref(&vote_event)).await;
+        live_scores.record_vote(
+            item_id,
+            (vote == 1) as i32,
+            (vote == -1) as i32,
+            vote_event.created_at,
+        );
+    }
+
+    fn ranked_ids(items: &[ScoredItem]) -> Vec<i32> {
+        items.iter().map(|item| item.item_id).collect()
+    }
+
+    #[tokio::test]
+    async fn incremental_updates_match_a_full_recompute() {
+        let storage = MemoryStorage::with_clock(Arc::new(ManualClock::new(NOW)));
+        let registry = registry();
+        let live_scores = LiveScores::new();
+        for item_id in 1..=5 {
+            insert_item(&storage, &live_scores, item_id).await;
+        }
+        insert_vote(&storage, &live_scores, 1, 2, 1).await;
+        insert_vote(&storage, &live_scores, 2, 3, -1).await;
+
+        let store = SnapshotStore::new(NOW, SnapshotConfig::default());
+        let mut tx = storage.begin().await.unwrap();
+        snapshots::refresh_snapshots(&mut *tx, &registry, &store, &live_scores, NOW)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+
+        // New items push the oldest ones out of the pools, and votes on items
+        // that fell out of them don't bring them back
+        insert_item(&storage, &live_scores, 6).await;
+        insert_item(&storage, &live_scores, 7).await;
+        insert_vote(&storage, &live_scores, 3, 4, 1).await;
+        insert_vote(&storage, &live_scores, 4, 4, 1).await;
+        insert_vote(&storage, &live_scores, 5, 6, -1).await;
+        insert_vote(&storage, &live_scores, 6, 2, 1).await;
+        assert_eq!(live_scores.take_changes(), Some(true));
+
+        let live_rankings: HashMap<String, Vec<ScoredItem>> =
+            live_scores.rankings().into_iter().collect();
+        let mut tx = storage.begin().await.unwrap();
+        for algorithm in registry.algorithms() {
+            let ranking = snapshots::algorithm_ranking(algorithm.name());
+            let expected = algorithm.rank(&mut *tx, NOW).await.unwrap();
+            let live = &live_rankings[&ranking];
+
+            assert_eq!(ranked_ids(live), ranked_ids(&expected), "{}", ranking);
+            for (live, expected) in live.iter().zip(&expected) {
+                assert_eq!(live.rank, expected.rank, "{}", ranking);
+                assert!((live.score - expected.score).abs() < 1e-6, "{}", ranking);
+            }
+        }
+    }
+}
@@ -1,25 +1,85 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use clap::{Args, Parser, Subcommand};
 use dotenv::dotenv;
-use std::sync::Arc;
-use tracing_subscriber;
-use sqlx::migrate::Migrator;
+use ranking_service::{
+    algs, common, evaluation, http_server, live_scores, replay, scheduler, snapshots, storage,
+    upvote_share_model,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::PathBuf,
+    sync::Arc,
+};
 
-mod algs {
-    pub mod hacker_news;
-    pub mod newest;
-    pub mod quality_news;
+#[derive(Parser)]
+#[command(about = "Ranking algorithms served over HTTP")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
 }
-mod api;
-mod common {
-    pub mod error;
-    pub mod model;
-    pub mod time;
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the HTTP server and the scheduler (the default)
+    Serve,
+    /// Replay an item and vote event log in simulated time and write the
+    /// resulting ranking timeline as JSONL
+    Replay(ReplayArgs),
+    /// Replay an item and vote event log and report ranking quality metrics
+    /// of each algorithm
+    Evaluate(EvaluateArgs),
+}
+
+#[derive(Args)]
+struct ReplayArgs {
+    /// JSONL log of `item` and `vote_event` entries. Reads the log from the
+    /// database at DATABASE_URL if omitted
+    #[arg(long)]
+    input: Option<PathBuf>,
+    /// File to write the ranking timeline to
+    #[arg(long)]
+    output: PathBuf,
+    /// Simulated time between samples, e.g. `30s`, `1m` or `1h`
+    #[arg(long, default_value = "1m", value_parser = parse_duration)]
+    sample_interval: i64,
+    /// Comma separated algorithms to record rankings of, all if omitted
+    #[arg(long, value_delimiter = ',')]
+    algorithms: Vec<String>,
+    /// Number of top items recorded of each ranking
+    #[arg(long, default_value_t = 30)]
+    top: usize,
 }
-mod database;
-mod http_server;
-mod scheduler;
 
-static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+#[derive(Args)]
+struct EvaluateArgs {
+    /// JSONL log of `item` and `vote_event` entries. Reads the log from the
+    /// database at DATABASE_URL if omitted
+    #[arg(long)]
+    input: Option<PathBuf>,
+    /// JSON object of relevance judgements by item id, e.g. the item quality
+    /// written by `simulator --truth`. Enables NDCG and Kendall tau
+    #[arg(long)]
+    truth: Option<PathBuf>,
+    /// Simulated time between samples, e.g. `30s`, `1m` or `1h`
+    #[arg(long, default_value = "1m", value_parser = parse_duration)]
+    sample_interval: i64,
+    /// Comma separated algorithms to evaluate, all if omitted
+    #[arg(long, value_delimiter = ',')]
+    algorithms: Vec<String>,
+    /// Number of top items of each ranking that count as shown
+    #[arg(long, default_value_t = evaluation::DEFAULT_TOP)]
+    top: usize,
+    /// Print the metrics as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+fn parse_duration(spec: &str) -> Result<i64, String> {
+    common::time::parse_duration(spec).map_err(|e| format!("{:?}", e))
+}
 
 #[tokio::main]
 async fn main() -> Result<(), common::error::AppError> {
@@ -27,12 +87,188 @@ async fn main() -> Result<(), common::error::AppError> {
 
     tracing_subscriber::fmt::init();
 
-    let pool: sqlx::SqlitePool = database::setup_database().await?;
+    match Cli::parse().command {
+        None | Some(Command::Serve) => serve().await,
+        Some(Command::Replay(args)) => run_replay(args).await,
+        Some(Command::Evaluate(args)) => run_evaluate(args).await,
+    }
+}
+
+async fn serve() -> Result<(), common::error::AppError> {
+    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let storage = storage::connect(&database_url).await?;
+
+    let clock: Arc<dyn common::time::Clock> = Arc::new(common::time::SystemClock);
+    let registry = Arc::new(algs::registry::AlgorithmRegistry::with_defaults());
+    let retraining_config = Arc::new(upvote_share_model::RetrainingConfig::from_env()?);
+    let snapshots = Arc::new(snapshots::SnapshotStore::new(
+        clock.now_millis(),
+        snapshots::SnapshotConfig::from_env()?,
+    ));
+    let live_scores = Arc::new(live_scores::LiveScores::new());
+    if snapshots.config().persist {
+        let mut tx = storage.begin().await?;
+        snapshots.restore(tx.get_snapshots().await?);
+        tx.commit().await?;
+    }
+
+    scheduler::start_scheduler(
+        Arc::clone(&storage),
+        Arc::clone(&registry),
+        Arc::clone(&retraining_config),
+        Arc::clone(&snapshots),
+        Arc::clone(&live_scores),
+        Arc::clone(&clock),
+    )
+    .await?;
+    http_server::start_http_server(http_server::AppState {
+        storage,
+        registry,
+        retraining_config,
+        snapshots,
+        live_scores,
+        clock,
+    })
+    .await?;
+
+    Ok(())
+}
+
+async fn load_log(
+    input: Option<&PathBuf>,
+) -> Result<Vec<replay::LogEntry>, common::error::AppError> {
+    match input {
+        Some(path) => {
+            let file =
+                File::open(path).with_context(|| format!("Couldn't open {}", path.display()))?;
+            replay::parse_log(BufReader::new(file))
+        }
+        None => {
+            let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+            let storage = storage::connect(&database_url).await?;
+            let mut tx = storage.begin().await?;
+            let log = replay::read_log(&mut *tx).await?;
+            tx.commit().await?;
+            Ok(log)
+        }
+    }
+}
+
+/// The named algorithms of the registry, all if no names are given.
+fn select_algorithms(
+    registry: &algs::registry::AlgorithmRegistry,
+    names: &[String],
+) -> Result<Vec<Arc<dyn algs::registry::RankingAlgorithm>>, common::error::AppError> {
+    if names.is_empty() {
+        return Ok(registry.algorithms().cloned().collect());
+    }
+    names
+        .iter()
+        .map(|name| {
+            registry.get(name).ok_or_else(|| {
+                common::error::AppError::NotFound(format!("Unknown ranking algorithm: {}", name))
+            })
+        })
+        .collect()
+}
+
+async fn run_replay(args: ReplayArgs) -> Result<(), common::error::AppError> {
+    let log = load_log(args.input.as_ref()).await?;
+    let registry = algs::registry::AlgorithmRegistry::with_defaults();
+    let config = replay::ReplayConfig {
+        sample_interval: args.sample_interval,
+        algorithms: select_algorithms(&registry, &args.algorithms)?,
+        top: args.top,
+        retraining: upvote_share_model::RetrainingConfig::from_env()?,
+    };
+
+    let output = File::create(&args.output)
+        .with_context(|| format!("Couldn't create {}", args.output.display()))?;
+    let mut output = BufWriter::new(output);
+    replay::replay(&log, &registry, &config, |entry| {
+        serde_json::to_writer(&mut output, &entry)?;
+        writeln!(output)?;
+        Ok(())
+    })
+    .await?;
+    output.flush()?;
+
+    Ok(())
+}
+
+async fn run_evaluate(args: EvaluateArgs) -> Result<(), common::error::AppError> {
+    let relevance: Option<HashMap<i32, f64>> = match &args.truth {
+        Some(path) => {
+            let file =
+                File::open(path).with_context(|| format!("Couldn't open {}", path.display()))?;
+            Some(
+                serde_json::from_reader(BufReader::new(file)).with_context(|| {
+                    format!("Invalid relevance judgements in {}", path.display())
+                })?,
+            )
+        }
+        None => None,
+    };
+    let log = load_log(args.input.as_ref()).await?;
+    let submission_times: HashMap<i32, i64> = log
+        .iter()
+        .filter_map(|entry| match entry {
+            replay::LogEntry::Item(item) => Some((item.item_id, item.created_at)),
+            replay::LogEntry::VoteEvent(_) => None,
+        })
+        .collect();
+
+    let registry = algs::registry::AlgorithmRegistry::with_defaults();
+    let config = replay::ReplayConfig {
+        sample_interval: args.sample_interval,
+        algorithms: select_algorithms(&registry, &args.algorithms)?,
+        // The whole ranking, since the ideal ranking for NDCG and Kendall tau
+        // considers all ranked items
+        top: usize::MAX,
+        retraining: upvote_share_model::RetrainingConfig::from_env()?,
+    };
+
+    let mut evaluators: BTreeMap<String, evaluation::Evaluator> = BTreeMap::new();
+    replay::replay(&log, &registry, &config, |entry| {
+        let ranking: Vec<i32> = entry.items.iter().map(|item| item.item_id).collect();
+        evaluators
+            .entry(entry.algorithm)
+            .or_insert_with(|| evaluation::Evaluator::new(args.top))
+            .record(entry.sample_time, &ranking, relevance.as_ref());
+        Ok(())
+    })
+    .await?;
+    let evaluations: BTreeMap<String, evaluation::Evaluation> = evaluators
+        .into_iter()
+        .map(|(algorithm, evaluator)| (algorithm, evaluator.finish(&submission_times)))
+        .collect();
 
-    MIGRATOR.run(&pool).await?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&evaluations)?);
+        return Ok(());
+    }
 
-    scheduler::start_scheduler(Arc::clone(&Arc::new(pool.clone()))).await?;
-    http_server::start_http_server(pool).await?;
+    let metric = |value: Option<f64>| value.map_or("-".to_string(), |v| format!("{:.3}", v));
+    println!(
+        "{:<16} {:>9} {:>8} {:>12} {:>8} {:>14} {:>10} {:>8}",
+        "algorithm", "rankings", "ndcg", "kendall tau", "churn", "to front page", "reached", "gini"
+    );
+    for (algorithm, e) in &evaluations {
+        let front_page = &e.time_to_front_page;
+        println!(
+            "{:<16} {:>9} {:>8} {:>12} {:>8} {:>14} {:>10} {:>8}",
+            algorithm,
+            e.rankings,
+            metric(e.ndcg),
+            metric(e.kendall_tau),
+            metric(e.churn),
+            front_page
+                .median
+                .map_or("-".to_string(), |millis| format!("{}m", millis / 60_000)),
+            format!("{}/{}", front_page.reached, front_page.items),
+            metric(e.exposure_gini),
+        );
+    }
 
     Ok(())
 }
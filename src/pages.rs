@@ -0,0 +1,64 @@
+use crate::algs::registry::AlgorithmRegistry;
+use crate::common::{
+    error::AppError,
+    model::{Page, ScoredItem},
+};
+use crate::storage::StorageTransaction;
+use tracing::info;
+
+// TODO: change to once a minute for production
+pub const PAGE_SAMPLE_SCHEDULE: &str = "1/5 * * * * *";
+
+pub async fn get_page_ranking(
+    tx: &mut dyn StorageTransaction,
+    registry: &AlgorithmRegistry,
+    page: &Page,
+    now: i64,
+) -> Result<Vec<ScoredItem>, AppError> {
+    let algorithm = registry.configure(&page.algorithm, &page.params)?;
+
+    let scored_items = algorithm
+        .rank(tx, now)
+        .await?
+        .into_iter()
+        .map(|item| ScoredItem {
+            page: page.page_id.clone(),
+            ..item
+        })
+        .collect();
+
+    Ok(scored_items)
+}
+
+/// Closes the running observation window of every page and starts a new one
+/// with the page's current ranks, so that rank and upvote share are tracked
+/// for each page separately.
+pub async fn record_page_samples(
+    tx: &mut dyn StorageTransaction,
+    registry: &AlgorithmRegistry,
+    sample_time: i64,
+) -> Result<(), AppError> {
+    for page in tx.get_pages().await? {
+        tx.close_rank_window(&page.page_id, sample_time).await?;
+
+        // A page that can't be ranked is skipped without aborting the
+        // transaction for the other pages
+        tx.savepoint().await?;
+        match get_page_ranking(tx, registry, &page, sample_time).await {
+            Ok(ranking) => {
+                tx.insert_page_ranks(&page.page_id, sample_time, &ranking)
+                    .await?;
+                tx.release_savepoint().await?;
+            }
+            Err(e) => {
+                info!(
+                    "Page {} not ready for sampling - Skipping: {:?}",
+                    page.page_id, e
+                );
+                tx.rollback_to_savepoint().await?;
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,259 @@
+use crate::algs::registry::{AlgorithmRegistry, RankingAlgorithm};
+use crate::common::{
+    error::AppError,
+    model::{Item, ScoredItem, VoteEvent},
+    time::{Clock, ManualClock},
+};
+use crate::pages;
+use crate::storage::{memory::MemoryStorage, Storage, StorageTransaction};
+use crate::upvote_share_model::{self, RetrainingConfig};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::io::BufRead;
+use std::sync::Arc;
+use tracing::{debug, info};
+
+/// A record of the item and vote event log, as read from and written to JSONL
+/// files, e.g. `{"type": "item", "item_id": 1, ...}`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LogEntry {
+    Item(Item),
+    VoteEvent(VoteEvent),
+}
+
+impl LogEntry {
+    pub fn created_at(&self) -> i64 {
+        match self {
+            LogEntry::Item(item) => item.created_at,
+            LogEntry::VoteEvent(vote_event) => vote_event.created_at,
+        }
+    }
+
+    /// Orders entries by creation time. Items come before votes created at the
+    /// same time, so that votes never precede the item they're cast on.
+    fn replay_order(&self) -> (i64, bool, i32) {
+        match self {
+            LogEntry::Item(item) => (item.created_at, false, item.item_id),
+            LogEntry::VoteEvent(vote_event) => {
+                (vote_event.created_at, true, vote_event.vote_event_id)
+            }
+        }
+    }
+}
+
+/// The top items of an algorithm's ranking at one point of a replay.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TimelineEntry {
+    pub sample_time: i64,
+    pub algorithm: String,
+    pub items: Vec<ScoredItem>,
+}
+
+#[derive(Clone)]
+pub struct ReplayConfig {
+    /// Simulated time between two samples in milliseconds.
+    pub sample_interval: i64,
+    /// Algorithms whose rankings make up the timeline.
+    pub algorithms: Vec<Arc<dyn RankingAlgorithm>>,
+    /// Number of top items recorded of each ranking.
+    pub top: usize,
+    pub retraining: RetrainingConfig,
+}
+
+/// Reads the item and vote event log from storage, in the order the entries
+/// were created.
+pub async fn read_log(tx: &mut dyn StorageTransaction) -> Result<Vec<LogEntry>, AppError> {
+    let items = tx.get_items().await?.into_iter().map(LogEntry::Item);
+    let vote_events = tx
+        .get_vote_events()
+        .await?
+        .into_iter()
+        .map(LogEntry::VoteEvent);
+
+    let mut log: Vec<LogEntry> = items.chain(vote_events).collect();
+    log.sort_by_key(LogEntry::replay_order);
+
+    Ok(log)
+}
+
+/// Parses a JSONL log, ignoring blank lines. Entries may be in any order.
+pub fn parse_log(reader: impl BufRead) -> Result<Vec<LogEntry>, AppError> {
+    let mut log = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: LogEntry = serde_json::from_str(&line)
+            .with_context(|| format!("Invalid log entry on line {}", i + 1))?;
+        log.push(entry);
+    }
+    log.sort_by_key(LogEntry::replay_order);
+
+    Ok(log)
+}
+
+/// Replays the log in simulated time on a fresh in-memory storage. Every
+/// `sample_interval` the entries created since the previous sample are
+/// inserted and the scheduler's jobs run as they would at that time: the
+/// algorithms record their samples, the pages their ranks, and the upvote
+/// share model is retrained if due. Then the configured algorithms rank the
+/// items and their rankings are passed to `on_entry`. Algorithms that can't
+/// rank yet are skipped. The replay ends with the first sample after the last
+/// entry, and is deterministic for a given log and configuration.
+pub async fn replay(
+    log: &[LogEntry],
+    registry: &AlgorithmRegistry,
+    config: &ReplayConfig,
+    mut on_entry: impl FnMut(TimelineEntry) -> Result<(), AppError>,
+) -> Result<(), AppError> {
+    if config.sample_interval <= 0 {
+        return Err(AppError::Validation(
+            "The sample interval must be positive".to_string(),
+        ));
+    }
+    let (Some(first), Some(last)) = (log.first(), log.last()) else {
+        return Ok(());
+    };
+
+    let clock = Arc::new(ManualClock::new(first.created_at()));
+    let storage = MemoryStorage::with_clock(Arc::clone(&clock) as Arc<dyn Clock>);
+    let mut entries = log.iter().peekable();
+    let mut sample_time = first.created_at();
+
+    info!(
+        "Replaying {} log entries from {} to {}",
+        log.len(),
+        first.created_at(),
+        last.created_at()
+    );
+
+    while sample_time <= last.created_at() {
+        sample_time += config.sample_interval;
+        clock.set(sample_time);
+
+        let mut tx = storage.begin().await?;
+        while let Some(entry) = entries.next_if(|e| e.created_at() <= sample_time) {
+            match entry {
+                LogEntry::Item(item) => tx.insert_item(item).await?,
+                LogEntry::VoteEvent(vote_event) => tx.insert_vote_event(vote_event).await?,
+            }
+        }
+        for algorithm in registry.algorithms() {
+            algorithm.record_sample(&mut *tx, sample_time).await?;
+        }
+        pages::record_page_samples(&mut *tx, registry, sample_time).await?;
+        upvote_share_model::retrain_if_due(&mut *tx, &config.retraining, sample_time).await?;
+
+        for algorithm in &config.algorithms {
+            let items = match algorithm.rank(&mut *tx, sample_time).await {
+                Ok(items) => items,
+                Err(e) => {
+                    debug!("Skipping ranking of {}: {:?}", algorithm.name(), e);
+                    continue;
+                }
+            };
+            on_entry(TimelineEntry {
+                sample_time,
+                algorithm: algorithm.name().to_string(),
+                items: items.into_iter().take(config.top).collect(),
+            })?;
+        }
+        tx.commit().await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algs::{hacker_news::HackerNews, newest::Newest, quality_news::QualityNews};
+    use crate::storage::memory::fixtures::{item, vote_event};
+    use itertools::Itertools;
+
+    const NOW: i64 = 1_700_000_000_000;
+    const MINUTE: i64 = 60 * 1000;
+
+    fn log() -> Vec<LogEntry> {
+        let mut log: Vec<LogEntry> = (1..=4)
+            .map(|item_id| LogEntry::Item(item(item_id, NOW + item_id as i64 * MINUTE)))
+            .collect();
+        for (i, item_id) in [1, 2, 2, 3, 4, 4, 4].into_iter().enumerate() {
+            let mut vote_event = vote_event(
+                i as i32 + 1,
+                item_id,
+                &format!("user-{}", i),
+                1,
+                NOW + (5 + i as i64) * MINUTE,
+            );
+            vote_event.rank = Some(i as i32 % 3 + 1);
+            vote_event.page = Some("quality_news".to_string());
+            log.push(LogEntry::VoteEvent(vote_event));
+        }
+        log
+    }
+
+    fn config(registry: &AlgorithmRegistry) -> ReplayConfig {
+        ReplayConfig {
+            sample_interval: MINUTE,
+            algorithms: registry.algorithms().cloned().collect(),
+            top: 3,
+            retraining: RetrainingConfig::default(),
+        }
+    }
+
+    async fn timeline(log: &[LogEntry]) -> Vec<String> {
+        let mut registry = AlgorithmRegistry::new();
+        registry.register(Newest::default());
+        registry.register(HackerNews::default());
+        registry.register(QualityNews::default());
+
+        let mut timeline = Vec::new();
+        replay(log, &registry, &config(&registry), |entry| {
+            timeline.push(serde_json::to_string(&entry)?);
+            Ok(())
+        })
+        .await
+        .unwrap();
+        timeline
+    }
+
+    #[tokio::test]
+    async fn replays_are_deterministic() {
+        let log = log();
+        let first = timeline(&log).await;
+
+        assert!(first.len() > 20);
+        assert_eq!(first, timeline(&log).await);
+    }
+
+    #[test]
+    fn parsed_logs_are_ordered_by_creation_time() {
+        let lines = log()
+            .iter()
+            .rev()
+            .map(|entry| serde_json::to_string(entry).unwrap())
+            .join("\n");
+
+        let parsed = parse_log(lines.as_bytes()).unwrap();
+        let times: Vec<i64> = parsed.iter().map(LogEntry::created_at).collect();
+        assert!(times.is_sorted());
+        assert_eq!(parsed.len(), log().len());
+    }
+
+    #[tokio::test]
+    async fn votes_are_replayed_after_their_item_created_at_the_same_time() {
+        let vote_event = vote_event(1, 1, "user", 1, NOW);
+        let lines = [
+            serde_json::to_string(&LogEntry::VoteEvent(vote_event)).unwrap(),
+            serde_json::to_string(&LogEntry::Item(item(1, NOW))).unwrap(),
+        ]
+        .join("\n");
+
+        let log = parse_log(lines.as_bytes()).unwrap();
+        assert!(matches!(log[0], LogEntry::Item(_)));
+        assert!(!timeline(&log).await.is_empty());
+    }
+}
@@ -1,35 +1,221 @@
-use crate::algs::quality_news;
-use crate::common::error::AppError;
-use sqlx::{Sqlite, SqlitePool, Transaction};
+use crate::algs::registry::AlgorithmRegistry;
+use crate::common::{error::AppError, time::Clock};
+use crate::live_scores::LiveScores;
+use crate::pages;
+use crate::snapshots::{self, SnapshotStore};
+use crate::storage::Storage;
+use crate::upvote_share_model::{self, RetrainingConfig};
 use std::sync::Arc;
+use tokio::sync::Mutex;
 use tokio_cron_scheduler::{Job, JobScheduler};
-use tracing::error;
+use tracing::{error, info};
 
-pub async fn start_scheduler(pool: Arc<SqlitePool>) -> Result<(), AppError> {
+pub async fn start_scheduler(
+    storage: Arc<dyn Storage>,
+    registry: Arc<AlgorithmRegistry>,
+    retraining_config: Arc<RetrainingConfig>,
+    snapshot_store: Arc<SnapshotStore>,
+    live_scores: Arc<LiveScores>,
+    clock: Arc<dyn Clock>,
+) -> Result<(), AppError> {
     let scheduler = JobScheduler::new().await?;
 
-    let job_pool = Arc::clone(&pool);
+    // SQLite can't upgrade two concurrent read transactions to write
+    // transactions, so jobs that write to the database run one at a time.
+    let write_lock = Arc::new(Mutex::new(()));
 
-    // TODO: change to once a minute for production
-    let cron_expression = "1/5 * * * * *";
+    for algorithm in registry.algorithms() {
+        let Some(cron_expression) = algorithm.sample_schedule() else {
+            continue;
+        };
+        info!(
+            "Scheduling samples for {} at: {}",
+            algorithm.name(),
+            cron_expression
+        );
+
+        let job_storage = Arc::clone(&storage);
+        let job_lock = Arc::clone(&write_lock);
+        let job_clock = Arc::clone(&clock);
+        let algorithm = Arc::clone(algorithm);
+
+        scheduler
+            .add(Job::new_async(
+                cron_expression.as_str(),
+                move |_uuid, _l| {
+                    let job_storage = Arc::clone(&job_storage);
+                    let job_lock = Arc::clone(&job_lock);
+                    let job_clock = Arc::clone(&job_clock);
+                    let algorithm = Arc::clone(&algorithm);
+                    Box::pin(async move {
+                        let _guard = job_lock.lock().await;
+                        let mut tx = job_storage
+                            .begin()
+                            .await
+                            .expect("Couldn't create transaction");
+                        match algorithm
+                            .record_sample(&mut *tx, job_clock.now_millis())
+                            .await
+                        {
+                            Ok(_) => {
+                                tx.commit().await.unwrap();
+                            }
+                            Err(e) => {
+                                tx.rollback().await.unwrap();
+                                error!("Error recording sample for {}: {:?}", algorithm.name(), e);
+                            }
+                        };
+                    })
+                },
+            )?)
+            .await?;
+    }
+
+    let job_storage = Arc::clone(&storage);
+    let job_lock = Arc::clone(&write_lock);
+    let job_clock = Arc::clone(&clock);
+    let job_registry = Arc::clone(&registry);
+
+    scheduler
+        .add(Job::new_async(
+            pages::PAGE_SAMPLE_SCHEDULE,
+            move |_uuid, _l| {
+                let job_storage = Arc::clone(&job_storage);
+                let job_lock = Arc::clone(&job_lock);
+                let job_clock = Arc::clone(&job_clock);
+                let job_registry = Arc::clone(&job_registry);
+                Box::pin(async move {
+                    let _guard = job_lock.lock().await;
+                    let mut tx = job_storage
+                        .begin()
+                        .await
+                        .expect("Couldn't create transaction");
+                    match pages::record_page_samples(
+                        &mut *tx,
+                        &job_registry,
+                        job_clock.now_millis(),
+                    )
+                    .await
+                    {
+                        Ok(_) => {
+                            tx.commit().await.unwrap();
+                        }
+                        Err(e) => {
+                            tx.rollback().await.unwrap();
+                            error!("Error recording page samples: {:?}", e);
+                        }
+                    };
+                })
+            },
+        )?)
+        .await?;
+
+    let job_storage = Arc::clone(&storage);
+    let job_lock = Arc::clone(&write_lock);
+    let job_clock = Arc::clone(&clock);
+
+    scheduler
+        .add(Job::new_async(
+            upvote_share_model::RETRAINING_CHECK_SCHEDULE,
+            move |_uuid, _l| {
+                let job_storage = Arc::clone(&job_storage);
+                let job_lock = Arc::clone(&job_lock);
+                let job_clock = Arc::clone(&job_clock);
+                let retraining_config = Arc::clone(&retraining_config);
+                Box::pin(async move {
+                    let _guard = job_lock.lock().await;
+                    let mut tx = job_storage
+                        .begin()
+                        .await
+                        .expect("Couldn't create transaction");
+                    match upvote_share_model::retrain_if_due(
+                        &mut *tx,
+                        &retraining_config,
+                        job_clock.now_millis(),
+                    )
+                    .await
+                    {
+                        Ok(_) => {
+                            tx.commit().await.unwrap();
+                        }
+                        Err(e) => {
+                            tx.rollback().await.unwrap();
+                            error!("Error retraining upvote share model: {:?}", e);
+                        }
+                    };
+                })
+            },
+        )?)
+        .await?;
+
+    let job_storage = Arc::clone(&storage);
+    let job_lock = Arc::clone(&write_lock);
+    let job_clock = Arc::clone(&clock);
+    let job_registry = Arc::clone(&registry);
 
     scheduler
-        .add(Job::new_async(cron_expression, move |_uuid, _l| {
-            let job_pool = Arc::clone(&job_pool);
-            Box::pin(async move {
-                let mut tx: Transaction<'_, Sqlite> =
-                    job_pool.begin().await.expect("Couldn't create transaction");
-                match quality_news::record_sample(&mut tx).await {
-                    Ok(_) => {
-                        tx.commit().await.unwrap();
+        .add(Job::new_async(
+            snapshots::SNAPSHOT_REFRESH_SCHEDULE,
+            move |_uuid, _l| {
+                let job_storage = Arc::clone(&job_storage);
+                let job_lock = Arc::clone(&job_lock);
+                let job_clock = Arc::clone(&job_clock);
+                let job_registry = Arc::clone(&job_registry);
+                let snapshot_store = Arc::clone(&snapshot_store);
+                let live_scores = Arc::clone(&live_scores);
+                Box::pin(async move {
+                    let now = job_clock.now_millis();
+                    if !snapshot_store.refresh_due(now) {
+                        // Votes since the last refresh only need the live
+                        // rankings to be published, unless some ranking can't
+                        // be maintained incrementally
+                        match live_scores.take_changes() {
+                            None => return,
+                            Some(true) => {
+                                snapshot_store.publish_many(now, live_scores.rankings());
+                                return;
+                            }
+                            Some(false) => {}
+                        }
                     }
-                    Err(e) => {
-                        tx.rollback().await.unwrap();
-                        error!("Error recording sample: {:?}", e);
+                    // Computing rankings only reads, unless the snapshots are persisted
+                    let persist = snapshot_store.config().persist;
+                    let _guard = if persist {
+                        Some(job_lock.lock().await)
+                    } else {
+                        None
+                    };
+                    let mut tx = job_storage
+                        .begin()
+                        .await
+                        .expect("Couldn't create transaction");
+                    let result = async {
+                        let refreshed = snapshots::refresh_snapshots(
+                            &mut *tx,
+                            &job_registry,
+                            &snapshot_store,
+                            &live_scores,
+                            now,
+                        )
+                        .await?;
+                        if persist {
+                            tx.upsert_snapshots(&refreshed).await?;
+                        }
+                        Ok::<_, AppError>(())
                     }
-                };
-            })
-        })?)
+                    .await;
+                    match result {
+                        Ok(_) => {
+                            tx.commit().await.unwrap();
+                        }
+                        Err(e) => {
+                            tx.rollback().await.unwrap();
+                            error!("Error refreshing ranking snapshots: {:?}", e);
+                        }
+                    };
+                })
+            },
+        )?)
         .await?;
 
     scheduler.start().await?;
@@ -0,0 +1,426 @@
+use crate::algs::registry::AlgorithmRegistry;
+use crate::common::{
+    error::AppError,
+    model::{Item, ScoredItem, VoteEvent, IMPRESSION_BUCKET_MILLIS},
+    time::{Clock, ManualClock},
+};
+use crate::evaluation::{self, Evaluation, Evaluator};
+use crate::pages;
+use crate::replay::LogEntry;
+use crate::storage::{memory::MemoryStorage, Storage};
+use crate::upvote_share_model::{self, RetrainingConfig};
+use anyhow::Context;
+use itertools::Itertools;
+use rand::{distributions::WeightedIndex, rngs::StdRng, Rng, SeedableRng};
+use rand_distr::{Beta, Distribution, Poisson};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use tracing::{debug, info};
+
+const HOUR_MILLIS: i64 = 60 * 60 * 1000;
+
+/// Probability that a user looks at the item at a rank, given that they are
+/// on the page.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PositionBias {
+    /// `1 / rank^exponent`
+    Power { exponent: f64 },
+    /// `decay^(rank - 1)`
+    Exponential { decay: f64 },
+    /// Probabilities of the ranks from the top, 0 beyond the last one.
+    Table { probabilities: Vec<f64> },
+}
+
+impl PositionBias {
+    pub fn examination_probability(&self, rank: i32) -> f64 {
+        let p = match self {
+            PositionBias::Power { exponent } => (rank as f64).powf(-exponent),
+            PositionBias::Exponential { decay } => decay.powi(rank - 1),
+            PositionBias::Table { probabilities } => {
+                probabilities.get(rank as usize - 1).copied().unwrap_or(0.0)
+            }
+        };
+        p.clamp(0.0, 1.0)
+    }
+}
+
+impl Default for PositionBias {
+    fn default() -> Self {
+        PositionBias::Power { exponent: 1.0 }
+    }
+}
+
+/// A group of users that behave alike.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UserPopulation {
+    pub name: String,
+    pub size: usize,
+    /// Visits of each user per hour.
+    pub sessions_per_hour: f64,
+    /// Relative frequency with which the users visit each page.
+    pub pages: BTreeMap<String, f64>,
+    /// Probability of upvoting a looked at item of quality 1.
+    pub upvote_propensity: f64,
+    /// How much the users' upvotes follow quality. An item of quality `q` is
+    /// upvoted with probability `upvote_propensity * q^quality_sensitivity`,
+    /// so with 0 users upvote regardless of quality.
+    pub quality_sensitivity: f64,
+}
+
+/// Parameters of a simulation. Times and durations are in milliseconds.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SimulationConfig {
+    pub seed: u64,
+    pub start_time: i64,
+    pub duration: i64,
+    /// Simulated time between two rounds of sampling and ranking. Users see
+    /// the rankings of the previous round.
+    pub step: i64,
+    /// How often the rankings are compared to the true quality of the items.
+    pub evaluation_interval: i64,
+    pub items_per_hour: f64,
+    /// Parameters of the beta distribution of the items' intrinsic quality,
+    /// which is between 0 and 1.
+    pub quality_alpha: f64,
+    pub quality_beta: f64,
+    /// Number of ranks shown on a page.
+    pub page_size: i32,
+    /// Position bias of each page. Pages not listed use `default_position_bias`.
+    pub position_bias: BTreeMap<String, PositionBias>,
+    pub default_position_bias: PositionBias,
+    pub populations: Vec<UserPopulation>,
+    /// Number of top items whose quality is compared.
+    pub top: usize,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            start_time: 1_700_000_000_000,
+            duration: 3 * 24 * HOUR_MILLIS,
+            step: 60 * 1000,
+            evaluation_interval: 10 * 60 * 1000,
+            items_per_hour: 20.0,
+            quality_alpha: 2.0,
+            quality_beta: 5.0,
+            page_size: 30,
+            position_bias: BTreeMap::from([(
+                "newest".to_string(),
+                PositionBias::Exponential { decay: 0.9 },
+            )]),
+            default_position_bias: PositionBias::default(),
+            populations: vec![
+                UserPopulation {
+                    name: "regulars".to_string(),
+                    size: 200,
+                    sessions_per_hour: 1.0,
+                    pages: BTreeMap::from([
+                        ("quality_news".to_string(), 0.45),
+                        ("hacker_news".to_string(), 0.45),
+                        ("newest".to_string(), 0.1),
+                    ]),
+                    upvote_propensity: 0.5,
+                    quality_sensitivity: 1.0,
+                },
+                UserPopulation {
+                    name: "casuals".to_string(),
+                    size: 1000,
+                    sessions_per_hour: 0.1,
+                    pages: BTreeMap::from([
+                        ("quality_news".to_string(), 0.5),
+                        ("hacker_news".to_string(), 0.5),
+                    ]),
+                    upvote_propensity: 0.2,
+                    quality_sensitivity: 0.5,
+                },
+            ],
+            top: 30,
+        }
+    }
+}
+
+impl SimulationConfig {
+    fn position_bias(&self, page_id: &str) -> &PositionBias {
+        self.position_bias
+            .get(page_id)
+            .unwrap_or(&self.default_position_bias)
+    }
+}
+
+/// How well an algorithm's rankings agreed with the true quality of the
+/// ranked items, averaged over all evaluations.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AlgorithmReport {
+    pub algorithm: String,
+    pub evaluations: usize,
+    /// Spearman correlation between score and quality.
+    pub quality_correlation: f64,
+    /// Mean quality of the top items.
+    pub top_quality: f64,
+    /// Mean quality of the best items the algorithm could have put on top.
+    pub ideal_top_quality: f64,
+    /// Fraction of the top items that are among the best ones.
+    pub top_overlap: f64,
+    /// Metrics of the rankings of all evaluations, with quality as relevance.
+    pub evaluation: Evaluation,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SimulationReport {
+    pub items: usize,
+    pub sessions: usize,
+    pub impressions: usize,
+    pub vote_events: usize,
+    pub algorithms: Vec<AlgorithmReport>,
+    /// The intrinsic quality of each item, the ground truth the rankings are
+    /// evaluated against.
+    #[serde(skip)]
+    pub quality: BTreeMap<i32, f64>,
+}
+
+/// Simulates users submitting and upvoting items on a fresh in-memory storage
+/// in simulated time, and evaluates how well the registry's algorithms
+/// recover the items' intrinsic quality. Every step, new items are submitted,
+/// users visit pages as they were ranked at the previous step, look at ranks
+/// according to the page's position bias and upvote what they look at
+/// depending on its quality. Then the scheduler's jobs run, and the pages are
+/// ranked again. Items and vote events are passed to `on_log` in the replay
+/// log format. The simulation is deterministic for a given configuration.
+pub async fn simulate(
+    config: &SimulationConfig,
+    registry: &AlgorithmRegistry,
+    retraining: &RetrainingConfig,
+    mut on_log: impl FnMut(&LogEntry) -> Result<(), AppError>,
+) -> Result<SimulationReport, AppError> {
+    if config.step <= 0 || config.evaluation_interval <= 0 {
+        return Err(AppError::Validation(
+            "The step and evaluation interval must be positive".to_string(),
+        ));
+    }
+    let quality_distribution = Beta::new(config.quality_alpha, config.quality_beta)
+        .context("Invalid quality distribution")?;
+    let page_choices = config
+        .populations
+        .iter()
+        .map(|population| {
+            let (pages, weights): (Vec<&String>, Vec<f64>) = population.pages.iter().unzip();
+            let choice = WeightedIndex::new(weights).with_context(|| {
+                format!("Invalid page weights of population {}", population.name)
+            })?;
+            Ok((pages, choice))
+        })
+        .collect::<Result<Vec<_>, AppError>>()?;
+
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let clock = Arc::new(ManualClock::new(config.start_time));
+    let storage = MemoryStorage::with_clock(Arc::clone(&clock) as Arc<dyn Clock>);
+    let end_time = config.start_time + config.duration;
+
+    let mut quality: HashMap<i32, f64> = HashMap::new();
+    let mut votes: HashSet<(String, i32)> = HashSet::new();
+    let mut rankings: BTreeMap<String, Vec<ScoredItem>> = BTreeMap::new();
+    let mut evaluations: BTreeMap<&'static str, Vec<QualityEvaluation>> = BTreeMap::new();
+    let mut evaluators: BTreeMap<&'static str, Evaluator> = BTreeMap::new();
+    let mut submission_times: HashMap<i32, i64> = HashMap::new();
+    let mut report = SimulationReport::default();
+    let mut next_evaluation = config.start_time + config.evaluation_interval;
+    let mut now = config.start_time;
+
+    info!(
+        "Simulating {} hours in steps of {} seconds",
+        config.duration / HOUR_MILLIS,
+        config.step / 1000
+    );
+
+    while now < end_time {
+        let step_start = now;
+        now = (now + config.step).min(end_time);
+        clock.set(now);
+        let mut tx = storage.begin().await?;
+
+        let n_items =
+            Poisson::new(config.items_per_hour * (now - step_start) as f64 / HOUR_MILLIS as f64)
+                .map_or(0, |d| d.sample(&mut rng) as usize);
+        for _ in 0..n_items {
+            let item = Item {
+                item_id: report.items as i32 + 1,
+                parent_id: None,
+                author_id: format!("author_{}", rng.gen_range(0..1000)),
+                created_at: rng.gen_range(step_start + 1..=now),
+            };
+            quality.insert(item.item_id, quality_distribution.sample(&mut rng));
+            submission_times.insert(item.item_id, item.created_at);
+            tx.insert_item(&item).await?;
+            on_log(&LogEntry::Item(item))?;
+            report.items += 1;
+        }
+
+        for (population, (pages, page_choice)) in config.populations.iter().zip(&page_choices) {
+            let expected_sessions =
+                population.size as f64 * population.sessions_per_hour * (now - step_start) as f64
+                    / HOUR_MILLIS as f64;
+            let n_sessions =
+                Poisson::new(expected_sessions).map_or(0, |d| d.sample(&mut rng) as usize);
+            for _ in 0..n_sessions {
+                report.sessions += 1;
+                let user_id = format!("{}_{}", population.name, rng.gen_range(0..population.size));
+                let page_id = pages[page_choice.sample(&mut rng)];
+                let position_bias = config.position_bias(page_id);
+                let session_time = rng.gen_range(step_start + 1..=now);
+                let Some(ranking) = rankings.get(page_id) else {
+                    continue;
+                };
+
+                for item in ranking.iter().take(config.page_size.max(0) as usize) {
+                    if !rng.gen_bool(position_bias.examination_probability(item.rank)) {
+                        continue;
+                    }
+                    report.impressions += 1;
+                    tx.record_impression(
+                        page_id,
+                        item.item_id,
+                        item.rank,
+                        session_time - session_time.rem_euclid(IMPRESSION_BUCKET_MILLIS),
+                    )
+                    .await?;
+
+                    let upvote_probability = population.upvote_propensity
+                        * quality[&item.item_id].powf(population.quality_sensitivity);
+                    if votes.contains(&(user_id.clone(), item.item_id))
+                        || !rng.gen_bool(upvote_probability.clamp(0.0, 1.0))
+                    {
+                        continue;
+                    }
+                    votes.insert((user_id.clone(), item.item_id));
+                    let vote_event = VoteEvent {
+                        vote_event_id: report.vote_events as i32 + 1,
+                        item_id: item.item_id,
+                        user_id: user_id.clone(),
+                        vote: 1,
+                        rank: Some(item.rank),
+                        page: Some(page_id.clone()),
+                        created_at: session_time,
+                    };
+                    tx.insert_vote_event(&vote_event).await?;
+                    on_log(&LogEntry::VoteEvent(vote_event))?;
+                    report.vote_events += 1;
+                }
+            }
+        }
+
+        for algorithm in registry.algorithms() {
+            algorithm.record_sample(&mut *tx, now).await?;
+        }
+        pages::record_page_samples(&mut *tx, registry, now).await?;
+        upvote_share_model::retrain_if_due(&mut *tx, retraining, now).await?;
+
+        rankings.clear();
+        for page in tx.get_pages().await? {
+            match pages::get_page_ranking(&mut *tx, registry, &page, now).await {
+                Ok(ranking) => {
+                    rankings.insert(page.page_id, ranking);
+                }
+                Err(e) => debug!("Page {} can't be ranked yet: {:?}", page.page_id, e),
+            }
+        }
+
+        if now >= next_evaluation || now == end_time {
+            next_evaluation += config.evaluation_interval;
+            for algorithm in registry.algorithms() {
+                if let Ok(ranking) = algorithm.rank(&mut *tx, now).await {
+                    evaluators
+                        .entry(algorithm.name())
+                        .or_insert_with(|| Evaluator::new(config.top))
+                        .record(
+                            now,
+                            &ranking.iter().map(|item| item.item_id).collect_vec(),
+                            Some(&quality),
+                        );
+                    if let Some(evaluation) = evaluate(&ranking, &quality, config.top) {
+                        evaluations
+                            .entry(algorithm.name())
+                            .or_default()
+                            .push(evaluation);
+                    }
+                }
+            }
+        }
+
+        tx.commit().await?;
+    }
+
+    report.algorithms = evaluations
+        .into_iter()
+        .map(|(algorithm, evaluations)| {
+            let n = evaluations.len() as f64;
+            let mean =
+                |f: fn(&QualityEvaluation) -> f64| evaluations.iter().map(f).sum::<f64>() / n;
+            AlgorithmReport {
+                algorithm: algorithm.to_string(),
+                evaluations: evaluations.len(),
+                quality_correlation: mean(|e| e.quality_correlation),
+                top_quality: mean(|e| e.top_quality),
+                ideal_top_quality: mean(|e| e.ideal_top_quality),
+                top_overlap: mean(|e| e.top_overlap),
+                evaluation: evaluators
+                    .get(algorithm)
+                    .map(|evaluator| evaluator.finish(&submission_times))
+                    .unwrap_or_default(),
+            }
+        })
+        .collect();
+    report.quality = quality.into_iter().collect();
+
+    Ok(report)
+}
+
+struct QualityEvaluation {
+    quality_correlation: f64,
+    top_quality: f64,
+    ideal_top_quality: f64,
+    top_overlap: f64,
+}
+
+/// Compares a ranking to the ideal ordering of the same items by quality.
+/// Returns `None` for rankings with fewer than two items.
+fn evaluate(
+    ranking: &[ScoredItem],
+    quality: &HashMap<i32, f64>,
+    top: usize,
+) -> Option<QualityEvaluation> {
+    if ranking.len() < 2 || top == 0 {
+        return None;
+    }
+    let qualities: Vec<f64> = ranking.iter().map(|item| quality[&item.item_id]).collect();
+    let ideal: Vec<f64> = qualities
+        .iter()
+        .copied()
+        .sorted_by(|a, b| b.total_cmp(a))
+        .collect();
+    let top = top.min(ranking.len());
+    let best_items: HashSet<i32> = ranking
+        .iter()
+        .sorted_by(|a, b| quality[&b.item_id].total_cmp(&quality[&a.item_id]))
+        .take(top)
+        .map(|item| item.item_id)
+        .collect();
+
+    Some(QualityEvaluation {
+        // Ranks go down as quality goes up
+        quality_correlation: -evaluation::spearman(
+            &ranking.iter().map(|item| item.rank as f64).collect_vec(),
+            &qualities,
+        ),
+        top_quality: qualities[..top].iter().sum::<f64>() / top as f64,
+        ideal_top_quality: ideal[..top].iter().sum::<f64>() / top as f64,
+        top_overlap: ranking[..top]
+            .iter()
+            .filter(|item| best_items.contains(&item.item_id))
+            .count() as f64
+            / top as f64,
+    })
+}
@@ -0,0 +1,706 @@
+use crate::algs::registry::AlgorithmRegistry;
+use crate::common::{
+    error::AppError,
+    model::{RankingPage, RankingResponse, ScoredItem},
+};
+use crate::live_scores::{LiveScores, RankingSeed};
+use crate::pages;
+use crate::storage::StorageTransaction;
+use anyhow::Context;
+use arc_swap::ArcSwap;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::debug;
+
+/// How often the scheduler checks whether the snapshots are due for a refresh.
+pub const SNAPSHOT_REFRESH_SCHEDULE: &str = "* * * * * *";
+/// Snapshots older than this can no longer be paged through.
+pub const SNAPSHOT_TTL_MILLIS: i64 = 10 * 60 * 1000;
+/// Upper bound on the number of snapshots kept in memory.
+pub const MAX_SNAPSHOTS: usize = 256;
+/// Upper bound on the number of snapshots of rankings with custom parameters
+/// kept in memory, on top of `MAX_SNAPSHOTS`.
+pub const MAX_CONFIGURED_SNAPSHOTS: usize = 64;
+
+pub fn algorithm_ranking(name: &str) -> String {
+    format!("algorithm:{}", name)
+}
+
+/// Key of an algorithm's ranking with parameters other than its defaults.
+pub fn configured_ranking(name: &str, params: &serde_json::Value) -> String {
+    format!("algorithm:{}:{}", name, params)
+}
+
+pub fn page_ranking(page_id: &str) -> String {
+    format!("page:{}", page_id)
+}
+
+/// A ranking as computed at one point in time. Clients paging through a
+/// ranking keep reading from the same snapshot, so items don't repeat or go
+/// missing when scores change between requests.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RankingSnapshot {
+    pub snapshot_id: u64,
+    /// Key of the ranking, e.g. `algorithm:hacker_news` or `page:quality_news`.
+    pub ranking: String,
+    pub computed_at: i64,
+    pub items: Vec<ScoredItem>,
+}
+
+impl RankingSnapshot {
+    pub fn page(&self, offset: usize, limit: Option<usize>) -> RankingPage {
+        let end = limit.map_or(self.items.len(), |limit| {
+            offset.saturating_add(limit).min(self.items.len())
+        });
+        let items = self
+            .items
+            .get(offset..end)
+            .map_or_else(Vec::new, |items| items.to_vec());
+        let next_cursor = (end < self.items.len()).then(|| Cursor::new(self, end).encode());
+
+        RankingPage {
+            snapshot_id: self.snapshot_id,
+            computed_at: self.computed_at,
+            items,
+            next_cursor,
+        }
+    }
+}
+
+/// Position in a ranking snapshot, handed to clients as an opaque string.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cursor {
+    pub snapshot_id: u64,
+    pub offset: usize,
+}
+
+impl Cursor {
+    fn new(snapshot: &RankingSnapshot, offset: usize) -> Self {
+        Self {
+            snapshot_id: snapshot.snapshot_id,
+            offset,
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        format!("{:x}.{:x}", self.snapshot_id, self.offset)
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self, AppError> {
+        let invalid = || AppError::Validation(format!("Invalid cursor: {}", cursor));
+        let (snapshot_id, offset) = cursor.split_once('.').ok_or_else(invalid)?;
+
+        Ok(Self {
+            snapshot_id: u64::from_str_radix(snapshot_id, 16).map_err(|_| invalid())?,
+            offset: usize::from_str_radix(offset, 16).map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// Query parameters for paging through a ranking. Without a cursor, paging
+/// starts at `offset` of the latest snapshot.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Pagination {
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    pub cursor: Option<String>,
+}
+
+impl Pagination {
+    /// Wraps a page of a ranking in the response shape the request asked for.
+    pub fn response(&self, page: RankingPage) -> RankingResponse {
+        if self.limit.is_some() || self.cursor.is_some() {
+            RankingResponse::Page(page)
+        } else {
+            RankingResponse::Items(page.items)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SnapshotConfig {
+    /// Snapshots are recomputed at least this often, since scores decay with
+    /// time even without new votes.
+    pub max_age: i64,
+    /// Whether the latest snapshots are stored in the database, so they can be
+    /// served right after a restart.
+    pub persist: bool,
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        Self {
+            max_age: 10 * 1000,
+            persist: false,
+        }
+    }
+}
+
+impl SnapshotConfig {
+    /// Reads `RANKING_SNAPSHOT_MAX_AGE_SECONDS` and `RANKING_SNAPSHOT_PERSIST`,
+    /// falling back to the defaults for unset variables.
+    pub fn from_env() -> Result<Self, AppError> {
+        let default = Self::default();
+        let max_age = match env::var("RANKING_SNAPSHOT_MAX_AGE_SECONDS") {
+            Ok(seconds) => {
+                seconds
+                    .trim()
+                    .parse::<i64>()
+                    .with_context(|| format!("Invalid snapshot max age: {}", seconds))?
+                    * 1000
+            }
+            Err(_) => default.max_age,
+        };
+        let persist = match env::var("RANKING_SNAPSHOT_PERSIST") {
+            Ok(flag) => matches!(flag.trim(), "1" | "true"),
+            Err(_) => default.persist,
+        };
+
+        Ok(Self { max_age, persist })
+    }
+}
+
+/// Materialized rankings. The latest snapshot of each ranking is served
+/// without locking or touching the database; recent snapshots are kept so
+/// cursors into them stay valid.
+pub struct SnapshotStore {
+    config: SnapshotConfig,
+    next_id: AtomicU64,
+    latest: ArcSwap<HashMap<String, Arc<RankingSnapshot>>>,
+    retained: Mutex<VecDeque<Arc<RankingSnapshot>>>,
+    /// Snapshots of rankings with custom parameters, least recently used
+    /// first. They're kept apart so that requests for arbitrary parameters
+    /// can't evict the snapshots of the other rankings.
+    configured: Mutex<VecDeque<Arc<RankingSnapshot>>>,
+    last_refresh: AtomicI64,
+}
+
+impl SnapshotStore {
+    /// Snapshot ids start at the given time, so cursors handed out before a
+    /// restart don't resolve to unrelated snapshots.
+    pub fn new(now: i64, config: SnapshotConfig) -> Self {
+        Self {
+            config,
+            next_id: AtomicU64::new(now.max(0) as u64),
+            latest: ArcSwap::from_pointee(HashMap::new()),
+            retained: Mutex::new(VecDeque::new()),
+            configured: Mutex::new(VecDeque::new()),
+            last_refresh: AtomicI64::new(i64::MIN),
+        }
+    }
+
+    pub fn config(&self) -> &SnapshotConfig {
+        &self.config
+    }
+
+    pub fn latest(&self, ranking: &str) -> Option<Arc<RankingSnapshot>> {
+        self.latest.load().get(ranking).cloned()
+    }
+
+    fn snapshot(
+        &self,
+        ranking: &str,
+        computed_at: i64,
+        items: Vec<ScoredItem>,
+    ) -> Arc<RankingSnapshot> {
+        Arc::new(RankingSnapshot {
+            snapshot_id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            ranking: ranking.to_string(),
+            computed_at,
+            items,
+        })
+    }
+
+    fn create(
+        &self,
+        ranking: &str,
+        computed_at: i64,
+        items: Vec<ScoredItem>,
+    ) -> Arc<RankingSnapshot> {
+        let snapshot = self.snapshot(ranking, computed_at, items);
+        self.retain(Arc::clone(&snapshot));
+
+        snapshot
+    }
+
+    fn retain(&self, snapshot: Arc<RankingSnapshot>) {
+        let mut retained = self.retained.lock().unwrap();
+        retained.retain(|s| snapshot.computed_at - s.computed_at < SNAPSHOT_TTL_MILLIS);
+        while retained.len() >= MAX_SNAPSHOTS {
+            retained.pop_front();
+        }
+        retained.push_back(snapshot);
+    }
+
+    /// Makes a ranking computed on demand the latest snapshot of that ranking.
+    pub fn publish(
+        &self,
+        ranking: &str,
+        computed_at: i64,
+        items: Vec<ScoredItem>,
+    ) -> Arc<RankingSnapshot> {
+        let snapshot = self.create(ranking, computed_at, items);
+        self.latest.rcu(|latest| {
+            let mut latest = HashMap::clone(latest);
+            latest.insert(ranking.to_string(), Arc::clone(&snapshot));
+            latest
+        });
+
+        snapshot
+    }
+
+    /// The snapshot of a ranking with custom parameters computed since the
+    /// last refresh, if it's still cached.
+    pub fn latest_configured(&self, ranking: &str) -> Option<Arc<RankingSnapshot>> {
+        let last_refresh = self.last_refresh.load(Ordering::Relaxed);
+        let mut configured = self.configured.lock().unwrap();
+        let position = configured
+            .iter()
+            .rposition(|s| s.ranking == ranking && s.computed_at >= last_refresh)?;
+        let snapshot = configured.remove(position)?;
+        configured.push_back(Arc::clone(&snapshot));
+
+        Some(snapshot)
+    }
+
+    /// Caches a ranking with custom parameters computed on demand until the
+    /// next refresh. Cursors into it stay valid until it expires or is the
+    /// least recently used of `MAX_CONFIGURED_SNAPSHOTS` such snapshots.
+    pub fn publish_configured(
+        &self,
+        ranking: &str,
+        computed_at: i64,
+        items: Vec<ScoredItem>,
+    ) -> Arc<RankingSnapshot> {
+        let snapshot = self.snapshot(ranking, computed_at, items);
+        let mut configured = self.configured.lock().unwrap();
+        configured.retain(|s| computed_at - s.computed_at < SNAPSHOT_TTL_MILLIS);
+        while configured.len() >= MAX_CONFIGURED_SNAPSHOTS {
+            configured.pop_front();
+        }
+        configured.push_back(Arc::clone(&snapshot));
+
+        snapshot
+    }
+
+    /// Makes rankings maintained incrementally since the last refresh the
+    /// latest snapshots.
+    pub fn publish_many(
+        &self,
+        computed_at: i64,
+        rankings: Vec<(String, Vec<ScoredItem>)>,
+    ) -> Vec<Arc<RankingSnapshot>> {
+        let snapshots: Vec<Arc<RankingSnapshot>> = rankings
+            .into_iter()
+            .map(|(ranking, items)| self.create(&ranking, computed_at, items))
+            .collect();
+        self.latest.rcu(|latest| {
+            let mut latest = HashMap::clone(latest);
+            latest.extend(snapshots.iter().map(|s| (s.ranking.clone(), Arc::clone(s))));
+            latest
+        });
+
+        snapshots
+    }
+
+    /// Replaces all latest snapshots at once. Rankings missing from
+    /// `rankings` are no longer served from a snapshot.
+    pub fn publish_all(
+        &self,
+        computed_at: i64,
+        rankings: Vec<(String, Vec<ScoredItem>)>,
+    ) -> Vec<Arc<RankingSnapshot>> {
+        let snapshots: Vec<Arc<RankingSnapshot>> = rankings
+            .into_iter()
+            .map(|(ranking, items)| self.create(&ranking, computed_at, items))
+            .collect();
+        self.latest.store(Arc::new(
+            snapshots
+                .iter()
+                .map(|s| (s.ranking.clone(), Arc::clone(s)))
+                .collect(),
+        ));
+        self.last_refresh.store(computed_at, Ordering::Relaxed);
+
+        snapshots
+    }
+
+    /// Serves snapshots persisted before a restart until the first refresh.
+    pub fn restore(&self, snapshots: Vec<RankingSnapshot>) {
+        let snapshots: Vec<Arc<RankingSnapshot>> = snapshots.into_iter().map(Arc::new).collect();
+        for snapshot in &snapshots {
+            self.retain(Arc::clone(snapshot));
+        }
+        self.latest.store(Arc::new(
+            snapshots
+                .into_iter()
+                .map(|s| (s.ranking.clone(), s))
+                .collect(),
+        ));
+    }
+
+    pub fn remove(&self, ranking: &str) {
+        self.latest.rcu(|latest| {
+            let mut latest = HashMap::clone(latest);
+            latest.remove(ranking);
+            latest
+        });
+    }
+
+    /// Whether the snapshots have reached their maximum age and should be
+    /// recomputed from the database.
+    pub fn refresh_due(&self, now: i64) -> bool {
+        now.saturating_sub(self.last_refresh.load(Ordering::Relaxed)) >= self.config.max_age
+    }
+
+    pub fn get(&self, snapshot_id: u64) -> Option<Arc<RankingSnapshot>> {
+        let find = |snapshots: &Mutex<VecDeque<Arc<RankingSnapshot>>>| {
+            snapshots
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|s| s.snapshot_id == snapshot_id)
+                .cloned()
+        };
+
+        find(&self.retained).or_else(|| find(&self.configured))
+    }
+
+    /// Continues paging from the cursor, if one was given. The cursor must
+    /// have been handed out for the same ranking.
+    pub fn resume(
+        &self,
+        ranking: &str,
+        pagination: &Pagination,
+    ) -> Result<Option<RankingPage>, AppError> {
+        let Some(cursor) = &pagination.cursor else {
+            return Ok(None);
+        };
+        if pagination.offset.is_some() {
+            return Err(AppError::Validation(
+                "offset can't be combined with a cursor".to_string(),
+            ));
+        }
+
+        let cursor = Cursor::decode(cursor)?;
+        let snapshot = self
+            .get(cursor.snapshot_id)
+            .ok_or(AppError::CursorExpired)?;
+        if snapshot.ranking != ranking {
+            return Err(AppError::Validation(format!(
+                "Cursor does not belong to ranking {}",
+                ranking
+            )));
+        }
+
+        Ok(Some(snapshot.page(cursor.offset, pagination.limit)))
+    }
+}
+
+/// Recomputes the rankings of all algorithms with their default parameters
+/// and of all pages, publishes them as the latest snapshots and reseeds the
+/// live scores with them. Rankings that can't be computed yet are skipped.
+pub async fn refresh_snapshots(
+    tx: &mut dyn StorageTransaction,
+    registry: &AlgorithmRegistry,
+    store: &SnapshotStore,
+    live_scores: &LiveScores,
+    now: i64,
+) -> Result<Vec<Arc<RankingSnapshot>>, AppError> {
+    let mut seeds = Vec::new();
+
+    // Each ranking runs in a savepoint, so that one failing doesn't abort the
+    // transaction for the others
+    for algorithm in registry.algorithms() {
+        tx.savepoint().await?;
+        match algorithm.rank(tx, now).await {
+            Ok(items) => {
+                tx.release_savepoint().await?;
+                seeds.push(RankingSeed {
+                    ranking: algorithm_ranking(algorithm.name()),
+                    page: algorithm.name().to_string(),
+                    algorithm: Arc::clone(algorithm),
+                    items,
+                });
+            }
+            Err(e) => {
+                debug!("Skipping snapshot of {}: {:?}", algorithm.name(), e);
+                tx.rollback_to_savepoint().await?;
+            }
+        }
+    }
+
+    for page in tx.get_pages().await? {
+        tx.savepoint().await?;
+        let ranking = match registry.configure(&page.algorithm, &page.params) {
+            Ok(algorithm) => pages::get_page_ranking(tx, registry, &page, now)
+                .await
+                .map(|items| (algorithm, items)),
+            Err(e) => Err(e),
+        };
+        match ranking {
+            Ok((algorithm, items)) => {
+                tx.release_savepoint().await?;
+                seeds.push(RankingSeed {
+                    ranking: page_ranking(&page.page_id),
+                    page: page.page_id,
+                    algorithm,
+                    items,
+                });
+            }
+            Err(e) => {
+                debug!("Skipping snapshot of page {}: {:?}", page.page_id, e);
+                tx.rollback_to_savepoint().await?;
+            }
+        }
+    }
+
+    let item_ids: Vec<i32> = seeds
+        .iter()
+        .flat_map(|seed| seed.items.iter().map(|item| item.item_id))
+        .unique()
+        .collect();
+    let aggregates = tx.get_item_aggregates(&item_ids).await?;
+
+    let snapshots = store.publish_all(
+        now,
+        seeds
+            .iter()
+            .map(|seed| (seed.ranking.clone(), seed.items.clone()))
+            .collect(),
+    );
+    live_scores.reseed(aggregates, seeds, now);
+
+    Ok(snapshots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algs::{newest::Newest, registry::RankingAlgorithm};
+    use crate::common::time::{Clock, ManualClock};
+    use crate::storage::memory::{
+        fixtures::{self, item},
+        MemoryStorage,
+    };
+    use crate::storage::Storage;
+
+    const MINUTE: i64 = 60 * 1000;
+
+    struct Service {
+        clock: Arc<ManualClock>,
+        storage: MemoryStorage,
+        registry: AlgorithmRegistry,
+        store: SnapshotStore,
+        live_scores: LiveScores,
+    }
+
+    impl Service {
+        fn new() -> Self {
+            let clock = Arc::new(ManualClock::new(1_700_000_000_000));
+            let mut registry = AlgorithmRegistry::new();
+            registry.register(Newest::default());
+
+            Self {
+                storage: MemoryStorage::with_clock(clock.clone()),
+                store: SnapshotStore::new(clock.now_millis(), SnapshotConfig::default()),
+                clock,
+                registry,
+                live_scores: LiveScores::new(),
+            }
+        }
+
+        async fn insert_item(&self, item_id: i32) {
+            let item = item(item_id, self.clock.now_millis());
+            fixtures::insert(&self.storage, &[item], &[]).await;
+        }
+
+        async fn refresh(&self) {
+            let mut tx = self.storage.begin().await.unwrap();
+            refresh_snapshots(
+                &mut *tx,
+                &self.registry,
+                &self.store,
+                &self.live_scores,
+                self.clock.now_millis(),
+            )
+            .await
+            .unwrap();
+            tx.commit().await.unwrap();
+        }
+
+        fn page(&self, cursor: Option<String>) -> Result<RankingPage, AppError> {
+            let ranking = algorithm_ranking("newest");
+            let pagination = Pagination {
+                limit: Some(2),
+                offset: None,
+                cursor,
+            };
+            match self.store.resume(&ranking, &pagination)? {
+                Some(page) => Ok(page),
+                None => Ok(self
+                    .store
+                    .latest(&ranking)
+                    .unwrap()
+                    .page(0, pagination.limit)),
+            }
+        }
+    }
+
+    /// Algorithm that writes an item before failing to rank.
+    struct Broken;
+
+    #[async_trait::async_trait]
+    impl RankingAlgorithm for Broken {
+        fn name(&self) -> &'static str {
+            "broken"
+        }
+
+        fn params(&self) -> serde_json::Value {
+            serde_json::Value::Null
+        }
+
+        fn configure(
+            &self,
+            _params: &serde_json::Value,
+        ) -> Result<Arc<dyn RankingAlgorithm>, AppError> {
+            Ok(Arc::new(Broken))
+        }
+
+        async fn rank(
+            &self,
+            tx: &mut dyn StorageTransaction,
+            now: i64,
+        ) -> Result<Vec<ScoredItem>, AppError> {
+            tx.insert_item(&item(99, now)).await?;
+            Err(anyhow::anyhow!("broken").into())
+        }
+    }
+
+    fn item_ids(page: &RankingPage) -> Vec<i32> {
+        page.items.iter().map(|item| item.item_id).collect()
+    }
+
+    #[tokio::test]
+    async fn pagination_is_stable_across_refreshes() {
+        let service = Service::new();
+        for item_id in 1..=5 {
+            service.insert_item(item_id).await;
+            service.clock.advance(MINUTE);
+        }
+        service.refresh().await;
+
+        let first = service.page(None).unwrap();
+        assert_eq!(item_ids(&first), vec![5, 4]);
+
+        // New items and a refresh don't shift the pages of the first snapshot
+        service.insert_item(6).await;
+        service.clock.advance(MINUTE);
+        service.refresh().await;
+        assert_eq!(item_ids(&service.page(None).unwrap()), vec![6, 5]);
+
+        let second = service.page(first.next_cursor.clone()).unwrap();
+        assert_eq!(second.snapshot_id, first.snapshot_id);
+        assert_eq!(item_ids(&second), vec![3, 2]);
+        let third = service.page(second.next_cursor.clone()).unwrap();
+        assert_eq!(item_ids(&third), vec![1]);
+        assert_eq!(third.next_cursor, None);
+
+        // Until the snapshot expires
+        service.clock.advance(SNAPSHOT_TTL_MILLIS);
+        service.refresh().await;
+        assert!(matches!(
+            service.page(first.next_cursor),
+            Err(AppError::CursorExpired)
+        ));
+    }
+
+    #[test]
+    fn configured_rankings_are_evicted_least_recently_used_first() {
+        let store = SnapshotStore::new(0, SnapshotConfig::default());
+        let snapshot = store.publish(&algorithm_ranking("top"), 0, Vec::new());
+        let ranking = |days: usize| {
+            configured_ranking("top", &serde_json::json!({"window": format!("{}d", days)}))
+        };
+        store.publish_configured(&ranking(0), 0, Vec::new());
+        store.publish_configured(&ranking(1), 0, Vec::new());
+        for days in 2..MAX_SNAPSHOTS + MAX_CONFIGURED_SNAPSHOTS {
+            // Keeps the first ranking in use
+            assert!(store.latest_configured(&ranking(0)).is_some());
+            store.publish_configured(&ranking(days), 0, Vec::new());
+        }
+
+        assert!(store.get(snapshot.snapshot_id).is_some());
+        assert!(store.latest(&ranking(0)).is_none());
+        assert!(store.latest_configured(&ranking(0)).is_some());
+        assert!(store.latest_configured(&ranking(1)).is_none());
+    }
+
+    #[test]
+    fn configured_rankings_are_recomputed_after_a_refresh() {
+        let store = SnapshotStore::new(0, SnapshotConfig::default());
+        let ranking = configured_ranking("best", &serde_json::json!({"window": "7d"}));
+        let snapshot = store.publish_configured(&ranking, 0, Vec::new());
+        store.publish_all(1, Vec::new());
+        let pagination = Pagination {
+            cursor: Some(Cursor::new(&snapshot, 0).encode()),
+            ..Pagination::default()
+        };
+
+        assert!(store.latest_configured(&ranking).is_none());
+        assert!(store.resume(&ranking, &pagination).unwrap().is_some());
+    }
+
+    #[test]
+    fn cursors_belong_to_their_ranking() {
+        let store = SnapshotStore::new(0, SnapshotConfig::default());
+        let snapshot = store.publish(&algorithm_ranking("newest"), 0, Vec::new());
+        let pagination = Pagination {
+            cursor: Some(Cursor::new(&snapshot, 0).encode()),
+            ..Pagination::default()
+        };
+
+        assert!(matches!(
+            store.resume(&algorithm_ranking("hacker_news"), &pagination),
+            Err(AppError::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn only_paged_requests_get_the_page_shape() {
+        let store = SnapshotStore::new(0, SnapshotConfig::default());
+        let snapshot = store.publish(&algorithm_ranking("newest"), 0, Vec::new());
+        let response = |pagination: Pagination| {
+            let page = snapshot.page(pagination.offset.unwrap_or(0), pagination.limit);
+            serde_json::to_value(pagination.response(page)).unwrap()
+        };
+
+        assert!(response(Pagination::default()).is_array());
+        let offset = Pagination {
+            offset: Some(1),
+            ..Pagination::default()
+        };
+        assert!(response(offset).is_array());
+        let limit = Pagination {
+            limit: Some(1),
+            ..Pagination::default()
+        };
+        assert!(response(limit)["items"].is_array());
+    }
+
+    #[tokio::test]
+    async fn failed_rankings_are_rolled_back_and_skipped() {
+        let mut service = Service::new();
+        service.registry.register(Broken);
+        service.insert_item(1).await;
+        service.refresh().await;
+
+        assert!(service.store.latest(&algorithm_ranking("broken")).is_none());
+        assert_eq!(item_ids(&service.page(None).unwrap()), vec![1]);
+    }
+}
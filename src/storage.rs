@@ -0,0 +1,377 @@
+use crate::algs::{
+    best::BestStats,
+    hacker_news::HnStats,
+    newest::NewestStats,
+    quality_news::{
+        model::{
+            ImpressionExpectedUpvotes, ItemWithRanks, QnSample, QnSampleInterval,
+            QnSampleWithPrediction, QnStats, RankProfileEntry, VoteEventRank,
+        },
+        ExpectedUpvotes,
+    },
+    reddit_hot::HotStats,
+    top::TopStats,
+};
+use crate::common::{
+    error::AppError,
+    model::{
+        Item, ItemAggregate, Page, PageDefinition, PageRank, RankUpvoteShare, ScoredItem, VoteEvent,
+    },
+};
+use crate::experiments::{Experiment, PageActivity};
+use crate::interleaving::{InterleavedPosition, Interleaving, InterleavingCredit};
+use crate::snapshots::RankingSnapshot;
+use crate::upvote_share_model::{
+    PageCoefficients, RankObservation, UpvoteShareModel, UpvoteShareModelVersion,
+};
+use async_trait::async_trait;
+use std::sync::Arc;
+
+pub mod memory;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+/// Connects to the database at `database_url` and applies pending migrations.
+/// `postgres://` and `postgresql://` URLs use the PostgreSQL backend, all
+/// others SQLite.
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+pub async fn connect(database_url: &str) -> Result<Arc<dyn Storage>, AppError> {
+    if database_url.starts_with("postgres://") || database_url.starts_with("postgresql://") {
+        #[cfg(feature = "postgres")]
+        {
+            let storage = postgres::PostgresStorage::connect(database_url).await?;
+            storage.migrate().await?;
+            return Ok(Arc::new(storage));
+        }
+        #[cfg(not(feature = "postgres"))]
+        return Err(anyhow::anyhow!("Built without the postgres feature: {}", database_url).into());
+    }
+
+    #[cfg(feature = "sqlite")]
+    {
+        let storage = sqlite::SqliteStorage::connect(database_url).await?;
+        storage.migrate().await?;
+        Ok(Arc::new(storage))
+    }
+    #[cfg(not(feature = "sqlite"))]
+    Err(anyhow::anyhow!("Built without the sqlite feature: {}", database_url).into())
+}
+
+/// A store for items, votes and everything the ranking algorithms derive from
+/// them. All reads and writes go through a transaction.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn begin(&self) -> Result<Box<dyn StorageTransaction>, AppError>;
+}
+
+/// The data operations of the service within a single transaction. Changes
+/// are discarded unless the transaction is committed.
+#[async_trait]
+pub trait StorageTransaction: Send {
+    async fn commit(self: Box<Self>) -> Result<(), AppError>;
+
+    async fn rollback(self: Box<Self>) -> Result<(), AppError>;
+
+    /// Starts a savepoint, so that a failed step can be undone without
+    /// aborting the whole transaction, as PostgreSQL does after any failed
+    /// statement. Savepoints nest.
+    async fn savepoint(&mut self) -> Result<(), AppError>;
+
+    /// Keeps the changes since the latest savepoint and ends it.
+    async fn release_savepoint(&mut self) -> Result<(), AppError>;
+
+    /// Discards the changes since the latest savepoint and ends it.
+    async fn rollback_to_savepoint(&mut self) -> Result<(), AppError>;
+
+    // Items and votes
+
+    async fn insert_item(&mut self, item: &Item) -> Result<(), AppError>;
+
+    async fn insert_vote_event(&mut self, vote_event: &VoteEvent) -> Result<(), AppError>;
+
+    /// The user's current vote on the item, if any.
+    async fn get_current_vote(
+        &mut self,
+        user_id: &str,
+        item_id: i32,
+    ) -> Result<Option<i32>, AppError>;
+
+    /// Counts an impression of an item at a rank on a page in the bucket
+    /// starting at `bucket_time`.
+    async fn record_impression(
+        &mut self,
+        page_id: &str,
+        item_id: i32,
+        rank: i32,
+        bucket_time: i64,
+    ) -> Result<(), AppError>;
+
+    /// The `pool_size` most recently submitted items.
+    async fn get_newest_stats(
+        &mut self,
+        pool_size: i32,
+        sample_time: i64,
+    ) -> Result<Vec<NewestStats>, AppError>;
+
+    /// The `pool_size` most recently submitted items with their upvotes.
+    async fn get_hn_stats(
+        &mut self,
+        pool_size: i32,
+        sample_time: i64,
+    ) -> Result<Vec<HnStats>, AppError>;
+
+    /// The `pool_size` most recently submitted items with their upvotes and
+    /// downvotes.
+    async fn get_hot_stats(&mut self, pool_size: i32) -> Result<Vec<HotStats>, AppError>;
+
+    /// Upvotes and downvotes of the items with votes whose latest change was
+    /// after `from` until `until`.
+    async fn get_best_stats(&mut self, from: i64, until: i64) -> Result<Vec<BestStats>, AppError>;
+
+    /// Upvotes of the `pool_size` most upvoted items, counting each user's
+    /// latest vote in the window from after `from` until `until`, with the
+    /// expected upvotes of the quality news samples taken in that window.
+    async fn get_top_stats(
+        &mut self,
+        from: i64,
+        until: i64,
+        pool_size: i32,
+    ) -> Result<Vec<TopStats>, AppError>;
+
+    /// Aggregates of the given items as of now.
+    async fn get_item_aggregates(
+        &mut self,
+        item_ids: &[i32],
+    ) -> Result<Vec<ItemAggregate>, AppError>;
+
+    /// All items in the order they were submitted.
+    async fn get_items(&mut self) -> Result<Vec<Item>, AppError>;
+
+    /// The item and all replies below it, in the order they were submitted.
+    /// Empty if the item doesn't exist.
+    async fn get_thread(&mut self, item_id: i32) -> Result<Vec<Item>, AppError>;
+
+    /// All vote events in the order they were cast.
+    async fn get_vote_events(&mut self) -> Result<Vec<VoteEvent>, AppError>;
+
+    // Pages
+
+    async fn get_pages(&mut self) -> Result<Vec<Page>, AppError>;
+
+    async fn get_page(&mut self, page_id: &str) -> Result<Option<Page>, AppError>;
+
+    async fn insert_page(&mut self, page: &PageDefinition) -> Result<Page, AppError>;
+
+    /// Deletes a page with its rank history. Returns the number of deleted
+    /// pages.
+    async fn delete_page(&mut self, page_id: &str) -> Result<u64, AppError>;
+
+    /// Attributes the upvotes cast on a page since its latest rank observation
+    /// to the observed items.
+    async fn close_rank_window(&mut self, page_id: &str, sample_time: i64) -> Result<(), AppError>;
+
+    async fn insert_page_ranks(
+        &mut self,
+        page_id: &str,
+        sample_time: i64,
+        ranking: &[ScoredItem],
+    ) -> Result<(), AppError>;
+
+    async fn get_upvote_shares_by_rank(
+        &mut self,
+        page_id: &str,
+    ) -> Result<Vec<RankUpvoteShare>, AppError>;
+
+    /// The page's ranks sampled from `from` until `until`, by sample time and
+    /// rank.
+    async fn get_page_rank_history(
+        &mut self,
+        page_id: &str,
+        from: i64,
+        until: i64,
+    ) -> Result<Vec<PageRank>, AppError>;
+
+    // Quality news sampling
+
+    async fn has_items(&mut self) -> Result<bool, AppError>;
+
+    async fn is_sampling_initialized(&mut self) -> Result<bool, AppError>;
+
+    async fn insert_sample_interval(
+        &mut self,
+        start_time: i64,
+    ) -> Result<QnSampleInterval, AppError>;
+
+    async fn get_latest_sample_interval(&mut self) -> Result<QnSampleInterval, AppError>;
+
+    async fn get_sitewide_upvotes_in_interval(
+        &mut self,
+        sample_time: i64,
+        previous_sample_time: i64,
+    ) -> Result<i32, AppError>;
+
+    async fn insert_rank_observations(
+        &mut self,
+        ranked_items: &[ItemWithRanks],
+        sample_interval: &QnSampleInterval,
+        sample_time: i64,
+    ) -> Result<(), AppError>;
+
+    /// Aggregates the rank observations of an interval into the time each item
+    /// spent at each combination of ranks.
+    async fn get_rank_profiles(
+        &mut self,
+        interval: &QnSampleInterval,
+        end_time: i64,
+    ) -> Result<Vec<RankProfileEntry>, AppError>;
+
+    async fn insert_rank_profiles(&mut self, profiles: &[RankProfileEntry])
+        -> Result<(), AppError>;
+
+    /// Cumulative upvotes and expected upvotes of the items in the quality news
+    /// pool at `sample_time`.
+    async fn get_qn_stats(
+        &mut self,
+        sample_time: i64,
+        expected_upvotes: ExpectedUpvotes,
+    ) -> Result<Vec<QnStats>, AppError>;
+
+    async fn insert_sample(&mut self, sample: &QnSampleWithPrediction) -> Result<(), AppError>;
+
+    /// Items shown up to `max_rank` on the page of each upvote in the
+    /// interval, as of the page's latest rank sample before the vote. The
+    /// upvoted item is at the rank recorded with the vote.
+    async fn get_vote_event_ranks_in_interval(
+        &mut self,
+        interval: &QnSampleInterval,
+        sample_time: i64,
+        max_rank: i32,
+    ) -> Result<Vec<VoteEventRank>, AppError>;
+
+    async fn get_impression_expected_upvotes_in_interval(
+        &mut self,
+        interval: &QnSampleInterval,
+        sample_time: i64,
+        lookback_start: i64,
+    ) -> Result<Vec<ImpressionExpectedUpvotes>, AppError>;
+
+    async fn get_sample_in_interval(
+        &mut self,
+        interval: &QnSampleInterval,
+        sample_time: i64,
+    ) -> Result<Vec<QnSample>, AppError>;
+
+    // Upvote share models
+
+    async fn get_rank_observations(
+        &mut self,
+        training_start: i64,
+        training_end: i64,
+    ) -> Result<Vec<RankObservation>, AppError>;
+
+    async fn insert_model(
+        &mut self,
+        created_at: i64,
+        training_start: i64,
+        training_end: i64,
+        coefficients: &[PageCoefficients],
+    ) -> Result<UpvoteShareModel, AppError>;
+
+    async fn get_model_versions(&mut self) -> Result<Vec<UpvoteShareModelVersion>, AppError>;
+
+    async fn get_model(&mut self, model_version: i64)
+        -> Result<Option<UpvoteShareModel>, AppError>;
+
+    async fn get_active_model(&mut self) -> Result<Option<UpvoteShareModel>, AppError>;
+
+    /// Makes the model the active one. A pinned model stays active when the
+    /// model is retrained on schedule.
+    async fn set_active_model(
+        &mut self,
+        model_version: i64,
+        activated_at: i64,
+        pinned: bool,
+    ) -> Result<(), AppError>;
+
+    async fn is_active_model_pinned(&mut self) -> Result<bool, AppError>;
+
+    /// Number of trained models and the training time of the latest one.
+    async fn get_training_history(&mut self) -> Result<(i64, Option<i64>), AppError>;
+
+    // Ranking snapshots
+
+    /// Stores each snapshot as the latest one of its ranking.
+    async fn upsert_snapshots(
+        &mut self,
+        snapshots: &[Arc<RankingSnapshot>],
+    ) -> Result<(), AppError>;
+
+    async fn get_snapshots(&mut self) -> Result<Vec<RankingSnapshot>, AppError>;
+
+    // Experiments
+
+    async fn insert_experiment(&mut self, experiment: &Experiment) -> Result<(), AppError>;
+
+    async fn get_experiments(&mut self) -> Result<Vec<Experiment>, AppError>;
+
+    async fn get_experiment(&mut self, experiment_id: &str)
+        -> Result<Option<Experiment>, AppError>;
+
+    async fn get_running_experiment(&mut self) -> Result<Option<Experiment>, AppError>;
+
+    /// Ends the experiment if it's running. Returns the number of ended
+    /// experiments.
+    async fn end_experiment(&mut self, experiment_id: &str, ended_at: i64)
+        -> Result<u64, AppError>;
+
+    /// Impressions reported for the page in buckets from `from` until `until`
+    /// and upvotes cast on it after `from` until `until`.
+    async fn get_page_activity(
+        &mut self,
+        page_id: &str,
+        from: i64,
+        until: i64,
+    ) -> Result<PageActivity, AppError>;
+
+    // Interleavings
+
+    async fn insert_interleaving(&mut self, interleaving: &Interleaving) -> Result<(), AppError>;
+
+    async fn get_interleavings(&mut self) -> Result<Vec<Interleaving>, AppError>;
+
+    async fn get_interleaving(
+        &mut self,
+        interleaving_id: &str,
+    ) -> Result<Option<Interleaving>, AppError>;
+
+    /// Records a list served to the user. Positions already recorded are kept.
+    async fn insert_interleaved_positions(
+        &mut self,
+        interleaving_id: &str,
+        user_id: &str,
+        positions: &[InterleavedPosition],
+    ) -> Result<(), AppError>;
+
+    /// The rank of the latest list served to the user until `at`, if that list
+    /// has the rank.
+    async fn get_interleaved_position(
+        &mut self,
+        interleaving_id: &str,
+        user_id: &str,
+        rank: i32,
+        at: i64,
+    ) -> Result<Option<InterleavedPosition>, AppError>;
+
+    async fn insert_interleaving_credit(
+        &mut self,
+        interleaving_id: &str,
+        credit: &InterleavingCredit,
+    ) -> Result<(), AppError>;
+
+    async fn get_interleaving_credits(
+        &mut self,
+        interleaving_id: &str,
+    ) -> Result<Vec<InterleavingCredit>, AppError>;
+}
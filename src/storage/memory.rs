@@ -0,0 +1,1664 @@
+use crate::algs::{
+    best::BestStats,
+    hacker_news::HnStats,
+    newest::NewestStats,
+    quality_news::{
+        model::{
+            ImpressionExpectedUpvotes, ItemWithRanks, QnSample, QnSampleInterval,
+            QnSampleWithPrediction, QnStats, RankProfileEntry, VoteEventRank,
+        },
+        ExpectedUpvotes, POOL_SIZE as QN_POOL_SIZE,
+    },
+    reddit_hot::HotStats,
+    registry::AlgorithmRegistry,
+    top::TopStats,
+};
+use crate::common::{
+    error::AppError,
+    model::{
+        Item, ItemAggregate, Page, PageDefinition, PageRank, RankUpvoteShare, ScoredItem,
+        VoteEvent, IMPRESSION_BUCKET_MILLIS,
+    },
+    time::{Clock, SystemClock},
+};
+use crate::experiments::{Experiment, PageActivity};
+use crate::interleaving::{InterleavedPosition, Interleaving, InterleavingCredit};
+use crate::snapshots::RankingSnapshot;
+use crate::storage::{Storage, StorageTransaction};
+use crate::upvote_share_model::{
+    PageCoefficients, RankObservation, UpvoteShareModel, UpvoteShareModelVersion,
+};
+use async_trait::async_trait;
+use itertools::Itertools;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Bound;
+use std::sync::Arc;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+/// Storage that keeps everything in memory, for tests, simulations and
+/// embedding the ranking pipeline without a database. Transactions run one at
+/// a time and are rolled back unless committed.
+#[derive(Clone)]
+pub struct MemoryStorage {
+    state: Arc<Mutex<MemoryState>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for MemoryStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryStorage {
+    /// Empty storage with the pages of the built-in algorithms, like a freshly
+    /// migrated database.
+    pub fn new() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
+
+    /// Like `new`, but timestamps the rows the database would timestamp
+    /// itself, such as page creation times, with the given clock.
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        let mut state = MemoryState::default();
+        let created_at = clock.now_millis();
+        for algorithm in AlgorithmRegistry::with_defaults().algorithms() {
+            state.pages.insert(
+                algorithm.name().to_string(),
+                Page {
+                    page_id: algorithm.name().to_string(),
+                    algorithm: algorithm.name().to_string(),
+                    params: serde_json::Value::Object(Default::default()),
+                    created_at,
+                },
+            );
+        }
+
+        Self {
+            state: Arc::new(Mutex::new(state)),
+            clock,
+        }
+    }
+}
+
+/// Fixtures for tests that run on the in-memory storage.
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use super::MemoryStorage;
+    use crate::common::model::{Item, VoteEvent};
+    use crate::storage::Storage;
+
+    /// Top-level item submitted at `created_at`.
+    pub fn item(item_id: i32, created_at: i64) -> Item {
+        Item {
+            item_id,
+            parent_id: None,
+            author_id: "author".to_string(),
+            created_at,
+        }
+    }
+
+    /// Vote that wasn't cast on a ranked page.
+    pub fn vote_event(
+        vote_event_id: i32,
+        item_id: i32,
+        user_id: &str,
+        vote: i32,
+        created_at: i64,
+    ) -> VoteEvent {
+        VoteEvent {
+            vote_event_id,
+            item_id,
+            user_id: user_id.to_string(),
+            vote,
+            rank: None,
+            page: None,
+            created_at,
+        }
+    }
+
+    /// Inserts the items and then the vote events in one committed
+    /// transaction.
+    pub async fn insert(storage: &MemoryStorage, items: &[Item], vote_events: &[VoteEvent]) {
+        let mut tx = storage.begin().await.unwrap();
+        for item in items {
+            tx.insert_item(item).await.unwrap();
+        }
+        for vote_event in vote_events {
+            tx.insert_vote_event(vote_event).await.unwrap();
+        }
+        tx.commit().await.unwrap();
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn begin(&self) -> Result<Box<dyn StorageTransaction>, AppError> {
+        Ok(Box::new(MemoryTransaction {
+            state: Arc::clone(&self.state).lock_owned().await,
+            clock: Arc::clone(&self.clock),
+            undo: Vec::new(),
+            savepoints: Vec::new(),
+        }))
+    }
+}
+
+/// Current vote of a user on an item.
+#[derive(Debug, Clone)]
+struct VoteRow {
+    vote: i32,
+    created_at: i64,
+}
+
+#[derive(Debug, Clone)]
+struct RankHistoryRow {
+    item_id: i32,
+    rank_top: Option<i32>,
+    rank_new: Option<i32>,
+    sample_time: i64,
+}
+
+#[derive(Debug, Clone, Default)]
+struct StatsRow {
+    updated_at: i64,
+    cumulative_upvotes: i32,
+    cumulative_expected_upvotes: f32,
+    cumulative_vote_event_expected_upvotes: f32,
+    cumulative_impression_expected_upvotes: f32,
+}
+
+/// Expected upvotes of an item in a sample, by the start of its interval.
+#[derive(Debug, Clone)]
+struct StatsHistoryRow {
+    item_id: i32,
+    start_time: i64,
+    expected_upvotes: f32,
+    vote_event_expected_upvotes: f32,
+    impression_expected_upvotes: f32,
+}
+
+#[derive(Debug, Clone)]
+struct PageRankRow {
+    rank: i32,
+    upvotes: Option<i32>,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    items: HashMap<i32, Item>,
+    /// Items by submission time, to find the newest ones.
+    items_by_time: BTreeSet<(i64, i32)>,
+    /// Vote events by creation time and id.
+    vote_events: BTreeMap<(i64, i32), VoteEvent>,
+    /// Creation times of the vote events by id.
+    vote_event_times: HashMap<i32, i64>,
+    /// Current votes by item and user.
+    votes: HashMap<i32, HashMap<String, VoteRow>>,
+    /// Impression counts by page, item, rank and bucket time.
+    impressions: BTreeMap<(String, i32, i32, i64), i32>,
+    pages: BTreeMap<String, Page>,
+    /// Page rank observations by page, sample time and item.
+    page_rank_history: BTreeMap<(String, i64, i32), PageRankRow>,
+    sample_intervals: Vec<QnSampleInterval>,
+    /// Rank observations by interval.
+    rank_history: BTreeMap<i32, Vec<RankHistoryRow>>,
+    rank_profiles: Vec<RankProfileEntry>,
+    stats: HashMap<i32, StatsRow>,
+    /// Samples in the order they were inserted.
+    stats_history: Vec<StatsHistoryRow>,
+    models: BTreeMap<i64, UpvoteShareModel>,
+    /// Version, activation time and pinning of the active model.
+    active_model: Option<(i64, i64, bool)>,
+    snapshots: BTreeMap<String, RankingSnapshot>,
+    experiments: BTreeMap<String, Experiment>,
+    interleavings: BTreeMap<String, Interleaving>,
+    /// Served positions by interleaving, user, serving time and rank.
+    interleaved_positions: BTreeMap<(String, String, i64, i32), InterleavedPosition>,
+    /// Credits with their interleaving by vote event id.
+    interleaving_credits: BTreeMap<i32, (String, InterleavingCredit)>,
+}
+
+impl MemoryState {
+    /// The newest top-level items submitted until `sample_time`, with their
+    /// submission times.
+    fn qn_item_pool(&self, sample_time: i64) -> Vec<(i32, i64)> {
+        self.items_by_time
+            .range(..=(sample_time, i32::MAX))
+            .rev()
+            .filter(|(_, item_id)| self.items[item_id].parent_id.is_none())
+            .take(QN_POOL_SIZE)
+            .map(|&(created_at, item_id)| (item_id, created_at))
+            .collect()
+    }
+
+    /// The `pool_size` newest items, top-level or not.
+    fn newest_items(&self, pool_size: i32) -> Vec<(i32, i64)> {
+        self.items_by_time
+            .iter()
+            .rev()
+            .take(pool_size.max(0) as usize)
+            .map(|&(created_at, item_id)| (item_id, created_at))
+            .collect()
+    }
+
+    /// Votes on the item whose latest change satisfies `filter`.
+    fn count_votes(&self, item_id: i32, filter: impl Fn(&VoteRow) -> bool) -> i32 {
+        self.votes.get(&item_id).map_or(0, |votes| {
+            votes.values().filter(|v| filter(v)).count() as i32
+        })
+    }
+
+    fn sitewide_upvotes_in_interval(&self, sample_time: i64, previous_sample_time: i64) -> i32 {
+        self.qn_item_pool(sample_time)
+            .iter()
+            .map(|&(item_id, _)| {
+                self.count_votes(item_id, |v| {
+                    v.vote == 1
+                        && v.created_at <= sample_time
+                        && v.created_at > previous_sample_time
+                })
+            })
+            .sum()
+    }
+
+    /// Vote events created after `after` until `until`.
+    fn vote_events_between(&self, after: i64, until: i64) -> impl Iterator<Item = &VoteEvent> {
+        self.vote_events
+            .range((
+                Bound::Excluded((after, i32::MAX)),
+                Bound::Included((until, i32::MAX)),
+            ))
+            .map(|(_, vote_event)| vote_event)
+    }
+
+    fn is_sampling_initialized(&self) -> bool {
+        !self.sample_intervals.is_empty()
+    }
+
+    fn model(&self, model_version: i64) -> Option<UpvoteShareModel> {
+        self.models.get(&model_version).cloned()
+    }
+}
+
+type Undo = Box<dyn FnOnce(&mut MemoryState) + Send>;
+
+/// Holds the storage exclusively until it is committed or rolled back. Every
+/// write records how to revert it, which is replayed if the transaction is
+/// dropped without committing.
+pub struct MemoryTransaction {
+    state: OwnedMutexGuard<MemoryState>,
+    clock: Arc<dyn Clock>,
+    undo: Vec<Undo>,
+    /// Lengths of the undo log at each open savepoint.
+    savepoints: Vec<usize>,
+}
+
+impl MemoryTransaction {
+    fn on_rollback(&mut self, undo: impl FnOnce(&mut MemoryState) + Send + 'static) {
+        self.undo.push(Box::new(undo));
+    }
+}
+
+impl Drop for MemoryTransaction {
+    fn drop(&mut self) {
+        while let Some(undo) = self.undo.pop() {
+            undo(&mut self.state);
+        }
+    }
+}
+
+fn conflict(message: String) -> AppError {
+    AppError::Conflict(message)
+}
+
+fn not_found(message: String) -> AppError {
+    AppError::NotFound(message)
+}
+
+fn invalid(message: String) -> AppError {
+    AppError::Validation(message)
+}
+
+#[async_trait]
+impl StorageTransaction for MemoryTransaction {
+    async fn commit(mut self: Box<Self>) -> Result<(), AppError> {
+        self.undo.clear();
+        Ok(())
+    }
+
+    async fn rollback(self: Box<Self>) -> Result<(), AppError> {
+        Ok(())
+    }
+
+    async fn savepoint(&mut self) -> Result<(), AppError> {
+        self.savepoints.push(self.undo.len());
+        Ok(())
+    }
+
+    async fn release_savepoint(&mut self) -> Result<(), AppError> {
+        self.savepoints
+            .pop()
+            .ok_or_else(|| anyhow::anyhow!("No savepoint to release"))?;
+        Ok(())
+    }
+
+    async fn rollback_to_savepoint(&mut self) -> Result<(), AppError> {
+        let savepoint = self
+            .savepoints
+            .pop()
+            .ok_or_else(|| anyhow::anyhow!("No savepoint to roll back to"))?;
+        for undo in self.undo.split_off(savepoint).into_iter().rev() {
+            undo(&mut self.state);
+        }
+        Ok(())
+    }
+
+    async fn insert_item(&mut self, item: &Item) -> Result<(), AppError> {
+        if self.state.items.contains_key(&item.item_id) {
+            return Err(conflict(format!("Duplicate item: {}", item.item_id)));
+        }
+        if let Some(parent_id) = item.parent_id {
+            if !self.state.items.contains_key(&parent_id) {
+                return Err(not_found(format!("Unknown parent item: {}", parent_id)));
+            }
+        }
+
+        let (item_id, created_at) = (item.item_id, item.created_at);
+        self.state.items.insert(item_id, item.clone());
+        self.state.items_by_time.insert((created_at, item_id));
+        self.on_rollback(move |s| {
+            s.items.remove(&item_id);
+            s.items_by_time.remove(&(created_at, item_id));
+        });
+
+        Ok(())
+    }
+
+    async fn insert_vote_event(&mut self, vote_event: &VoteEvent) -> Result<(), AppError> {
+        let vote_event_id = vote_event.vote_event_id;
+        if self.state.vote_event_times.contains_key(&vote_event_id) {
+            return Err(conflict(format!("Duplicate vote event: {}", vote_event_id)));
+        }
+        if !self.state.items.contains_key(&vote_event.item_id) {
+            return Err(not_found(format!("Unknown item: {}", vote_event.item_id)));
+        }
+        if !(-1..=1).contains(&vote_event.vote) {
+            return Err(invalid(format!("Invalid vote: {}", vote_event.vote)));
+        }
+
+        let key = (vote_event.created_at, vote_event_id);
+        self.state.vote_events.insert(key, vote_event.clone());
+        self.state
+            .vote_event_times
+            .insert(vote_event_id, vote_event.created_at);
+        self.on_rollback(move |s| {
+            s.vote_events.remove(&key);
+            s.vote_event_times.remove(&vote_event_id);
+        });
+
+        let (item_id, user_id) = (vote_event.item_id, vote_event.user_id.clone());
+        let previous = self.state.votes.entry(item_id).or_default().insert(
+            user_id.clone(),
+            VoteRow {
+                vote: vote_event.vote,
+                created_at: vote_event.created_at,
+            },
+        );
+        self.on_rollback(move |s| {
+            let votes = s.votes.entry(item_id).or_default();
+            match previous {
+                Some(previous) => votes.insert(user_id, previous),
+                None => votes.remove(&user_id),
+            };
+        });
+
+        Ok(())
+    }
+
+    async fn get_current_vote(
+        &mut self,
+        user_id: &str,
+        item_id: i32,
+    ) -> Result<Option<i32>, AppError> {
+        Ok(self
+            .state
+            .votes
+            .get(&item_id)
+            .and_then(|votes| votes.get(user_id))
+            .map(|v| v.vote))
+    }
+
+    async fn record_impression(
+        &mut self,
+        page_id: &str,
+        item_id: i32,
+        rank: i32,
+        bucket_time: i64,
+    ) -> Result<(), AppError> {
+        if !self.state.pages.contains_key(page_id) {
+            return Err(not_found(format!("Unknown page: {}", page_id)));
+        }
+        if !self.state.items.contains_key(&item_id) {
+            return Err(not_found(format!("Unknown item: {}", item_id)));
+        }
+
+        let key = (page_id.to_string(), item_id, rank, bucket_time);
+        *self.state.impressions.entry(key.clone()).or_default() += 1;
+        self.on_rollback(move |s| {
+            if let Some(count) = s.impressions.get_mut(&key) {
+                *count -= 1;
+                if *count == 0 {
+                    s.impressions.remove(&key);
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    async fn get_newest_stats(
+        &mut self,
+        pool_size: i32,
+        sample_time: i64,
+    ) -> Result<Vec<NewestStats>, AppError> {
+        Ok(self
+            .state
+            .newest_items(pool_size)
+            .into_iter()
+            .map(|(item_id, submission_time)| NewestStats {
+                item_id,
+                sample_time,
+                submission_time,
+            })
+            .collect())
+    }
+
+    async fn get_hn_stats(
+        &mut self,
+        pool_size: i32,
+        sample_time: i64,
+    ) -> Result<Vec<HnStats>, AppError> {
+        let state = &self.state;
+        Ok(state
+            .newest_items(pool_size)
+            .into_iter()
+            .map(|(item_id, submission_time)| HnStats {
+                item_id,
+                sample_time,
+                submission_time,
+                upvotes: state.count_votes(item_id, |v| v.vote == 1),
+            })
+            .collect())
+    }
+
+    async fn get_hot_stats(&mut self, pool_size: i32) -> Result<Vec<HotStats>, AppError> {
+        let state = &self.state;
+        Ok(state
+            .newest_items(pool_size)
+            .into_iter()
+            .map(|(item_id, submission_time)| HotStats {
+                item_id,
+                submission_time,
+                upvotes: state.count_votes(item_id, |v| v.vote == 1),
+                downvotes: state.count_votes(item_id, |v| v.vote == -1),
+            })
+            .collect())
+    }
+
+    async fn get_best_stats(&mut self, from: i64, until: i64) -> Result<Vec<BestStats>, AppError> {
+        let in_window = |v: &VoteRow| v.created_at > from && v.created_at <= until;
+        let state = &self.state;
+        Ok(state
+            .items_by_time
+            .iter()
+            .map(|&(_, item_id)| BestStats {
+                item_id,
+                upvotes: state.count_votes(item_id, |v| v.vote == 1 && in_window(v)),
+                downvotes: state.count_votes(item_id, |v| v.vote == -1 && in_window(v)),
+            })
+            .filter(|stats| stats.upvotes + stats.downvotes > 0)
+            .collect())
+    }
+
+    async fn get_top_stats(
+        &mut self,
+        from: i64,
+        until: i64,
+        pool_size: i32,
+    ) -> Result<Vec<TopStats>, AppError> {
+        let state = &self.state;
+        // Later events replace earlier ones, leaving each user's latest vote
+        let latest_votes: HashMap<(i32, &str), &VoteEvent> = state
+            .vote_events_between(from, until)
+            .map(|ve| ((ve.item_id, ve.user_id.as_str()), ve))
+            .collect();
+        let upvotes: Vec<(i32, usize)> = latest_votes
+            .values()
+            .filter(|ve| ve.vote == 1)
+            .map(|ve| ve.item_id)
+            .counts()
+            .into_iter()
+            .sorted_by_key(|&(item_id, upvotes)| std::cmp::Reverse((upvotes, item_id)))
+            .take(pool_size.max(0) as usize)
+            .collect();
+        let mut window_stats: HashMap<i32, (f32, f32, f32)> = HashMap::new();
+        for row in state
+            .stats_history
+            .iter()
+            .filter(|row| row.start_time >= from && row.start_time < until)
+        {
+            let stats = window_stats.entry(row.item_id).or_default();
+            stats.0 += row.expected_upvotes;
+            stats.1 += row.vote_event_expected_upvotes;
+            stats.2 += row.impression_expected_upvotes;
+        }
+
+        Ok(upvotes
+            .into_iter()
+            .map(|(item_id, upvotes)| {
+                let window_stats = window_stats.get(&item_id);
+                TopStats {
+                    item_id,
+                    submission_time: state.items[&item_id].created_at,
+                    upvotes: upvotes as i32,
+                    expected_upvotes: window_stats.map(|s| s.0),
+                    vote_event_expected_upvotes: window_stats.map(|s| s.1),
+                    impression_expected_upvotes: window_stats.map(|s| s.2),
+                }
+            })
+            .collect())
+    }
+
+    async fn get_item_aggregates(
+        &mut self,
+        item_ids: &[i32],
+    ) -> Result<Vec<ItemAggregate>, AppError> {
+        let state = &self.state;
+        Ok(item_ids
+            .iter()
+            .filter_map(|item_id| {
+                let item = state.items.get(item_id)?;
+                let stats = state.stats.get(item_id);
+                Some(ItemAggregate {
+                    item_id: *item_id,
+                    parent_id: item.parent_id,
+                    submission_time: item.created_at,
+                    upvotes: state.count_votes(*item_id, |v| v.vote == 1),
+                    downvotes: state.count_votes(*item_id, |v| v.vote == -1),
+                    expected_upvotes: stats.map(|s| s.cumulative_expected_upvotes),
+                    vote_event_expected_upvotes: stats
+                        .map(|s| s.cumulative_vote_event_expected_upvotes),
+                    impression_expected_upvotes: stats
+                        .map(|s| s.cumulative_impression_expected_upvotes),
+                    last_vote_at: state
+                        .votes
+                        .get(item_id)
+                        .and_then(|votes| votes.values().map(|v| v.created_at).max()),
+                })
+            })
+            .collect())
+    }
+
+    async fn get_items(&mut self) -> Result<Vec<Item>, AppError> {
+        Ok(self
+            .state
+            .items_by_time
+            .iter()
+            .map(|(_, item_id)| self.state.items[item_id].clone())
+            .collect())
+    }
+
+    async fn get_thread(&mut self, item_id: i32) -> Result<Vec<Item>, AppError> {
+        let state = &self.state;
+        if !state.items.contains_key(&item_id) {
+            return Ok(Vec::new());
+        }
+        let replies = state
+            .items
+            .values()
+            .filter_map(|item| Some((item.parent_id?, item.item_id)))
+            .into_group_map();
+
+        let mut thread = vec![item_id];
+        let mut next = 0;
+        while let Some(parent_id) = thread.get(next).copied() {
+            thread.extend(replies.get(&parent_id).into_iter().flatten());
+            next += 1;
+        }
+
+        Ok(thread
+            .into_iter()
+            .map(|item_id| state.items[&item_id].clone())
+            .sorted_by_key(|item| (item.created_at, item.item_id))
+            .collect())
+    }
+
+    async fn get_vote_events(&mut self) -> Result<Vec<VoteEvent>, AppError> {
+        Ok(self.state.vote_events.values().cloned().collect())
+    }
+
+    async fn get_pages(&mut self) -> Result<Vec<Page>, AppError> {
+        Ok(self.state.pages.values().cloned().collect())
+    }
+
+    async fn get_page(&mut self, page_id: &str) -> Result<Option<Page>, AppError> {
+        Ok(self.state.pages.get(page_id).cloned())
+    }
+
+    async fn insert_page(&mut self, page: &PageDefinition) -> Result<Page, AppError> {
+        if self.state.pages.contains_key(&page.page_id) {
+            return Err(conflict(format!("Duplicate page: {}", page.page_id)));
+        }
+
+        let page = Page {
+            page_id: page.page_id.clone(),
+            algorithm: page.algorithm.clone(),
+            params: page.params.clone(),
+            created_at: self.clock.now_millis(),
+        };
+        self.state.pages.insert(page.page_id.clone(), page.clone());
+        let page_id = page.page_id.clone();
+        self.on_rollback(move |s| {
+            s.pages.remove(&page_id);
+        });
+
+        Ok(page)
+    }
+
+    async fn delete_page(&mut self, page_id: &str) -> Result<u64, AppError> {
+        let Some(page) = self.state.pages.remove(page_id) else {
+            return Ok(0);
+        };
+
+        // Like the foreign keys of the SQL backends, deleting a page deletes
+        // its rank history and impressions
+        let state = &mut *self.state;
+        let page_ranks: Vec<_> = state
+            .page_rank_history
+            .extract_if(.., |(p, _, _), _| p == page_id)
+            .collect();
+        let impressions: Vec<_> = state
+            .impressions
+            .extract_if(.., |(p, _, _, _), _| p == page_id)
+            .collect();
+        self.on_rollback(move |s| {
+            s.pages.insert(page.page_id.clone(), page);
+            s.page_rank_history.extend(page_ranks);
+            s.impressions.extend(impressions);
+        });
+
+        Ok(1)
+    }
+
+    async fn close_rank_window(&mut self, page_id: &str, sample_time: i64) -> Result<(), AppError> {
+        let state = &mut *self.state;
+        let Some(latest) = state
+            .page_rank_history
+            .range(
+                (page_id.to_string(), i64::MIN, i32::MIN)
+                    ..=(page_id.to_string(), i64::MAX, i32::MAX),
+            )
+            .map(|((_, time, _), _)| *time)
+            .max()
+        else {
+            return Ok(());
+        };
+
+        let upvotes_by_item: HashMap<i32, i32> = state
+            .vote_events_between(latest, sample_time)
+            .filter(|ve| ve.page.as_deref() == Some(page_id) && ve.vote == 1)
+            .map(|ve| (ve.item_id, 1))
+            .into_grouping_map()
+            .sum();
+        let upvotes = |item_id: i32| upvotes_by_item.get(&item_id).copied().unwrap_or(0);
+        let closed: Vec<(i32, i32)> = state
+            .page_rank_history
+            .range(
+                (page_id.to_string(), latest, i32::MIN)..=(page_id.to_string(), latest, i32::MAX),
+            )
+            .filter(|(_, row)| row.upvotes.is_none())
+            .map(|((_, _, item_id), _)| (*item_id, upvotes(*item_id)))
+            .collect();
+
+        for (item_id, upvotes) in &closed {
+            if let Some(row) =
+                state
+                    .page_rank_history
+                    .get_mut(&(page_id.to_string(), latest, *item_id))
+            {
+                row.upvotes = Some(*upvotes);
+            }
+        }
+        let page_id = page_id.to_string();
+        self.on_rollback(move |s| {
+            for (item_id, _) in closed {
+                if let Some(row) = s
+                    .page_rank_history
+                    .get_mut(&(page_id.clone(), latest, item_id))
+                {
+                    row.upvotes = None;
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    async fn insert_page_ranks(
+        &mut self,
+        page_id: &str,
+        sample_time: i64,
+        ranking: &[ScoredItem],
+    ) -> Result<(), AppError> {
+        if !self.state.pages.contains_key(page_id) {
+            return Err(not_found(format!("Unknown page: {}", page_id)));
+        }
+
+        for r in ranking {
+            let key = (page_id.to_string(), sample_time, r.item_id);
+            if self.state.page_rank_history.contains_key(&key) {
+                return Err(conflict(format!(
+                    "Duplicate rank of item {} on page {} at {}",
+                    r.item_id, page_id, sample_time
+                )));
+            }
+            self.state.page_rank_history.insert(
+                key.clone(),
+                PageRankRow {
+                    rank: r.rank,
+                    upvotes: None,
+                },
+            );
+            self.on_rollback(move |s| {
+                s.page_rank_history.remove(&key);
+            });
+        }
+
+        Ok(())
+    }
+
+    async fn get_upvote_shares_by_rank(
+        &mut self,
+        page_id: &str,
+    ) -> Result<Vec<RankUpvoteShare>, AppError> {
+        let upvotes_by_rank: BTreeMap<i32, i32> = self
+            .state
+            .page_rank_history
+            .iter()
+            .filter(|((p, _, _), _)| p == page_id)
+            .filter_map(|(_, row)| Some((row.rank, row.upvotes?)))
+            .into_grouping_map()
+            .sum()
+            .into_iter()
+            .collect();
+        let total_upvotes: i32 = upvotes_by_rank.values().sum();
+
+        Ok(upvotes_by_rank
+            .into_iter()
+            .map(|(rank, upvotes)| RankUpvoteShare {
+                rank,
+                upvotes,
+                upvote_share: if total_upvotes > 0 {
+                    upvotes as f32 / total_upvotes as f32
+                } else {
+                    0.0
+                },
+            })
+            .collect())
+    }
+
+    async fn get_page_rank_history(
+        &mut self,
+        page_id: &str,
+        from: i64,
+        until: i64,
+    ) -> Result<Vec<PageRank>, AppError> {
+        Ok(self
+            .state
+            .page_rank_history
+            .range((page_id.to_string(), from, i32::MIN)..=(page_id.to_string(), until, i32::MAX))
+            .map(|((_, sample_time, item_id), row)| PageRank {
+                sample_time: *sample_time,
+                item_id: *item_id,
+                rank: row.rank,
+            })
+            .sorted_by_key(|r| (r.sample_time, r.rank))
+            .collect())
+    }
+
+    async fn has_items(&mut self) -> Result<bool, AppError> {
+        Ok(!self.state.items.is_empty())
+    }
+
+    async fn is_sampling_initialized(&mut self) -> Result<bool, AppError> {
+        Ok(self.state.is_sampling_initialized())
+    }
+
+    async fn insert_sample_interval(
+        &mut self,
+        start_time: i64,
+    ) -> Result<QnSampleInterval, AppError> {
+        let interval = QnSampleInterval {
+            interval_id: self.state.sample_intervals.len() as i32 + 1,
+            start_time,
+        };
+        self.state.sample_intervals.push(interval.clone());
+        self.on_rollback(|s| {
+            s.sample_intervals.pop();
+        });
+
+        Ok(interval)
+    }
+
+    async fn get_latest_sample_interval(&mut self) -> Result<QnSampleInterval, AppError> {
+        self.state
+            .sample_intervals
+            .last()
+            .cloned()
+            .ok_or(AppError::SamplingNotInitialized)
+    }
+
+    async fn get_sitewide_upvotes_in_interval(
+        &mut self,
+        sample_time: i64,
+        previous_sample_time: i64,
+    ) -> Result<i32, AppError> {
+        Ok(self
+            .state
+            .sitewide_upvotes_in_interval(sample_time, previous_sample_time))
+    }
+
+    async fn insert_rank_observations(
+        &mut self,
+        ranked_items: &[ItemWithRanks],
+        sample_interval: &QnSampleInterval,
+        sample_time: i64,
+    ) -> Result<(), AppError> {
+        let interval_id = sample_interval.interval_id;
+        let observations = self.state.rank_history.entry(interval_id).or_default();
+        let previous_len = observations.len();
+        observations.extend(ranked_items.iter().map(|r| RankHistoryRow {
+            item_id: r.item_id,
+            rank_top: Some(r.rank_top),
+            rank_new: Some(r.rank_new),
+            sample_time,
+        }));
+        self.on_rollback(move |s| {
+            if let Some(observations) = s.rank_history.get_mut(&interval_id) {
+                observations.truncate(previous_len);
+            }
+        });
+
+        Ok(())
+    }
+
+    async fn get_rank_profiles(
+        &mut self,
+        interval: &QnSampleInterval,
+        end_time: i64,
+    ) -> Result<Vec<RankProfileEntry>, AppError> {
+        let Some(observations) = self.state.rank_history.get(&interval.interval_id) else {
+            return Ok(Vec::new());
+        };
+
+        // Every observation holds until the next one, the last one until the
+        // end of the interval
+        let times: Vec<i64> = observations
+            .iter()
+            .map(|o| o.sample_time)
+            .sorted()
+            .dedup()
+            .collect();
+        let durations: HashMap<i64, i64> = times
+            .iter()
+            .enumerate()
+            .map(|(i, &time)| (time, times.get(i + 1).copied().unwrap_or(end_time) - time))
+            .collect();
+
+        Ok(observations
+            .iter()
+            .map(|o| {
+                (
+                    (o.item_id, o.rank_top, o.rank_new),
+                    durations[&o.sample_time],
+                )
+            })
+            .into_grouping_map()
+            .sum()
+            .into_iter()
+            .map(
+                |((item_id, rank_top, rank_new), duration)| RankProfileEntry {
+                    item_id,
+                    interval_id: interval.interval_id,
+                    rank_top,
+                    rank_new,
+                    duration,
+                },
+            )
+            .collect())
+    }
+
+    async fn insert_rank_profiles(
+        &mut self,
+        profiles: &[RankProfileEntry],
+    ) -> Result<(), AppError> {
+        let previous_len = self.state.rank_profiles.len();
+        self.state.rank_profiles.extend_from_slice(profiles);
+        self.on_rollback(move |s| s.rank_profiles.truncate(previous_len));
+
+        Ok(())
+    }
+
+    async fn get_qn_stats(
+        &mut self,
+        sample_time: i64,
+        expected_upvotes: ExpectedUpvotes,
+    ) -> Result<Vec<QnStats>, AppError> {
+        let state = &self.state;
+        let initialized = state.is_sampling_initialized();
+
+        Ok(state
+            .qn_item_pool(sample_time)
+            .into_iter()
+            .map(|(item_id, submission_time)| {
+                let cumulative_upvotes =
+                    state.count_votes(item_id, |v| v.vote == 1 && v.created_at <= sample_time);
+                let stats = state.stats.get(&item_id).filter(|_| initialized);
+                QnStats {
+                    item_id,
+                    updated_at: stats.map_or(sample_time, |s| s.updated_at),
+                    sample_time,
+                    submission_time,
+                    cumulative_upvotes,
+                    cumulative_expected_upvotes: stats.map_or(cumulative_upvotes as f32, |s| {
+                        match expected_upvotes {
+                            ExpectedUpvotes::RankProfile => s.cumulative_expected_upvotes,
+                            ExpectedUpvotes::VoteEvents => s.cumulative_vote_event_expected_upvotes,
+                            ExpectedUpvotes::Impressions => {
+                                s.cumulative_impression_expected_upvotes
+                            }
+                        }
+                    }),
+                }
+            })
+            .collect())
+    }
+
+    async fn insert_sample(&mut self, sample: &QnSampleWithPrediction) -> Result<(), AppError> {
+        let item_id = sample.sample.item_id;
+        if !self.state.items.contains_key(&item_id) {
+            return Err(not_found(format!("Unknown item: {}", item_id)));
+        }
+
+        let previous = self.state.stats.get(&item_id).cloned();
+        let stats = self.state.stats.entry(item_id).or_default();
+        stats.updated_at = self.clock.now_millis();
+        stats.cumulative_upvotes += sample.sample.upvotes;
+        stats.cumulative_expected_upvotes += sample.expected_upvotes;
+        stats.cumulative_vote_event_expected_upvotes += sample.vote_event_expected_upvotes;
+        stats.cumulative_impression_expected_upvotes += sample.impression_expected_upvotes;
+        self.state.stats_history.push(StatsHistoryRow {
+            item_id,
+            start_time: sample.sample.interval.start_time,
+            expected_upvotes: sample.expected_upvotes,
+            vote_event_expected_upvotes: sample.vote_event_expected_upvotes,
+            impression_expected_upvotes: sample.impression_expected_upvotes,
+        });
+        self.on_rollback(move |s| {
+            match previous {
+                Some(previous) => s.stats.insert(item_id, previous),
+                None => s.stats.remove(&item_id),
+            };
+            s.stats_history.pop();
+        });
+
+        Ok(())
+    }
+
+    async fn get_vote_event_ranks_in_interval(
+        &mut self,
+        interval: &QnSampleInterval,
+        sample_time: i64,
+        max_rank: i32,
+    ) -> Result<Vec<VoteEventRank>, AppError> {
+        let state = &self.state;
+        let mut vote_event_ranks = Vec::new();
+        for ve in state
+            .vote_events_between(interval.start_time, sample_time)
+            .filter(|ve| ve.vote == 1)
+        {
+            let (Some(page_id), Some(rank)) = (&ve.page, ve.rank) else {
+                continue;
+            };
+            vote_event_ranks.push(VoteEventRank {
+                vote_event_id: ve.vote_event_id,
+                item_id: ve.item_id,
+                page_id: page_id.clone(),
+                rank,
+            });
+
+            let Some(page_sample_time) = state
+                .page_rank_history
+                .range(
+                    (page_id.clone(), i64::MIN, i32::MIN)
+                        ..=(page_id.clone(), ve.created_at, i32::MAX),
+                )
+                .map(|((_, time, _), _)| *time)
+                .next_back()
+            else {
+                continue;
+            };
+            vote_event_ranks.extend(
+                state
+                    .page_rank_history
+                    .range(
+                        (page_id.clone(), page_sample_time, i32::MIN)
+                            ..=(page_id.clone(), page_sample_time, i32::MAX),
+                    )
+                    .filter(|((_, _, item_id), row)| *item_id != ve.item_id && row.rank <= max_rank)
+                    .map(|((_, _, item_id), row)| VoteEventRank {
+                        vote_event_id: ve.vote_event_id,
+                        item_id: *item_id,
+                        page_id: page_id.clone(),
+                        rank: row.rank,
+                    }),
+            );
+        }
+
+        Ok(vote_event_ranks)
+    }
+
+    async fn get_impression_expected_upvotes_in_interval(
+        &mut self,
+        interval: &QnSampleInterval,
+        sample_time: i64,
+        lookback_start: i64,
+    ) -> Result<Vec<ImpressionExpectedUpvotes>, AppError> {
+        let bucket = |time: i64| time - time.rem_euclid(IMPRESSION_BUCKET_MILLIS);
+        let state = &self.state;
+
+        let impressions_by_rank: HashMap<(&str, i32), i32> = state
+            .impressions
+            .iter()
+            .filter(|((_, _, _, time), _)| {
+                *time >= bucket(lookback_start) && *time < bucket(sample_time)
+            })
+            .map(|((page_id, _, rank, _), count)| ((page_id.as_str(), *rank), *count))
+            .into_grouping_map()
+            .sum();
+        let upvotes_by_rank: HashMap<(&str, i32), i32> = state
+            .vote_events_between(lookback_start, sample_time)
+            .filter(|ve| ve.vote == 1)
+            .filter_map(|ve| Some(((ve.page.as_deref()?, ve.rank?), 1)))
+            .into_grouping_map()
+            .sum();
+
+        // Each impression in the interval is worth the upvotes per impression
+        // at its page and rank
+        Ok(state
+            .impressions
+            .iter()
+            .filter(|((_, _, _, time), _)| {
+                *time >= bucket(interval.start_time) && *time < bucket(sample_time)
+            })
+            .filter_map(|((page_id, item_id, rank, _), count)| {
+                let impressions = impressions_by_rank.get(&(page_id.as_str(), *rank))?;
+                let upvotes = upvotes_by_rank
+                    .get(&(page_id.as_str(), *rank))
+                    .copied()
+                    .unwrap_or(0);
+                Some((
+                    *item_id,
+                    *count as f32 * upvotes as f32 / *impressions as f32,
+                ))
+            })
+            .into_grouping_map()
+            .sum()
+            .into_iter()
+            .map(|(item_id, expected_upvotes)| ImpressionExpectedUpvotes {
+                item_id,
+                expected_upvotes,
+            })
+            .collect())
+    }
+
+    async fn get_sample_in_interval(
+        &mut self,
+        interval: &QnSampleInterval,
+        sample_time: i64,
+    ) -> Result<Vec<QnSample>, AppError> {
+        let state = &self.state;
+        let sitewide_upvotes =
+            state.sitewide_upvotes_in_interval(sample_time, interval.start_time) as f32;
+
+        Ok(state
+            .qn_item_pool(sample_time)
+            .into_iter()
+            .map(|(item_id, submission_time)| {
+                let upvotes = state.count_votes(item_id, |v| {
+                    v.created_at > interval.start_time && v.created_at <= sample_time
+                });
+                QnSample {
+                    item_id,
+                    interval: interval.clone(),
+                    sample_time,
+                    submission_time,
+                    upvotes,
+                    upvote_share: if sitewide_upvotes > 0.0 {
+                        upvotes as f32 / sitewide_upvotes
+                    } else {
+                        0.0
+                    },
+                }
+            })
+            .collect())
+    }
+
+    async fn get_rank_observations(
+        &mut self,
+        training_start: i64,
+        training_end: i64,
+    ) -> Result<Vec<RankObservation>, AppError> {
+        let state = &self.state;
+        let upvotes_by_rank: BTreeMap<(String, i32), i64> = state
+            .vote_events_between(training_start, training_end)
+            .filter(|ve| ve.vote == 1)
+            .filter_map(|ve| Some(((ve.page.clone()?, ve.rank?), 1)))
+            .into_grouping_map()
+            .sum()
+            .into_iter()
+            .collect();
+        let exposures_by_rank: BTreeMap<(String, i32), i64> = state
+            .page_rank_history
+            .iter()
+            .filter(|((_, time, _), row)| {
+                row.upvotes.is_some() && *time > training_start && *time <= training_end
+            })
+            .map(|((page_id, _, _), row)| ((page_id.clone(), row.rank), 1))
+            .into_grouping_map()
+            .sum()
+            .into_iter()
+            .collect();
+        let sampled_pages: BTreeSet<&str> = exposures_by_rank
+            .keys()
+            .map(|(page_id, _)| page_id.as_str())
+            .collect();
+
+        // Pages whose ranks are never sampled get an exposure of 1 per rank
+        Ok(upvotes_by_rank
+            .keys()
+            .chain(exposures_by_rank.keys())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .filter(|key| {
+                exposures_by_rank.contains_key(*key) || !sampled_pages.contains(key.0.as_str())
+            })
+            .map(|key| RankObservation {
+                page_id: key.0.clone(),
+                rank: key.1,
+                upvotes: upvotes_by_rank.get(key).copied().unwrap_or(0),
+                exposures: exposures_by_rank.get(key).copied().unwrap_or(1),
+            })
+            .collect())
+    }
+
+    async fn insert_model(
+        &mut self,
+        created_at: i64,
+        training_start: i64,
+        training_end: i64,
+        coefficients: &[PageCoefficients],
+    ) -> Result<UpvoteShareModel, AppError> {
+        let model = UpvoteShareModel {
+            model_version: self.state.models.keys().last().map_or(1, |v| v + 1),
+            created_at,
+            training_start,
+            training_end,
+            coefficients: coefficients
+                .iter()
+                .cloned()
+                .sorted_by(|a, b| a.page_id.cmp(&b.page_id))
+                .collect(),
+        };
+        self.state.models.insert(model.model_version, model.clone());
+        let model_version = model.model_version;
+        self.on_rollback(move |s| {
+            s.models.remove(&model_version);
+        });
+
+        Ok(UpvoteShareModel {
+            coefficients: coefficients.to_vec(),
+            ..model
+        })
+    }
+
+    async fn get_model_versions(&mut self) -> Result<Vec<UpvoteShareModelVersion>, AppError> {
+        let active_version = self.state.active_model.map(|(version, _, _)| version);
+        Ok(self
+            .state
+            .models
+            .values()
+            .rev()
+            .map(|m| UpvoteShareModelVersion {
+                model_version: m.model_version,
+                created_at: m.created_at,
+                training_start: m.training_start,
+                training_end: m.training_end,
+                active: Some(m.model_version) == active_version,
+            })
+            .collect())
+    }
+
+    async fn get_model(
+        &mut self,
+        model_version: i64,
+    ) -> Result<Option<UpvoteShareModel>, AppError> {
+        Ok(self.state.model(model_version))
+    }
+
+    async fn get_active_model(&mut self) -> Result<Option<UpvoteShareModel>, AppError> {
+        Ok(self
+            .state
+            .active_model
+            .and_then(|(version, _, _)| self.state.model(version)))
+    }
+
+    async fn set_active_model(
+        &mut self,
+        model_version: i64,
+        activated_at: i64,
+        pinned: bool,
+    ) -> Result<(), AppError> {
+        if !self.state.models.contains_key(&model_version) {
+            return Err(not_found(format!(
+                "Unknown upvote share model version: {}",
+                model_version
+            )));
+        }
+
+        let previous = self
+            .state
+            .active_model
+            .replace((model_version, activated_at, pinned));
+        self.on_rollback(move |s| s.active_model = previous);
+
+        Ok(())
+    }
+
+    async fn is_active_model_pinned(&mut self) -> Result<bool, AppError> {
+        Ok(self.state.active_model.is_some_and(|(_, _, pinned)| pinned))
+    }
+
+    async fn get_training_history(&mut self) -> Result<(i64, Option<i64>), AppError> {
+        Ok((
+            self.state.models.len() as i64,
+            self.state.models.values().map(|m| m.created_at).max(),
+        ))
+    }
+
+    async fn upsert_snapshots(
+        &mut self,
+        snapshots: &[Arc<RankingSnapshot>],
+    ) -> Result<(), AppError> {
+        for snapshot in snapshots {
+            let ranking = snapshot.ranking.clone();
+            let previous = self
+                .state
+                .snapshots
+                .insert(ranking.clone(), RankingSnapshot::clone(snapshot));
+            self.on_rollback(move |s| {
+                match previous {
+                    Some(previous) => s.snapshots.insert(ranking, previous),
+                    None => s.snapshots.remove(&ranking),
+                };
+            });
+        }
+
+        Ok(())
+    }
+
+    async fn get_snapshots(&mut self) -> Result<Vec<RankingSnapshot>, AppError> {
+        Ok(self.state.snapshots.values().cloned().collect())
+    }
+
+    async fn insert_experiment(&mut self, experiment: &Experiment) -> Result<(), AppError> {
+        if self
+            .state
+            .experiments
+            .contains_key(&experiment.experiment_id)
+        {
+            return Err(conflict(format!(
+                "Duplicate experiment: {}",
+                experiment.experiment_id
+            )));
+        }
+        if experiment.ended_at.is_none()
+            && self
+                .state
+                .experiments
+                .values()
+                .any(|e| e.ended_at.is_none())
+        {
+            return Err(conflict("Another experiment is running".to_string()));
+        }
+
+        self.state
+            .experiments
+            .insert(experiment.experiment_id.clone(), experiment.clone());
+        let experiment_id = experiment.experiment_id.clone();
+        self.on_rollback(move |s| {
+            s.experiments.remove(&experiment_id);
+        });
+
+        Ok(())
+    }
+
+    async fn get_experiments(&mut self) -> Result<Vec<Experiment>, AppError> {
+        Ok(self
+            .state
+            .experiments
+            .values()
+            .sorted_by_key(|e| (e.created_at, e.experiment_id.clone()))
+            .cloned()
+            .collect())
+    }
+
+    async fn get_experiment(
+        &mut self,
+        experiment_id: &str,
+    ) -> Result<Option<Experiment>, AppError> {
+        Ok(self.state.experiments.get(experiment_id).cloned())
+    }
+
+    async fn get_running_experiment(&mut self) -> Result<Option<Experiment>, AppError> {
+        Ok(self
+            .state
+            .experiments
+            .values()
+            .find(|e| e.ended_at.is_none())
+            .cloned())
+    }
+
+    async fn end_experiment(
+        &mut self,
+        experiment_id: &str,
+        ended_at: i64,
+    ) -> Result<u64, AppError> {
+        let Some(experiment) = self
+            .state
+            .experiments
+            .get_mut(experiment_id)
+            .filter(|e| e.ended_at.is_none())
+        else {
+            return Ok(0);
+        };
+
+        experiment.ended_at = Some(ended_at);
+        let experiment_id = experiment_id.to_string();
+        self.on_rollback(move |s| {
+            if let Some(experiment) = s.experiments.get_mut(&experiment_id) {
+                experiment.ended_at = None;
+            }
+        });
+
+        Ok(1)
+    }
+
+    async fn get_page_activity(
+        &mut self,
+        page_id: &str,
+        from: i64,
+        until: i64,
+    ) -> Result<PageActivity, AppError> {
+        let impressions = self
+            .state
+            .impressions
+            .iter()
+            .filter(|((p, _, _, bucket_time), _)| {
+                p == page_id && *bucket_time >= from && *bucket_time <= until
+            })
+            .map(|(_, &impressions)| impressions as i64)
+            .sum();
+        let upvotes = self
+            .state
+            .vote_events_between(from, until)
+            .filter(|ve| ve.page.as_deref() == Some(page_id) && ve.vote == 1)
+            .count() as i64;
+
+        Ok(PageActivity {
+            impressions,
+            upvotes,
+        })
+    }
+
+    async fn insert_interleaving(&mut self, interleaving: &Interleaving) -> Result<(), AppError> {
+        if self
+            .state
+            .interleavings
+            .contains_key(&interleaving.interleaving_id)
+        {
+            return Err(conflict(format!(
+                "Duplicate interleaving: {}",
+                interleaving.interleaving_id
+            )));
+        }
+
+        self.state
+            .interleavings
+            .insert(interleaving.interleaving_id.clone(), interleaving.clone());
+        let interleaving_id = interleaving.interleaving_id.clone();
+        self.on_rollback(move |s| {
+            s.interleavings.remove(&interleaving_id);
+        });
+
+        Ok(())
+    }
+
+    async fn get_interleavings(&mut self) -> Result<Vec<Interleaving>, AppError> {
+        Ok(self
+            .state
+            .interleavings
+            .values()
+            .sorted_by_key(|i| (i.created_at, i.interleaving_id.clone()))
+            .cloned()
+            .collect())
+    }
+
+    async fn get_interleaving(
+        &mut self,
+        interleaving_id: &str,
+    ) -> Result<Option<Interleaving>, AppError> {
+        Ok(self.state.interleavings.get(interleaving_id).cloned())
+    }
+
+    async fn insert_interleaved_positions(
+        &mut self,
+        interleaving_id: &str,
+        user_id: &str,
+        positions: &[InterleavedPosition],
+    ) -> Result<(), AppError> {
+        let mut inserted = Vec::new();
+        for position in positions {
+            let key = (
+                interleaving_id.to_string(),
+                user_id.to_string(),
+                position.served_at,
+                position.rank,
+            );
+            if !self.state.interleaved_positions.contains_key(&key) {
+                self.state
+                    .interleaved_positions
+                    .insert(key.clone(), position.clone());
+                inserted.push(key);
+            }
+        }
+        self.on_rollback(move |s| {
+            for key in inserted {
+                s.interleaved_positions.remove(&key);
+            }
+        });
+
+        Ok(())
+    }
+
+    async fn get_interleaved_position(
+        &mut self,
+        interleaving_id: &str,
+        user_id: &str,
+        rank: i32,
+        at: i64,
+    ) -> Result<Option<InterleavedPosition>, AppError> {
+        let (interleaving_id, user_id) = (interleaving_id.to_string(), user_id.to_string());
+        let Some(((_, _, served_at, _), _)) = self
+            .state
+            .interleaved_positions
+            .range(
+                (interleaving_id.clone(), user_id.clone(), i64::MIN, i32::MIN)
+                    ..=(interleaving_id.clone(), user_id.clone(), at, i32::MAX),
+            )
+            .next_back()
+        else {
+            return Ok(None);
+        };
+
+        Ok(self
+            .state
+            .interleaved_positions
+            .get(&(interleaving_id, user_id, *served_at, rank))
+            .cloned())
+    }
+
+    async fn insert_interleaving_credit(
+        &mut self,
+        interleaving_id: &str,
+        credit: &InterleavingCredit,
+    ) -> Result<(), AppError> {
+        if self
+            .state
+            .interleaving_credits
+            .contains_key(&credit.vote_event_id)
+        {
+            return Err(conflict(format!(
+                "Duplicate interleaving credit: {}",
+                credit.vote_event_id
+            )));
+        }
+
+        self.state.interleaving_credits.insert(
+            credit.vote_event_id,
+            (interleaving_id.to_string(), credit.clone()),
+        );
+        let vote_event_id = credit.vote_event_id;
+        self.on_rollback(move |s| {
+            s.interleaving_credits.remove(&vote_event_id);
+        });
+
+        Ok(())
+    }
+
+    async fn get_interleaving_credits(
+        &mut self,
+        interleaving_id: &str,
+    ) -> Result<Vec<InterleavingCredit>, AppError> {
+        Ok(self
+            .state
+            .interleaving_credits
+            .values()
+            .filter(|(i, _)| i == interleaving_id)
+            .map(|(_, credit)| credit.clone())
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures::item;
+
+    fn vote_event(vote_event_id: i32, item_id: i32, vote: i32) -> VoteEvent {
+        fixtures::vote_event(
+            vote_event_id,
+            item_id,
+            "user",
+            vote,
+            100 + vote_event_id as i64,
+        )
+    }
+
+    async fn item_ids(storage: &MemoryStorage) -> Vec<i32> {
+        let mut tx = storage.begin().await.unwrap();
+        let items = tx.get_items().await.unwrap();
+        items.iter().map(|item| item.item_id).sorted().collect()
+    }
+
+    #[tokio::test]
+    async fn uncommitted_transactions_leave_no_trace() {
+        let storage = MemoryStorage::new();
+        let mut tx = storage.begin().await.unwrap();
+        tx.insert_item(&item(1, 0)).await.unwrap();
+        tx.commit().await.unwrap();
+
+        let mut tx = storage.begin().await.unwrap();
+        tx.insert_item(&item(2, 0)).await.unwrap();
+        tx.insert_vote_event(&vote_event(1, 1, 1)).await.unwrap();
+        drop(tx);
+
+        assert_eq!(item_ids(&storage).await, vec![1]);
+        let mut tx = storage.begin().await.unwrap();
+        assert_eq!(tx.get_current_vote("user", 1).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn rolling_back_to_a_savepoint_keeps_earlier_writes() {
+        let storage = MemoryStorage::new();
+        let mut tx = storage.begin().await.unwrap();
+        tx.insert_item(&item(1, 0)).await.unwrap();
+        tx.savepoint().await.unwrap();
+        tx.insert_item(&item(2, 0)).await.unwrap();
+        tx.rollback_to_savepoint().await.unwrap();
+        tx.savepoint().await.unwrap();
+        tx.insert_item(&item(3, 0)).await.unwrap();
+        tx.release_savepoint().await.unwrap();
+        tx.commit().await.unwrap();
+
+        assert_eq!(item_ids(&storage).await, vec![1, 3]);
+    }
+
+    #[tokio::test]
+    async fn vote_events_replace_the_current_vote() {
+        let storage = MemoryStorage::new();
+        let mut tx = storage.begin().await.unwrap();
+        tx.insert_item(&item(1, 0)).await.unwrap();
+        tx.insert_vote_event(&vote_event(1, 1, 1)).await.unwrap();
+        tx.insert_vote_event(&vote_event(2, 1, -1)).await.unwrap();
+
+        assert_eq!(tx.get_current_vote("user", 1).await.unwrap(), Some(-1));
+        let aggregates = tx.get_item_aggregates(&[1]).await.unwrap();
+        assert_eq!((aggregates[0].upvotes, aggregates[0].downvotes), (0, 1));
+    }
+
+    #[tokio::test]
+    async fn duplicate_items_conflict() {
+        let storage = MemoryStorage::new();
+        let mut tx = storage.begin().await.unwrap();
+        tx.insert_item(&item(1, 0)).await.unwrap();
+
+        assert!(matches!(
+            tx.insert_item(&item(1, 0)).await,
+            Err(AppError::Conflict(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn votes_outside_minus_one_to_one_are_invalid() {
+        let storage = MemoryStorage::new();
+        let mut tx = storage.begin().await.unwrap();
+        tx.insert_item(&item(1, 0)).await.unwrap();
+
+        assert!(matches!(
+            tx.insert_vote_event(&vote_event(1, 1, 5)).await,
+            Err(AppError::Validation(_))
+        ));
+        assert_eq!(tx.get_current_vote("user", 1).await.unwrap(), None);
+    }
+}
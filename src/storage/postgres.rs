@@ -0,0 +1,65 @@
+use crate::common::error::AppError;
+use crate::storage::{Storage, StorageTransaction};
+use async_trait::async_trait;
+use sqlx::migrate::Migrator;
+use sqlx::postgres::{PgPool, PgPoolOptions, PgRow};
+use sqlx::Postgres;
+
+// The queries in `sql/` are shared with the SQLite backend, so the module
+// is compiled once for each database.
+#[allow(clippy::duplicate_mod)]
+#[path = "sql/mod.rs"]
+mod sql;
+
+pub use sql::SqlTransaction as PostgresTransaction;
+
+type Db = Postgres;
+type Row = PgRow;
+
+mod dialect {
+    use crate::common::error::AppError;
+
+    /// Condition that a column is one of the ids bound as `$1` with
+    /// [`item_ids`].
+    pub const IN_ITEM_IDS: &str = "= any($1)";
+
+    pub fn item_ids(item_ids: &[i32]) -> Result<&[i32], AppError> {
+        Ok(item_ids)
+    }
+}
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations/postgres");
+
+#[derive(Clone)]
+pub struct PostgresStorage {
+    pool: PgPool,
+}
+
+impl PostgresStorage {
+    pub async fn connect(database_url: &str) -> Result<Self, AppError> {
+        let pool = PgPoolOptions::new()
+            .max_connections(10)
+            .connect(database_url)
+            .await?;
+
+        Ok(Self { pool })
+    }
+
+    /// Applies the migrations in `migrations/postgres/` that haven't been run
+    /// yet.
+    pub async fn migrate(&self) -> Result<(), AppError> {
+        MIGRATOR.run(&self.pool).await?;
+        Ok(())
+    }
+
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+}
+
+#[async_trait]
+impl Storage for PostgresStorage {
+    async fn begin(&self) -> Result<Box<dyn StorageTransaction>, AppError> {
+        Ok(Box::new(PostgresTransaction::new(self.pool.begin().await?)))
+    }
+}
@@ -0,0 +1,144 @@
+use super::Db;
+use crate::common::error::AppError;
+use crate::experiments::{Experiment, PageActivity};
+use sqlx::{query, query_as, types::Json, Transaction};
+
+pub async fn insert_experiment(
+    tx: &mut Transaction<'_, Db>,
+    experiment: &Experiment,
+) -> Result<(), AppError> {
+    query(
+        "
+        insert into experiment (
+              experiment_id
+            , arms
+            , created_at
+            , ended_at
+        ) values ($1, $2, $3, $4)
+        ",
+    )
+    .bind(&experiment.experiment_id)
+    .bind(Json(&experiment.arms))
+    .bind(experiment.created_at)
+    .bind(experiment.ended_at)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_experiments(tx: &mut Transaction<'_, Db>) -> Result<Vec<Experiment>, AppError> {
+    let experiments: Vec<Experiment> = query_as(
+        "
+        select
+              experiment_id
+            , arms
+            , created_at
+            , ended_at
+        from experiment
+        order by created_at, experiment_id
+        ",
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+
+    Ok(experiments)
+}
+
+pub async fn get_experiment(
+    tx: &mut Transaction<'_, Db>,
+    experiment_id: &str,
+) -> Result<Option<Experiment>, AppError> {
+    let experiment: Option<Experiment> = query_as(
+        "
+        select
+              experiment_id
+            , arms
+            , created_at
+            , ended_at
+        from experiment
+        where experiment_id = $1
+        ",
+    )
+    .bind(experiment_id)
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    Ok(experiment)
+}
+
+pub async fn get_running_experiment(
+    tx: &mut Transaction<'_, Db>,
+) -> Result<Option<Experiment>, AppError> {
+    let experiment: Option<Experiment> = query_as(
+        "
+        select
+              experiment_id
+            , arms
+            , created_at
+            , ended_at
+        from experiment
+        where ended_at is null
+        ",
+    )
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    Ok(experiment)
+}
+
+pub async fn end_experiment(
+    tx: &mut Transaction<'_, Db>,
+    experiment_id: &str,
+    ended_at: i64,
+) -> Result<u64, AppError> {
+    let result = query(
+        "
+        update experiment
+        set ended_at = $1
+        where experiment_id = $2
+        and ended_at is null
+        ",
+    )
+    .bind(ended_at)
+    .bind(experiment_id)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+pub async fn get_page_activity(
+    tx: &mut Transaction<'_, Db>,
+    page_id: &str,
+    from: i64,
+    until: i64,
+) -> Result<PageActivity, AppError> {
+    let activity: PageActivity = query_as(
+        "
+        select
+              (
+                select cast(coalesce(sum(impressions), 0) as bigint)
+                from impression
+                where page_id = $1
+                and bucket_time >= $2
+                and bucket_time <= $3
+              ) as impressions
+            , (
+                select count(*)
+                from vote_event
+                where page = $1
+                and vote = 1
+                and created_at > $2
+                and created_at <= $3
+              ) as upvotes
+        ",
+    )
+    .bind(page_id)
+    .bind(from)
+    .bind(until)
+    .fetch_one(&mut **tx)
+    .await?;
+
+    Ok(activity)
+}
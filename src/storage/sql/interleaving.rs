@@ -0,0 +1,190 @@
+use super::Db;
+use crate::common::error::AppError;
+use crate::interleaving::{InterleavedPosition, Interleaving, InterleavingCredit};
+use sqlx::{query, query_as, Transaction};
+
+pub async fn insert_interleaving(
+    tx: &mut Transaction<'_, Db>,
+    interleaving: &Interleaving,
+) -> Result<(), AppError> {
+    query(
+        "
+        insert into interleaving (
+              interleaving_id
+            , algorithm_a
+            , algorithm_b
+            , created_at
+        ) values ($1, $2, $3, $4)
+        ",
+    )
+    .bind(&interleaving.interleaving_id)
+    .bind(&interleaving.algorithm_a)
+    .bind(&interleaving.algorithm_b)
+    .bind(interleaving.created_at)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_interleavings(
+    tx: &mut Transaction<'_, Db>,
+) -> Result<Vec<Interleaving>, AppError> {
+    let interleavings: Vec<Interleaving> = query_as(
+        "
+        select
+              interleaving_id
+            , algorithm_a
+            , algorithm_b
+            , created_at
+        from interleaving
+        order by created_at, interleaving_id
+        ",
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+
+    Ok(interleavings)
+}
+
+pub async fn get_interleaving(
+    tx: &mut Transaction<'_, Db>,
+    interleaving_id: &str,
+) -> Result<Option<Interleaving>, AppError> {
+    let interleaving: Option<Interleaving> = query_as(
+        "
+        select
+              interleaving_id
+            , algorithm_a
+            , algorithm_b
+            , created_at
+        from interleaving
+        where interleaving_id = $1
+        ",
+    )
+    .bind(interleaving_id)
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    Ok(interleaving)
+}
+
+pub async fn insert_interleaved_positions(
+    tx: &mut Transaction<'_, Db>,
+    interleaving_id: &str,
+    user_id: &str,
+    positions: &[InterleavedPosition],
+) -> Result<(), AppError> {
+    for position in positions {
+        query(
+            "
+            insert into interleaved_position (
+                  interleaving_id
+                , user_id
+                , served_at
+                , rank
+                , item_id
+                , algorithm
+            ) values ($1, $2, $3, $4, $5, $6)
+            on conflict do nothing
+            ",
+        )
+        .bind(interleaving_id)
+        .bind(user_id)
+        .bind(position.served_at)
+        .bind(position.rank)
+        .bind(position.item_id)
+        .bind(&position.algorithm)
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    Ok(())
+}
+
+pub async fn get_interleaved_position(
+    tx: &mut Transaction<'_, Db>,
+    interleaving_id: &str,
+    user_id: &str,
+    rank: i32,
+    at: i64,
+) -> Result<Option<InterleavedPosition>, AppError> {
+    let position: Option<InterleavedPosition> = query_as(
+        "
+        select
+              served_at
+            , rank
+            , item_id
+            , algorithm
+        from interleaved_position
+        where interleaving_id = $1
+        and user_id = $2
+        and rank = $3
+        and served_at = (
+            select max(served_at)
+            from interleaved_position
+            where interleaving_id = $1
+            and user_id = $2
+            and served_at <= $4
+        )
+        ",
+    )
+    .bind(interleaving_id)
+    .bind(user_id)
+    .bind(rank)
+    .bind(at)
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    Ok(position)
+}
+
+pub async fn insert_interleaving_credit(
+    tx: &mut Transaction<'_, Db>,
+    interleaving_id: &str,
+    credit: &InterleavingCredit,
+) -> Result<(), AppError> {
+    query(
+        "
+        insert into interleaving_credit (
+              vote_event_id
+            , interleaving_id
+            , user_id
+            , served_at
+            , algorithm
+        ) values ($1, $2, $3, $4, $5)
+        ",
+    )
+    .bind(credit.vote_event_id)
+    .bind(interleaving_id)
+    .bind(&credit.user_id)
+    .bind(credit.served_at)
+    .bind(&credit.algorithm)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_interleaving_credits(
+    tx: &mut Transaction<'_, Db>,
+    interleaving_id: &str,
+) -> Result<Vec<InterleavingCredit>, AppError> {
+    let credits: Vec<InterleavingCredit> = query_as(
+        "
+        select
+              vote_event_id
+            , user_id
+            , served_at
+            , algorithm
+        from interleaving_credit
+        where interleaving_id = $1
+        order by vote_event_id
+        ",
+    )
+    .bind(interleaving_id)
+    .fetch_all(&mut **tx)
+    .await?;
+
+    Ok(credits)
+}
@@ -0,0 +1,416 @@
+use super::{dialect, Db};
+use crate::algs::{
+    best::BestStats, hacker_news::HnStats, newest::NewestStats, reddit_hot::HotStats, top::TopStats,
+};
+use crate::common::{
+    error::AppError,
+    model::{Item, ItemAggregate, VoteEvent},
+};
+use sqlx::{query, query_as, query_scalar, Transaction};
+
+pub async fn insert_item(tx: &mut Transaction<'_, Db>, item: &Item) -> Result<(), AppError> {
+    query(
+        "
+        insert into item (
+              item_id
+            , parent_id
+            , author_id
+            , created_at
+        ) values ($1, $2, $3, $4)
+        ",
+    )
+    .bind(item.item_id)
+    .bind(item.parent_id)
+    .bind(&item.author_id)
+    .bind(item.created_at)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn insert_vote_event(
+    tx: &mut Transaction<'_, Db>,
+    vote_event: &VoteEvent,
+) -> Result<(), AppError> {
+    query(
+        "
+        insert into vote_event (
+              vote_event_id
+            , item_id
+            , user_id
+            , vote
+            , rank
+            , page
+            , created_at
+        ) values ($1, $2, $3, $4, $5, $6, $7)
+        ",
+    )
+    .bind(vote_event.vote_event_id)
+    .bind(vote_event.item_id)
+    .bind(&vote_event.user_id)
+    .bind(vote_event.vote)
+    .bind(vote_event.rank)
+    .bind(&vote_event.page)
+    .bind(vote_event.created_at)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn record_impression(
+    tx: &mut Transaction<'_, Db>,
+    page_id: &str,
+    item_id: i32,
+    rank: i32,
+    bucket_time: i64,
+) -> Result<(), AppError> {
+    query(
+        "
+        insert into impression (
+              page_id
+            , item_id
+            , rank
+            , bucket_time
+            , impressions
+        ) values ($1, $2, $3, $4, 1)
+        on conflict (page_id, item_id, rank, bucket_time) do update set
+            impressions = impression.impressions + 1
+        ",
+    )
+    .bind(page_id)
+    .bind(item_id)
+    .bind(rank)
+    .bind(bucket_time)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_newest_stats(
+    tx: &mut Transaction<'_, Db>,
+    pool_size: i32,
+    sample_time: i64,
+) -> Result<Vec<NewestStats>, AppError> {
+    let stats = query_as::<_, NewestStats>(
+        "
+        select
+              item_id
+            , cast($1 as bigint) as sample_time
+            , created_at as submission_time
+        from item
+        order by created_at desc
+        limit $2
+        ",
+    )
+    .bind(sample_time)
+    .bind(pool_size)
+    .fetch_all(&mut **tx)
+    .await?;
+
+    Ok(stats)
+}
+
+pub async fn get_hn_stats(
+    tx: &mut Transaction<'_, Db>,
+    pool_size: i32,
+    sample_time: i64,
+) -> Result<Vec<HnStats>, AppError> {
+    let stats = query_as::<_, HnStats>(
+        "
+        with newest_items as (
+            select *
+            from item
+            order by created_at desc
+            limit $1
+        )
+        , upvote_counts as (
+          select
+              item_id
+            , cast(count(*) as integer) as upvotes
+          from vote
+          where vote = 1
+          group by item_id
+        )
+        select
+            ni.item_id
+          , cast($2 as bigint) as sample_time
+          , ni.created_at as submission_time
+          , coalesce(uc.upvotes, 0) as upvotes
+        from newest_items ni
+        left outer join upvote_counts uc
+        on ni.item_id = uc.item_id
+        ",
+    )
+    .bind(pool_size)
+    .bind(sample_time)
+    .fetch_all(&mut **tx)
+    .await?;
+
+    Ok(stats)
+}
+
+pub async fn get_hot_stats(
+    tx: &mut Transaction<'_, Db>,
+    pool_size: i32,
+) -> Result<Vec<HotStats>, AppError> {
+    let stats = query_as::<_, HotStats>(
+        "
+        with newest_items as (
+            select *
+            from item
+            order by created_at desc
+            limit $1
+        )
+        , vote_counts as (
+          select
+              item_id
+            , cast(count(*) filter (where vote = 1) as integer) as upvotes
+            , cast(count(*) filter (where vote = -1) as integer) as downvotes
+          from vote
+          group by item_id
+        )
+        select
+            ni.item_id
+          , ni.created_at as submission_time
+          , coalesce(vc.upvotes, 0) as upvotes
+          , coalesce(vc.downvotes, 0) as downvotes
+        from newest_items ni
+        left outer join vote_counts vc
+        on ni.item_id = vc.item_id
+        ",
+    )
+    .bind(pool_size)
+    .fetch_all(&mut **tx)
+    .await?;
+
+    Ok(stats)
+}
+
+pub async fn get_best_stats(
+    tx: &mut Transaction<'_, Db>,
+    from: i64,
+    until: i64,
+) -> Result<Vec<BestStats>, AppError> {
+    let stats = query_as::<_, BestStats>(
+        "
+        select
+              item_id
+            , cast(count(*) filter (where vote = 1) as integer) as upvotes
+            , cast(count(*) filter (where vote = -1) as integer) as downvotes
+        from vote
+        where created_at > $1
+        and created_at <= $2
+        and vote in (1, -1)
+        group by item_id
+        ",
+    )
+    .bind(from)
+    .bind(until)
+    .fetch_all(&mut **tx)
+    .await?;
+
+    Ok(stats)
+}
+
+/// Upvotes of the `pool_size` most upvoted items, counting each user's latest
+/// vote in the window from after `from` until `until`, with the expected
+/// upvotes of the samples taken in that window.
+pub async fn get_top_stats(
+    tx: &mut Transaction<'_, Db>,
+    from: i64,
+    until: i64,
+    pool_size: i32,
+) -> Result<Vec<TopStats>, AppError> {
+    let stats = query_as::<_, TopStats>(
+        "
+        with latest_votes as (
+            select
+                  item_id
+                , vote
+                , row_number() over (
+                    partition by item_id, user_id
+                    order by created_at desc, vote_event_id desc
+                  ) as n
+            from vote_event
+            where created_at > $1
+            and created_at <= $2
+        )
+        , upvote_counts as (
+            select
+                  item_id
+                , cast(count(*) as integer) as upvotes
+            from latest_votes
+            where n = 1
+            and vote = 1
+            group by item_id
+            order by upvotes desc, item_id desc
+            limit $3
+        )
+        , window_stats as (
+            select
+                  sh.item_id
+                , sum(sh.expected_upvotes) as expected_upvotes
+                , sum(sh.vote_event_expected_upvotes) as vote_event_expected_upvotes
+                , sum(sh.impression_expected_upvotes) as impression_expected_upvotes
+            from stats_history sh
+            join qn_sample_interval si
+            on sh.interval_id = si.interval_id
+            where si.start_time >= $1
+            and si.start_time < $2
+            and sh.item_id in (select item_id from upvote_counts)
+            group by sh.item_id
+        )
+        select
+              i.item_id
+            , i.created_at as submission_time
+            , uc.upvotes
+            , ws.expected_upvotes
+            , ws.vote_event_expected_upvotes
+            , ws.impression_expected_upvotes
+        from upvote_counts uc
+        join item i
+        on uc.item_id = i.item_id
+        left outer join window_stats ws
+        on uc.item_id = ws.item_id
+        ",
+    )
+    .bind(from)
+    .bind(until)
+    .bind(pool_size)
+    .fetch_all(&mut **tx)
+    .await?;
+
+    Ok(stats)
+}
+
+/// Aggregates of the given items as of now.
+pub async fn get_item_aggregates(
+    tx: &mut Transaction<'_, Db>,
+    item_ids: &[i32],
+) -> Result<Vec<ItemAggregate>, AppError> {
+    let sql = format!(
+        "
+        with upvote_counts as (
+            select
+                  item_id
+                , cast(count(*) filter (where vote = 1) as integer) as upvotes
+                , cast(count(*) filter (where vote = -1) as integer) as downvotes
+                , max(created_at) as last_vote_at
+            from vote
+            group by item_id
+        )
+        select
+              i.item_id
+            , i.parent_id
+            , i.created_at as submission_time
+            , coalesce(uc.upvotes, 0) as upvotes
+            , coalesce(uc.downvotes, 0) as downvotes
+            , s.cumulative_expected_upvotes as expected_upvotes
+            , s.cumulative_vote_event_expected_upvotes as vote_event_expected_upvotes
+            , s.cumulative_impression_expected_upvotes as impression_expected_upvotes
+            , uc.last_vote_at
+        from item i
+        left outer join upvote_counts uc
+        on i.item_id = uc.item_id
+        left outer join stats s
+        on i.item_id = s.item_id
+        where i.item_id {}
+        ",
+        dialect::IN_ITEM_IDS
+    );
+    let aggregates = query_as::<_, ItemAggregate>(&sql)
+        .bind(dialect::item_ids(item_ids)?)
+        .fetch_all(&mut **tx)
+        .await?;
+
+    Ok(aggregates)
+}
+
+/// The user's current vote on the item, if any.
+pub async fn get_current_vote(
+    tx: &mut Transaction<'_, Db>,
+    user_id: &str,
+    item_id: i32,
+) -> Result<Option<i32>, AppError> {
+    let vote: Option<i32> =
+        query_scalar("select vote from vote where user_id = $1 and item_id = $2")
+            .bind(user_id)
+            .bind(item_id)
+            .fetch_optional(&mut **tx)
+            .await?;
+
+    Ok(vote)
+}
+
+pub async fn get_items(tx: &mut Transaction<'_, Db>) -> Result<Vec<Item>, AppError> {
+    let items: Vec<Item> = query_as(
+        "
+        select
+              item_id
+            , parent_id
+            , author_id
+            , created_at
+        from item
+        order by created_at, item_id
+        ",
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+
+    Ok(items)
+}
+
+pub async fn get_thread(tx: &mut Transaction<'_, Db>, item_id: i32) -> Result<Vec<Item>, AppError> {
+    let items: Vec<Item> = query_as(
+        "
+        with recursive thread(item_id) as (
+            select item_id
+            from item
+            where item_id = $1
+            union
+            select i.item_id
+            from item i
+            join thread t
+            on i.parent_id = t.item_id
+        )
+        select
+              i.item_id
+            , i.parent_id
+            , i.author_id
+            , i.created_at
+        from item i
+        join thread t
+        on i.item_id = t.item_id
+        order by i.created_at, i.item_id
+        ",
+    )
+    .bind(item_id)
+    .fetch_all(&mut **tx)
+    .await?;
+
+    Ok(items)
+}
+
+pub async fn get_vote_events(tx: &mut Transaction<'_, Db>) -> Result<Vec<VoteEvent>, AppError> {
+    let vote_events: Vec<VoteEvent> = query_as(
+        "
+        select
+              vote_event_id
+            , item_id
+            , user_id
+            , vote
+            , rank
+            , page
+            , created_at
+        from vote_event
+        order by created_at, vote_event_id
+        ",
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+
+    Ok(vote_events)
+}
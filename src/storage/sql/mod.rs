@@ -0,0 +1,16 @@
+//! Queries of the SQLite and PostgreSQL backends. Each backend includes this
+//! module with its database as `Db` and its rows as `Row`, and `dialect` holds
+//! the SQL that differs between the two.
+
+use super::{dialect, Db, Row};
+
+mod experiments;
+mod interleaving;
+mod items;
+mod pages;
+mod quality_news;
+mod snapshots;
+mod transaction;
+mod upvote_share_model;
+
+pub use transaction::SqlTransaction;
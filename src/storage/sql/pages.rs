@@ -0,0 +1,204 @@
+use super::Db;
+use crate::common::{
+    error::AppError,
+    model::{Page, PageDefinition, PageRank, RankUpvoteShare, ScoredItem},
+};
+use sqlx::{query, query_as, types::Json, Transaction};
+
+pub async fn get_pages(tx: &mut Transaction<'_, Db>) -> Result<Vec<Page>, AppError> {
+    let pages: Vec<Page> = query_as(
+        "
+        select
+              page_id
+            , algorithm
+            , params
+            , created_at
+        from page
+        order by page_id
+        ",
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+
+    Ok(pages)
+}
+
+pub async fn get_page(
+    tx: &mut Transaction<'_, Db>,
+    page_id: &str,
+) -> Result<Option<Page>, AppError> {
+    let page: Option<Page> = query_as(
+        "
+        select
+              page_id
+            , algorithm
+            , params
+            , created_at
+        from page
+        where page_id = $1
+        ",
+    )
+    .bind(page_id)
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    Ok(page)
+}
+
+pub async fn insert_page(
+    tx: &mut Transaction<'_, Db>,
+    page: &PageDefinition,
+) -> Result<Page, AppError> {
+    let page: Page = query_as(
+        "
+        insert into page (
+              page_id
+            , algorithm
+            , params
+        ) values ($1, $2, $3)
+        returning *
+        ",
+    )
+    .bind(&page.page_id)
+    .bind(&page.algorithm)
+    .bind(Json(&page.params))
+    .fetch_one(&mut **tx)
+    .await?;
+
+    Ok(page)
+}
+
+pub async fn delete_page(tx: &mut Transaction<'_, Db>, page_id: &str) -> Result<u64, AppError> {
+    let result = query("delete from page where page_id = $1")
+        .bind(page_id)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Attributes the upvotes cast on a page since its latest rank observation to
+/// the observed items.
+pub async fn close_rank_window(
+    tx: &mut Transaction<'_, Db>,
+    page_id: &str,
+    sample_time: i64,
+) -> Result<(), AppError> {
+    query(
+        "
+        update page_rank_history
+        set upvotes = (
+            select count(*)
+            from vote_event ve
+            where ve.page = page_rank_history.page_id
+            and ve.item_id = page_rank_history.item_id
+            and ve.vote = 1
+            and ve.created_at > page_rank_history.sample_time
+            and ve.created_at <= $1
+        )
+        where page_id = $2
+        and upvotes is null
+        and sample_time = (
+            select max(sample_time)
+            from page_rank_history
+            where page_id = $3
+        )
+        ",
+    )
+    .bind(sample_time)
+    .bind(page_id)
+    .bind(page_id)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn insert_page_ranks(
+    tx: &mut Transaction<'_, Db>,
+    page_id: &str,
+    sample_time: i64,
+    ranking: &[ScoredItem],
+) -> Result<(), AppError> {
+    for r in ranking {
+        query(
+            "
+            insert into page_rank_history (
+                  page_id
+                , sample_time
+                , item_id
+                , rank
+            ) values ($1, $2, $3, $4)
+            ",
+        )
+        .bind(page_id)
+        .bind(sample_time)
+        .bind(r.item_id)
+        .bind(r.rank)
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    Ok(())
+}
+
+pub async fn get_upvote_shares_by_rank(
+    tx: &mut Transaction<'_, Db>,
+    page_id: &str,
+) -> Result<Vec<RankUpvoteShare>, AppError> {
+    let shares: Vec<RankUpvoteShare> = query_as(
+        "
+        with upvotes_by_rank as (
+            select
+                  rank
+                , cast(sum(upvotes) as integer) as upvotes
+            from page_rank_history
+            where page_id = $1
+            and upvotes is not null
+            group by rank
+        )
+        select
+              rank
+            , upvotes
+            , cast(coalesce(
+                  cast(upvotes as real) / nullif(sum(upvotes) over (), 0)
+                , 0.0
+            ) as real) as upvote_share
+        from upvotes_by_rank
+        order by rank
+        ",
+    )
+    .bind(page_id)
+    .fetch_all(&mut **tx)
+    .await?;
+
+    Ok(shares)
+}
+
+pub async fn get_page_rank_history(
+    tx: &mut Transaction<'_, Db>,
+    page_id: &str,
+    from: i64,
+    until: i64,
+) -> Result<Vec<PageRank>, AppError> {
+    let ranks: Vec<PageRank> = query_as(
+        "
+        select
+              sample_time
+            , item_id
+            , rank
+        from page_rank_history
+        where page_id = $1
+        and sample_time >= $2
+        and sample_time <= $3
+        order by sample_time, rank
+        ",
+    )
+    .bind(page_id)
+    .bind(from)
+    .bind(until)
+    .fetch_all(&mut **tx)
+    .await?;
+
+    Ok(ranks)
+}
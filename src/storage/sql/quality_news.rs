@@ -0,0 +1,537 @@
+use super::{Db, Row as DbRow};
+use crate::algs::quality_news::model::{
+    ImpressionExpectedUpvotes, ItemWithRanks, QnSample, QnSampleInterval, QnSampleWithPrediction,
+    QnStats, RankProfileEntry, VoteEventRank,
+};
+use crate::algs::quality_news::ExpectedUpvotes;
+use crate::common::{error::AppError, model::IMPRESSION_BUCKET_MILLIS};
+use sqlx::{query, query_as, query_scalar, FromRow, Row, Transaction};
+
+impl<'r> FromRow<'r, DbRow> for QnSample {
+    fn from_row(row: &'r DbRow) -> Result<Self, sqlx::Error> {
+        Ok(QnSample {
+            item_id: row.try_get("item_id")?,
+            interval: QnSampleInterval {
+                interval_id: row.try_get("interval_id")?,
+                start_time: row.try_get("start_time")?,
+            },
+            sample_time: row.try_get("sample_time")?,
+            submission_time: row.try_get("submission_time")?,
+            upvotes: row.try_get("upvotes")?,
+            upvote_share: row.try_get("upvote_share")?,
+        })
+    }
+}
+
+pub async fn insert_sample_interval(
+    tx: &mut Transaction<'_, Db>,
+    start_time: i64,
+) -> Result<QnSampleInterval, AppError> {
+    let sample_interval: QnSampleInterval = query_as(
+        "
+        insert into qn_sample_interval (start_time)
+        values ($1)
+        returning *
+        ",
+    )
+    .bind(start_time)
+    .fetch_one(&mut **tx)
+    .await?;
+
+    Ok(sample_interval)
+}
+
+pub async fn get_sitewide_upvotes_in_interval(
+    tx: &mut Transaction<'_, Db>,
+    sample_time: i64,
+    previous_sample_time: i64,
+) -> Result<i32, AppError> {
+    let sitewide_upvotes: i32 = query_scalar(
+        "
+        with item_pool as (
+            select item_id
+            from item
+            where created_at <= $1
+            and parent_id is null
+            order by created_at desc
+            limit 1500
+        )
+        select cast(count(*) as integer)
+        from vote v
+        join item_pool i
+        on v.item_id = i.item_id
+        where vote = 1
+        and created_at <= $2
+        and created_at > $3
+        ",
+    )
+    .bind(sample_time)
+    .bind(sample_time)
+    .bind(previous_sample_time)
+    .fetch_one(&mut **tx)
+    .await?;
+
+    Ok(sitewide_upvotes)
+}
+
+pub async fn insert_rank_observations(
+    tx: &mut Transaction<'_, Db>,
+    ranked_items: &[ItemWithRanks],
+    sample_interval: &QnSampleInterval,
+    sample_time: i64,
+) -> Result<(), AppError> {
+    for r in ranked_items {
+        query(
+            "
+            insert into rank_history (
+                  item_id
+                , interval_id
+                , rank_top
+                , rank_new
+                , sample_time
+            ) values ($1, $2, $3, $4, $5)
+            ",
+        )
+        .bind(r.item_id)
+        .bind(sample_interval.interval_id)
+        .bind(r.rank_top)
+        .bind(r.rank_new)
+        .bind(sample_time)
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Aggregates the rank observations of an interval into the time each item
+/// spent at each combination of ranks. Every observation holds until the next
+/// one, the last one until the end of the interval.
+pub async fn get_rank_profiles(
+    tx: &mut Transaction<'_, Db>,
+    interval: &QnSampleInterval,
+    end_time: i64,
+) -> Result<Vec<RankProfileEntry>, AppError> {
+    let profiles = query_as::<_, RankProfileEntry>(
+        "
+        with observation_times as (
+            select distinct sample_time
+            from rank_history
+            where interval_id = $1
+        )
+        , observation_durations as (
+            select
+                  sample_time
+                , coalesce(
+                      lead(sample_time) over (order by sample_time)
+                    , $2
+                ) - sample_time as duration
+            from observation_times
+        )
+        select
+              r.item_id
+            , r.interval_id
+            , r.rank_top
+            , r.rank_new
+            , cast(sum(d.duration) as bigint) as duration
+        from rank_history r
+        join observation_durations d
+        on r.sample_time = d.sample_time
+        where r.interval_id = $3
+        group by r.item_id, r.interval_id, r.rank_top, r.rank_new
+        ",
+    )
+    .bind(interval.interval_id)
+    .bind(end_time)
+    .bind(interval.interval_id)
+    .fetch_all(&mut **tx)
+    .await?;
+
+    Ok(profiles)
+}
+
+pub async fn insert_rank_profiles(
+    tx: &mut Transaction<'_, Db>,
+    profiles: &[RankProfileEntry],
+) -> Result<(), AppError> {
+    for p in profiles {
+        query(
+            "
+            insert into rank_profile (
+                  item_id
+                , interval_id
+                , rank_top
+                , rank_new
+                , duration
+            ) values ($1, $2, $3, $4, $5)
+            ",
+        )
+        .bind(p.item_id)
+        .bind(p.interval_id)
+        .bind(p.rank_top)
+        .bind(p.rank_new)
+        .bind(p.duration)
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    Ok(())
+}
+
+pub async fn get_latest_sample_interval(
+    tx: &mut Transaction<'_, Db>,
+) -> Result<QnSampleInterval, AppError> {
+    let latest_interval: QnSampleInterval = query_as(
+        "
+        select
+              interval_id
+            , start_time
+        from qn_sample_interval
+        where interval_id = (
+            select max(interval_id)
+            from qn_sample_interval
+        )
+        ",
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+    Ok(latest_interval)
+}
+
+pub async fn get_stats(
+    tx: &mut Transaction<'_, Db>,
+    sample_time: i64,
+    expected_upvotes: ExpectedUpvotes,
+) -> Result<Vec<QnStats>, AppError> {
+    if !is_sampling_initialized(tx).await? {
+        let stats = query_as::<_, QnStats>(
+            "
+            with item_pool as (
+                select
+                      item_id
+                    , created_at as submission_time
+                from item
+                where created_at <= $1
+                and parent_id is null
+                order by created_at desc
+                limit 1500
+            )
+            , upvote_counts as (
+                select
+                      item_id
+                    , cast(count(*) as integer) as cumulative_upvotes
+                from vote
+                where created_at <= $2
+                and vote = 1
+                group by item_id
+            )
+            select
+                  ip.item_id
+                , cast($3 as bigint) as updated_at
+                , cast($4 as bigint) as sample_time
+                , ip.submission_time
+                , coalesce(uc.cumulative_upvotes, 0) as cumulative_upvotes
+                , cast(coalesce(uc.cumulative_upvotes, 0) as real) as cumulative_expected_upvotes
+            from item_pool ip
+            left outer join upvote_counts uc
+            on ip.item_id = uc.item_id
+            ",
+        )
+        .bind(sample_time)
+        .bind(sample_time)
+        .bind(sample_time)
+        .bind(sample_time)
+        .fetch_all(&mut **tx)
+        .await?;
+
+        return Ok(stats);
+    }
+
+    let stats = query_as::<_, QnStats>(
+        "
+        with item_pool as (
+            select
+                  item_id
+                , created_at as submission_time
+            from item
+            where created_at <= $1
+            and parent_id is null
+            order by created_at desc
+            limit 1500
+        )
+        , upvote_counts as (
+            select
+                  item_id
+                , cast(count(*) as integer) as cumulative_upvotes
+            from vote
+            where created_at <= $2
+            and vote = 1
+            group by item_id
+        )
+        select
+              ip.item_id
+            , coalesce(s.updated_at, $3) as updated_at
+            , cast($4 as bigint) as sample_time
+            , ip.submission_time
+            , coalesce(uc.cumulative_upvotes, 0) as cumulative_upvotes
+            , coalesce(
+                  case cast($5 as text)
+                      when 'vote_events' then s.cumulative_vote_event_expected_upvotes
+                      when 'impressions' then s.cumulative_impression_expected_upvotes
+                      else s.cumulative_expected_upvotes
+                  end
+                , cast(coalesce(uc.cumulative_upvotes, 0) as real)
+            ) as cumulative_expected_upvotes
+        from item_pool ip
+        left outer join upvote_counts uc
+        on ip.item_id = uc.item_id
+        left outer join stats s
+        on ip.item_id = s.item_id
+        ",
+    )
+    .bind(sample_time)
+    .bind(sample_time)
+    .bind(sample_time)
+    .bind(sample_time)
+    .bind(expected_upvotes.as_str())
+    .fetch_all(&mut **tx)
+    .await?;
+
+    Ok(stats)
+}
+
+pub async fn insert_sample(
+    tx: &mut Transaction<'_, Db>,
+    stats: &QnSampleWithPrediction,
+) -> Result<(), AppError> {
+    query(
+        "
+        insert into stats_history (
+              item_id
+            , interval_id
+            , upvotes
+            , upvote_share
+            , expected_upvotes
+            , expected_upvote_share
+            , vote_event_expected_upvotes
+            , vote_event_expected_upvote_share
+            , impression_expected_upvotes
+        )
+        values ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        ",
+    )
+    .bind(stats.sample.item_id)
+    .bind(stats.sample.interval.interval_id)
+    .bind(stats.sample.upvotes)
+    .bind(stats.sample.upvote_share)
+    .bind(stats.expected_upvotes)
+    .bind(stats.expected_upvote_share)
+    .bind(stats.vote_event_expected_upvotes)
+    .bind(stats.vote_event_expected_upvote_share)
+    .bind(stats.impression_expected_upvotes)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_vote_event_ranks_in_interval(
+    tx: &mut Transaction<'_, Db>,
+    interval: &QnSampleInterval,
+    sample_time: i64,
+    max_rank: i32,
+) -> Result<Vec<VoteEventRank>, AppError> {
+    let vote_event_ranks = query_as::<_, VoteEventRank>(
+        "
+        with upvote as (
+            select
+                  vote_event_id
+                , item_id
+                , page
+                , rank
+                , (
+                    select max(sample_time)
+                    from page_rank_history h
+                    where h.page_id = vote_event.page
+                    and h.sample_time <= vote_event.created_at
+                ) as page_sample_time
+            from vote_event
+            where vote = 1
+            and page is not null
+            and rank is not null
+            and created_at > $1
+            and created_at <= $2
+        )
+        select
+              vote_event_id
+            , item_id
+            , page as page_id
+            , rank
+        from upvote
+        union all
+        select
+              u.vote_event_id
+            , h.item_id
+            , h.page_id
+            , h.rank
+        from upvote u
+        join page_rank_history h
+        on h.page_id = u.page
+        and h.sample_time = u.page_sample_time
+        where h.item_id != u.item_id
+        and h.rank <= $3
+        ",
+    )
+    .bind(interval.start_time)
+    .bind(sample_time)
+    .bind(max_rank)
+    .fetch_all(&mut **tx)
+    .await?;
+
+    Ok(vote_event_ranks)
+}
+
+/// Expected upvotes of each item shown during the interval, from the
+/// impressions reported by clients. Each impression is worth the upvotes per
+/// impression at its page and rank since `lookback_start`, pooled over all
+/// items. Items without impressions in the interval are omitted.
+pub async fn get_impression_expected_upvotes_in_interval(
+    tx: &mut Transaction<'_, Db>,
+    interval: &QnSampleInterval,
+    sample_time: i64,
+    lookback_start: i64,
+) -> Result<Vec<ImpressionExpectedUpvotes>, AppError> {
+    let bucket = |time: i64| time - time.rem_euclid(IMPRESSION_BUCKET_MILLIS);
+
+    let expected_upvotes = query_as::<_, ImpressionExpectedUpvotes>(
+        "
+        with impressions_by_rank as (
+            select
+                  page_id
+                , rank
+                , sum(impressions) as impressions
+            from impression
+            where bucket_time >= $1
+            and bucket_time < $2
+            group by page_id, rank
+        )
+        , upvotes_by_rank as (
+            select
+                  page as page_id
+                , rank
+                , count(*) as upvotes
+            from vote_event
+            where vote = 1
+            and page is not null
+            and rank is not null
+            and created_at > $3
+            and created_at <= $4
+            group by page, rank
+        )
+        , upvote_rates as (
+            select
+                  i.page_id
+                , i.rank
+                , coalesce(u.upvotes, 0) * 1.0 / i.impressions as upvotes_per_impression
+            from impressions_by_rank i
+            left outer join upvotes_by_rank u
+            on i.page_id = u.page_id
+            and i.rank = u.rank
+        )
+        select
+              im.item_id
+            , cast(sum(im.impressions * r.upvotes_per_impression) as real) as expected_upvotes
+        from impression im
+        join upvote_rates r
+        on im.page_id = r.page_id
+        and im.rank = r.rank
+        where im.bucket_time >= $5
+        and im.bucket_time < $6
+        group by im.item_id
+        ",
+    )
+    .bind(bucket(lookback_start))
+    .bind(bucket(sample_time))
+    .bind(lookback_start)
+    .bind(sample_time)
+    .bind(bucket(interval.start_time))
+    .bind(bucket(sample_time))
+    .fetch_all(&mut **tx)
+    .await?;
+
+    Ok(expected_upvotes)
+}
+
+pub async fn get_sample_in_interval(
+    tx: &mut Transaction<'_, Db>,
+    interval: &QnSampleInterval,
+    sample_time: i64,
+) -> Result<Vec<QnSample>, AppError> {
+    let sitewide_upvotes =
+        get_sitewide_upvotes_in_interval(tx, sample_time, interval.start_time).await? as f32;
+    let start_time = interval.start_time;
+    let interval_id = interval.interval_id;
+
+    let stats = query_as::<_, QnSample>(
+        "
+        with upvotes_by_item_in_interval as (
+            select
+                  item_id
+                , cast(count(*) as integer) as upvotes
+            from vote
+            where created_at > $1
+            and created_at <= $2
+            group by item_id
+        )
+        , item_pool as (
+            select
+                  item_id
+                , cast($3 as integer) as interval_id
+                , created_at as submission_time
+            from item
+            where created_at <= $4
+            and parent_id is null
+            order by created_at desc
+            limit 1500
+        )
+        select
+              i.item_id
+            , i.interval_id
+            , cast($5 as bigint) as start_time
+            , cast($6 as bigint) as sample_time
+            , i.submission_time
+            , coalesce(u.upvotes, 0) as upvotes
+            , cast(coalesce(u.upvotes / nullif(cast($7 as real), 0), 0) as real) as upvote_share
+        from item_pool i
+        left outer join upvotes_by_item_in_interval u
+        on i.item_id = u.item_id
+        ",
+    )
+    .bind(start_time)
+    .bind(sample_time)
+    .bind(interval_id)
+    .bind(sample_time)
+    .bind(start_time)
+    .bind(sample_time)
+    .bind(sitewide_upvotes)
+    .fetch_all(&mut **tx)
+    .await?;
+
+    Ok(stats)
+}
+
+pub async fn has_items(tx: &mut Transaction<'_, Db>) -> Result<bool, AppError> {
+    let item = query_scalar::<_, i32>("select 1 from item limit 1")
+        .fetch_optional(&mut **tx)
+        .await?;
+
+    Ok(item.is_some())
+}
+
+pub async fn is_sampling_initialized(tx: &mut Transaction<'_, Db>) -> Result<bool, AppError> {
+    let interval = query_scalar::<_, i32>("select 1 from qn_sample_interval limit 1")
+        .fetch_optional(&mut **tx)
+        .await?;
+
+    Ok(interval.is_some())
+}
@@ -0,0 +1,62 @@
+use super::Db;
+use crate::common::error::AppError;
+use crate::snapshots::RankingSnapshot;
+use sqlx::{query, query_as, Transaction};
+use std::sync::Arc;
+
+/// Stores each snapshot as the latest one of its ranking.
+pub async fn upsert_snapshots(
+    tx: &mut Transaction<'_, Db>,
+    snapshots: &[Arc<RankingSnapshot>],
+) -> Result<(), AppError> {
+    for snapshot in snapshots {
+        query(
+            "
+            insert into ranking_snapshot (
+                  ranking
+                , snapshot_id
+                , computed_at
+                , items
+            ) values ($1, $2, $3, $4)
+            on conflict (ranking) do update set
+                  snapshot_id = excluded.snapshot_id
+                , computed_at = excluded.computed_at
+                , items       = excluded.items
+            ",
+        )
+        .bind(&snapshot.ranking)
+        .bind(snapshot.snapshot_id as i64)
+        .bind(snapshot.computed_at)
+        .bind(serde_json::to_string(&snapshot.items)?)
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    Ok(())
+}
+
+pub async fn get_snapshots(tx: &mut Transaction<'_, Db>) -> Result<Vec<RankingSnapshot>, AppError> {
+    let rows: Vec<(String, i64, i64, String)> = query_as(
+        "
+        select
+              ranking
+            , snapshot_id
+            , computed_at
+            , items
+        from ranking_snapshot
+        ",
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+
+    rows.into_iter()
+        .map(|(ranking, snapshot_id, computed_at, items)| {
+            Ok(RankingSnapshot {
+                snapshot_id: snapshot_id as u64,
+                ranking,
+                computed_at,
+                items: serde_json::from_str(&items)?,
+            })
+        })
+        .collect()
+}
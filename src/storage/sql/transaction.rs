@@ -0,0 +1,476 @@
+use super::{
+    experiments, interleaving, items, pages, quality_news, snapshots, upvote_share_model, Db,
+};
+use crate::algs::{
+    best::BestStats,
+    hacker_news::HnStats,
+    newest::NewestStats,
+    quality_news::{
+        model::{
+            ImpressionExpectedUpvotes, ItemWithRanks, QnSample, QnSampleInterval,
+            QnSampleWithPrediction, QnStats, RankProfileEntry, VoteEventRank,
+        },
+        ExpectedUpvotes,
+    },
+    reddit_hot::HotStats,
+    top::TopStats,
+};
+use crate::common::{
+    error::AppError,
+    model::{
+        Item, ItemAggregate, Page, PageDefinition, PageRank, RankUpvoteShare, ScoredItem, VoteEvent,
+    },
+};
+use crate::experiments::{Experiment, PageActivity};
+use crate::interleaving::{InterleavedPosition, Interleaving, InterleavingCredit};
+use crate::snapshots::RankingSnapshot;
+use crate::storage::StorageTransaction;
+use crate::upvote_share_model::{
+    PageCoefficients, RankObservation, UpvoteShareModel, UpvoteShareModelVersion,
+};
+use async_trait::async_trait;
+use sqlx::{query, Transaction};
+use std::sync::Arc;
+
+/// A transaction of the backend. Each operation runs the backend's queries on
+/// it.
+pub struct SqlTransaction {
+    tx: Transaction<'static, Db>,
+}
+
+impl SqlTransaction {
+    pub(crate) fn new(tx: Transaction<'static, Db>) -> Self {
+        Self { tx }
+    }
+}
+
+#[async_trait]
+impl StorageTransaction for SqlTransaction {
+    async fn commit(self: Box<Self>) -> Result<(), AppError> {
+        self.tx.commit().await?;
+        Ok(())
+    }
+
+    async fn rollback(self: Box<Self>) -> Result<(), AppError> {
+        self.tx.rollback().await?;
+        Ok(())
+    }
+
+    async fn savepoint(&mut self) -> Result<(), AppError> {
+        query("savepoint step").execute(&mut *self.tx).await?;
+        Ok(())
+    }
+
+    async fn release_savepoint(&mut self) -> Result<(), AppError> {
+        query("release savepoint step")
+            .execute(&mut *self.tx)
+            .await?;
+        Ok(())
+    }
+
+    async fn rollback_to_savepoint(&mut self) -> Result<(), AppError> {
+        query("rollback to savepoint step")
+            .execute(&mut *self.tx)
+            .await?;
+        query("release savepoint step")
+            .execute(&mut *self.tx)
+            .await?;
+        Ok(())
+    }
+
+    async fn insert_item(&mut self, item: &Item) -> Result<(), AppError> {
+        items::insert_item(&mut self.tx, item).await
+    }
+
+    async fn insert_vote_event(&mut self, vote_event: &VoteEvent) -> Result<(), AppError> {
+        items::insert_vote_event(&mut self.tx, vote_event).await
+    }
+
+    async fn get_current_vote(
+        &mut self,
+        user_id: &str,
+        item_id: i32,
+    ) -> Result<Option<i32>, AppError> {
+        items::get_current_vote(&mut self.tx, user_id, item_id).await
+    }
+
+    async fn record_impression(
+        &mut self,
+        page_id: &str,
+        item_id: i32,
+        rank: i32,
+        bucket_time: i64,
+    ) -> Result<(), AppError> {
+        items::record_impression(&mut self.tx, page_id, item_id, rank, bucket_time).await
+    }
+
+    async fn get_newest_stats(
+        &mut self,
+        pool_size: i32,
+        sample_time: i64,
+    ) -> Result<Vec<NewestStats>, AppError> {
+        items::get_newest_stats(&mut self.tx, pool_size, sample_time).await
+    }
+
+    async fn get_hn_stats(
+        &mut self,
+        pool_size: i32,
+        sample_time: i64,
+    ) -> Result<Vec<HnStats>, AppError> {
+        items::get_hn_stats(&mut self.tx, pool_size, sample_time).await
+    }
+
+    async fn get_hot_stats(&mut self, pool_size: i32) -> Result<Vec<HotStats>, AppError> {
+        items::get_hot_stats(&mut self.tx, pool_size).await
+    }
+
+    async fn get_best_stats(&mut self, from: i64, until: i64) -> Result<Vec<BestStats>, AppError> {
+        items::get_best_stats(&mut self.tx, from, until).await
+    }
+
+    async fn get_top_stats(
+        &mut self,
+        from: i64,
+        until: i64,
+        pool_size: i32,
+    ) -> Result<Vec<TopStats>, AppError> {
+        items::get_top_stats(&mut self.tx, from, until, pool_size).await
+    }
+
+    async fn get_item_aggregates(
+        &mut self,
+        item_ids: &[i32],
+    ) -> Result<Vec<ItemAggregate>, AppError> {
+        items::get_item_aggregates(&mut self.tx, item_ids).await
+    }
+
+    async fn get_items(&mut self) -> Result<Vec<Item>, AppError> {
+        items::get_items(&mut self.tx).await
+    }
+
+    async fn get_thread(&mut self, item_id: i32) -> Result<Vec<Item>, AppError> {
+        items::get_thread(&mut self.tx, item_id).await
+    }
+
+    async fn get_vote_events(&mut self) -> Result<Vec<VoteEvent>, AppError> {
+        items::get_vote_events(&mut self.tx).await
+    }
+
+    async fn get_pages(&mut self) -> Result<Vec<Page>, AppError> {
+        pages::get_pages(&mut self.tx).await
+    }
+
+    async fn get_page(&mut self, page_id: &str) -> Result<Option<Page>, AppError> {
+        pages::get_page(&mut self.tx, page_id).await
+    }
+
+    async fn insert_page(&mut self, page: &PageDefinition) -> Result<Page, AppError> {
+        pages::insert_page(&mut self.tx, page).await
+    }
+
+    async fn delete_page(&mut self, page_id: &str) -> Result<u64, AppError> {
+        pages::delete_page(&mut self.tx, page_id).await
+    }
+
+    async fn close_rank_window(&mut self, page_id: &str, sample_time: i64) -> Result<(), AppError> {
+        pages::close_rank_window(&mut self.tx, page_id, sample_time).await
+    }
+
+    async fn insert_page_ranks(
+        &mut self,
+        page_id: &str,
+        sample_time: i64,
+        ranking: &[ScoredItem],
+    ) -> Result<(), AppError> {
+        pages::insert_page_ranks(&mut self.tx, page_id, sample_time, ranking).await
+    }
+
+    async fn get_upvote_shares_by_rank(
+        &mut self,
+        page_id: &str,
+    ) -> Result<Vec<RankUpvoteShare>, AppError> {
+        pages::get_upvote_shares_by_rank(&mut self.tx, page_id).await
+    }
+
+    async fn get_page_rank_history(
+        &mut self,
+        page_id: &str,
+        from: i64,
+        until: i64,
+    ) -> Result<Vec<PageRank>, AppError> {
+        pages::get_page_rank_history(&mut self.tx, page_id, from, until).await
+    }
+
+    async fn has_items(&mut self) -> Result<bool, AppError> {
+        quality_news::has_items(&mut self.tx).await
+    }
+
+    async fn is_sampling_initialized(&mut self) -> Result<bool, AppError> {
+        quality_news::is_sampling_initialized(&mut self.tx).await
+    }
+
+    async fn insert_sample_interval(
+        &mut self,
+        start_time: i64,
+    ) -> Result<QnSampleInterval, AppError> {
+        quality_news::insert_sample_interval(&mut self.tx, start_time).await
+    }
+
+    async fn get_latest_sample_interval(&mut self) -> Result<QnSampleInterval, AppError> {
+        quality_news::get_latest_sample_interval(&mut self.tx).await
+    }
+
+    async fn get_sitewide_upvotes_in_interval(
+        &mut self,
+        sample_time: i64,
+        previous_sample_time: i64,
+    ) -> Result<i32, AppError> {
+        quality_news::get_sitewide_upvotes_in_interval(
+            &mut self.tx,
+            sample_time,
+            previous_sample_time,
+        )
+        .await
+    }
+
+    async fn insert_rank_observations(
+        &mut self,
+        ranked_items: &[ItemWithRanks],
+        sample_interval: &QnSampleInterval,
+        sample_time: i64,
+    ) -> Result<(), AppError> {
+        quality_news::insert_rank_observations(
+            &mut self.tx,
+            ranked_items,
+            sample_interval,
+            sample_time,
+        )
+        .await
+    }
+
+    async fn get_rank_profiles(
+        &mut self,
+        interval: &QnSampleInterval,
+        end_time: i64,
+    ) -> Result<Vec<RankProfileEntry>, AppError> {
+        quality_news::get_rank_profiles(&mut self.tx, interval, end_time).await
+    }
+
+    async fn insert_rank_profiles(
+        &mut self,
+        profiles: &[RankProfileEntry],
+    ) -> Result<(), AppError> {
+        quality_news::insert_rank_profiles(&mut self.tx, profiles).await
+    }
+
+    async fn get_qn_stats(
+        &mut self,
+        sample_time: i64,
+        expected_upvotes: ExpectedUpvotes,
+    ) -> Result<Vec<QnStats>, AppError> {
+        quality_news::get_stats(&mut self.tx, sample_time, expected_upvotes).await
+    }
+
+    async fn insert_sample(&mut self, sample: &QnSampleWithPrediction) -> Result<(), AppError> {
+        quality_news::insert_sample(&mut self.tx, sample).await
+    }
+
+    async fn get_vote_event_ranks_in_interval(
+        &mut self,
+        interval: &QnSampleInterval,
+        sample_time: i64,
+        max_rank: i32,
+    ) -> Result<Vec<VoteEventRank>, AppError> {
+        quality_news::get_vote_event_ranks_in_interval(
+            &mut self.tx,
+            interval,
+            sample_time,
+            max_rank,
+        )
+        .await
+    }
+
+    async fn get_impression_expected_upvotes_in_interval(
+        &mut self,
+        interval: &QnSampleInterval,
+        sample_time: i64,
+        lookback_start: i64,
+    ) -> Result<Vec<ImpressionExpectedUpvotes>, AppError> {
+        quality_news::get_impression_expected_upvotes_in_interval(
+            &mut self.tx,
+            interval,
+            sample_time,
+            lookback_start,
+        )
+        .await
+    }
+
+    async fn get_sample_in_interval(
+        &mut self,
+        interval: &QnSampleInterval,
+        sample_time: i64,
+    ) -> Result<Vec<QnSample>, AppError> {
+        quality_news::get_sample_in_interval(&mut self.tx, interval, sample_time).await
+    }
+
+    async fn get_rank_observations(
+        &mut self,
+        training_start: i64,
+        training_end: i64,
+    ) -> Result<Vec<RankObservation>, AppError> {
+        upvote_share_model::get_rank_observations(&mut self.tx, training_start, training_end).await
+    }
+
+    async fn insert_model(
+        &mut self,
+        created_at: i64,
+        training_start: i64,
+        training_end: i64,
+        coefficients: &[PageCoefficients],
+    ) -> Result<UpvoteShareModel, AppError> {
+        upvote_share_model::insert_model(
+            &mut self.tx,
+            created_at,
+            training_start,
+            training_end,
+            coefficients,
+        )
+        .await
+    }
+
+    async fn get_model_versions(&mut self) -> Result<Vec<UpvoteShareModelVersion>, AppError> {
+        upvote_share_model::get_model_versions(&mut self.tx).await
+    }
+
+    async fn get_model(
+        &mut self,
+        model_version: i64,
+    ) -> Result<Option<UpvoteShareModel>, AppError> {
+        upvote_share_model::get_model(&mut self.tx, model_version).await
+    }
+
+    async fn get_active_model(&mut self) -> Result<Option<UpvoteShareModel>, AppError> {
+        upvote_share_model::get_active_model(&mut self.tx).await
+    }
+
+    async fn set_active_model(
+        &mut self,
+        model_version: i64,
+        activated_at: i64,
+        pinned: bool,
+    ) -> Result<(), AppError> {
+        upvote_share_model::set_active_model(&mut self.tx, model_version, activated_at, pinned)
+            .await
+    }
+
+    async fn is_active_model_pinned(&mut self) -> Result<bool, AppError> {
+        upvote_share_model::is_active_model_pinned(&mut self.tx).await
+    }
+
+    async fn get_training_history(&mut self) -> Result<(i64, Option<i64>), AppError> {
+        upvote_share_model::get_training_history(&mut self.tx).await
+    }
+
+    async fn upsert_snapshots(
+        &mut self,
+        snapshots: &[Arc<RankingSnapshot>],
+    ) -> Result<(), AppError> {
+        snapshots::upsert_snapshots(&mut self.tx, snapshots).await
+    }
+
+    async fn get_snapshots(&mut self) -> Result<Vec<RankingSnapshot>, AppError> {
+        snapshots::get_snapshots(&mut self.tx).await
+    }
+
+    async fn insert_experiment(&mut self, experiment: &Experiment) -> Result<(), AppError> {
+        experiments::insert_experiment(&mut self.tx, experiment).await
+    }
+
+    async fn get_experiments(&mut self) -> Result<Vec<Experiment>, AppError> {
+        experiments::get_experiments(&mut self.tx).await
+    }
+
+    async fn get_experiment(
+        &mut self,
+        experiment_id: &str,
+    ) -> Result<Option<Experiment>, AppError> {
+        experiments::get_experiment(&mut self.tx, experiment_id).await
+    }
+
+    async fn get_running_experiment(&mut self) -> Result<Option<Experiment>, AppError> {
+        experiments::get_running_experiment(&mut self.tx).await
+    }
+
+    async fn end_experiment(
+        &mut self,
+        experiment_id: &str,
+        ended_at: i64,
+    ) -> Result<u64, AppError> {
+        experiments::end_experiment(&mut self.tx, experiment_id, ended_at).await
+    }
+
+    async fn get_page_activity(
+        &mut self,
+        page_id: &str,
+        from: i64,
+        until: i64,
+    ) -> Result<PageActivity, AppError> {
+        experiments::get_page_activity(&mut self.tx, page_id, from, until).await
+    }
+
+    async fn insert_interleaving(&mut self, interleaving: &Interleaving) -> Result<(), AppError> {
+        interleaving::insert_interleaving(&mut self.tx, interleaving).await
+    }
+
+    async fn get_interleavings(&mut self) -> Result<Vec<Interleaving>, AppError> {
+        interleaving::get_interleavings(&mut self.tx).await
+    }
+
+    async fn get_interleaving(
+        &mut self,
+        interleaving_id: &str,
+    ) -> Result<Option<Interleaving>, AppError> {
+        interleaving::get_interleaving(&mut self.tx, interleaving_id).await
+    }
+
+    async fn insert_interleaved_positions(
+        &mut self,
+        interleaving_id: &str,
+        user_id: &str,
+        positions: &[InterleavedPosition],
+    ) -> Result<(), AppError> {
+        interleaving::insert_interleaved_positions(
+            &mut self.tx,
+            interleaving_id,
+            user_id,
+            positions,
+        )
+        .await
+    }
+
+    async fn get_interleaved_position(
+        &mut self,
+        interleaving_id: &str,
+        user_id: &str,
+        rank: i32,
+        at: i64,
+    ) -> Result<Option<InterleavedPosition>, AppError> {
+        interleaving::get_interleaved_position(&mut self.tx, interleaving_id, user_id, rank, at)
+            .await
+    }
+
+    async fn insert_interleaving_credit(
+        &mut self,
+        interleaving_id: &str,
+        credit: &InterleavingCredit,
+    ) -> Result<(), AppError> {
+        interleaving::insert_interleaving_credit(&mut self.tx, interleaving_id, credit).await
+    }
+
+    async fn get_interleaving_credits(
+        &mut self,
+        interleaving_id: &str,
+    ) -> Result<Vec<InterleavingCredit>, AppError> {
+        interleaving::get_interleaving_credits(&mut self.tx, interleaving_id).await
+    }
+}
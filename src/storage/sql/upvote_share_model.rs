@@ -0,0 +1,270 @@
+use super::Db;
+use crate::common::error::AppError;
+use crate::upvote_share_model::{
+    PageCoefficients, RankObservation, UpvoteShareModel, UpvoteShareModelVersion,
+};
+use sqlx::{query, query_as, query_scalar, Transaction};
+
+/// Upvotes per page and rank from the vote event log, together with the number
+/// of sampling windows in which the rank was observed on the page. Pages whose
+/// ranks are never sampled by the service get an exposure of 1 per rank.
+pub async fn get_rank_observations(
+    tx: &mut Transaction<'_, Db>,
+    training_start: i64,
+    training_end: i64,
+) -> Result<Vec<RankObservation>, AppError> {
+    let observations: Vec<RankObservation> = query_as(
+        "
+        with upvotes_by_rank as (
+            select
+                  page as page_id
+                , rank
+                , count(*) as upvotes
+            from vote_event
+            where vote = 1
+            and page is not null
+            and rank is not null
+            and created_at > $1
+            and created_at <= $2
+            group by page, rank
+        )
+        , exposures_by_rank as (
+            select
+                  page_id
+                , rank
+                , count(*) as exposures
+            from page_rank_history
+            where upvotes is not null
+            and sample_time > $3
+            and sample_time <= $4
+            group by page_id, rank
+        )
+        , sampled_pages as (
+            select distinct page_id
+            from exposures_by_rank
+        )
+        , observed_ranks as (
+            select page_id, rank from upvotes_by_rank
+            union
+            select page_id, rank from exposures_by_rank
+        )
+        select
+              o.page_id
+            , o.rank
+            , coalesce(u.upvotes, 0) as upvotes
+            , coalesce(e.exposures, 1) as exposures
+        from observed_ranks o
+        left outer join upvotes_by_rank u
+        on o.page_id = u.page_id
+        and o.rank = u.rank
+        left outer join exposures_by_rank e
+        on o.page_id = e.page_id
+        and o.rank = e.rank
+        where e.exposures is not null
+        or o.page_id not in (select page_id from sampled_pages)
+        order by o.page_id, o.rank
+        ",
+    )
+    .bind(training_start)
+    .bind(training_end)
+    .bind(training_start)
+    .bind(training_end)
+    .fetch_all(&mut **tx)
+    .await?;
+
+    Ok(observations)
+}
+
+pub async fn insert_model(
+    tx: &mut Transaction<'_, Db>,
+    created_at: i64,
+    training_start: i64,
+    training_end: i64,
+    coefficients: &[PageCoefficients],
+) -> Result<UpvoteShareModel, AppError> {
+    let model_version: i64 = query_scalar(
+        "
+        insert into upvote_share_model (
+              created_at
+            , training_start
+            , training_end
+        ) values ($1, $2, $3)
+        returning model_version
+        ",
+    )
+    .bind(created_at)
+    .bind(training_start)
+    .bind(training_end)
+    .fetch_one(&mut **tx)
+    .await?;
+
+    for c in coefficients {
+        query(
+            "
+            insert into upvote_share_model_coefficients (
+                  model_version
+                , page_id
+                , intercept
+                , log_rank_coefficient
+                , n_observations
+                , deviance
+                , null_deviance
+            ) values ($1, $2, $3, $4, $5, $6, $7)
+            ",
+        )
+        .bind(model_version)
+        .bind(&c.page_id)
+        .bind(c.intercept)
+        .bind(c.log_rank_coefficient)
+        .bind(c.n_observations)
+        .bind(c.deviance)
+        .bind(c.null_deviance)
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    Ok(UpvoteShareModel {
+        model_version,
+        created_at,
+        training_start,
+        training_end,
+        coefficients: coefficients.to_vec(),
+    })
+}
+
+pub async fn get_model_versions(
+    tx: &mut Transaction<'_, Db>,
+) -> Result<Vec<UpvoteShareModelVersion>, AppError> {
+    let versions: Vec<UpvoteShareModelVersion> = query_as(
+        "
+        select
+              m.model_version
+            , m.created_at
+            , m.training_start
+            , m.training_end
+            , a.model_version is not null as active
+        from upvote_share_model m
+        left outer join active_upvote_share_model a
+        on m.model_version = a.model_version
+        order by m.model_version desc
+        ",
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+
+    Ok(versions)
+}
+
+pub async fn get_model(
+    tx: &mut Transaction<'_, Db>,
+    model_version: i64,
+) -> Result<Option<UpvoteShareModel>, AppError> {
+    let model: Option<(i64, i64, i64, i64)> = query_as(
+        "
+        select
+              model_version
+            , created_at
+            , training_start
+            , training_end
+        from upvote_share_model
+        where model_version = $1
+        ",
+    )
+    .bind(model_version)
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    let Some((model_version, created_at, training_start, training_end)) = model else {
+        return Ok(None);
+    };
+
+    let coefficients: Vec<PageCoefficients> = query_as(
+        "
+        select
+              page_id
+            , intercept
+            , log_rank_coefficient
+            , n_observations
+            , deviance
+            , null_deviance
+        from upvote_share_model_coefficients
+        where model_version = $1
+        order by page_id
+        ",
+    )
+    .bind(model_version)
+    .fetch_all(&mut **tx)
+    .await?;
+
+    Ok(Some(UpvoteShareModel {
+        model_version,
+        created_at,
+        training_start,
+        training_end,
+        coefficients,
+    }))
+}
+
+pub async fn get_active_model(
+    tx: &mut Transaction<'_, Db>,
+) -> Result<Option<UpvoteShareModel>, AppError> {
+    let active_version: Option<i64> =
+        query_scalar("select model_version from active_upvote_share_model")
+            .fetch_optional(&mut **tx)
+            .await?;
+
+    match active_version {
+        Some(model_version) => get_model(tx, model_version).await,
+        None => Ok(None),
+    }
+}
+
+pub async fn set_active_model(
+    tx: &mut Transaction<'_, Db>,
+    model_version: i64,
+    activated_at: i64,
+    pinned: bool,
+) -> Result<(), AppError> {
+    query(
+        "
+        insert into active_upvote_share_model (
+              singleton
+            , model_version
+            , activated_at
+            , pinned
+        ) values (1, $1, $2, $3)
+        on conflict (singleton) do update set
+              model_version = excluded.model_version
+            , activated_at  = excluded.activated_at
+            , pinned        = excluded.pinned
+        ",
+    )
+    .bind(model_version)
+    .bind(activated_at)
+    .bind(pinned)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+/// Whether the active model was activated by hand.
+pub async fn is_active_model_pinned(tx: &mut Transaction<'_, Db>) -> Result<bool, AppError> {
+    let pinned: Option<bool> = query_scalar("select pinned from active_upvote_share_model")
+        .fetch_optional(&mut **tx)
+        .await?;
+
+    Ok(pinned.unwrap_or(false))
+}
+
+/// Number of models trained so far and the creation time of the latest one.
+pub async fn get_training_history(
+    tx: &mut Transaction<'_, Db>,
+) -> Result<(i64, Option<i64>), AppError> {
+    let history: (i64, Option<i64>) =
+        query_as("select count(*), max(created_at) from upvote_share_model")
+            .fetch_one(&mut **tx)
+            .await?;
+
+    Ok(history)
+}
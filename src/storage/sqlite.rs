@@ -0,0 +1,73 @@
+use crate::common::error::AppError;
+use crate::storage::{Storage, StorageTransaction};
+use async_trait::async_trait;
+use sqlx::migrate::Migrator;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions, SqliteRow};
+use sqlx::Sqlite;
+use std::str::FromStr;
+
+// The queries in `sql/` are shared with the PostgreSQL backend, so the module
+// is compiled once for each database.
+#[allow(clippy::duplicate_mod)]
+#[path = "sql/mod.rs"]
+mod sql;
+
+pub use sql::SqlTransaction as SqliteTransaction;
+
+type Db = Sqlite;
+type Row = SqliteRow;
+
+mod dialect {
+    use crate::common::error::AppError;
+
+    /// Condition that a column is one of the ids bound as `$1` with
+    /// [`item_ids`].
+    pub const IN_ITEM_IDS: &str = "in (select value from json_each($1))";
+
+    /// SQLite has no arrays, so the ids are bound as a JSON array.
+    pub fn item_ids(item_ids: &[i32]) -> Result<String, AppError> {
+        Ok(serde_json::to_string(item_ids)?)
+    }
+}
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+#[derive(Clone)]
+pub struct SqliteStorage {
+    pool: SqlitePool,
+}
+
+impl SqliteStorage {
+    pub async fn connect(database_url: &str) -> Result<Self, AppError> {
+        let connect_opts = SqliteConnectOptions::from_str(database_url)?
+            .create_if_missing(true)
+            .journal_mode(sqlx::sqlite::SqliteJournalMode::Wal)
+            .synchronous(sqlx::sqlite::SqliteSynchronous::Normal)
+            .busy_timeout(std::time::Duration::from_secs(30))
+            .foreign_keys(true);
+
+        let pool = SqlitePoolOptions::new()
+            .max_connections(5)
+            .connect_with(connect_opts)
+            .await?;
+
+        Ok(Self { pool })
+    }
+
+    /// Applies the migrations in `migrations/` that haven't been run yet.
+    pub async fn migrate(&self) -> Result<(), AppError> {
+        MIGRATOR.run(&self.pool).await?;
+        Ok(())
+    }
+
+    pub fn pool(&self) -> &SqlitePool {
+        &self.pool
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn begin(&self) -> Result<Box<dyn StorageTransaction>, AppError> {
+        Ok(Box::new(SqliteTransaction::new(self.pool.begin().await?)))
+    }
+}
@@ -0,0 +1,444 @@
+use crate::algs::{
+    best::{wilson_lower_bound, Best},
+    quality_news::{
+        model::{QnStats, UpvoteRatePrior},
+        QualityNews,
+    },
+};
+use crate::common::{
+    error::AppError,
+    model::{Item, ItemAggregate},
+};
+use crate::storage::StorageTransaction;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+/// Levels of replies returned unless requested otherwise.
+pub const DEFAULT_DEPTH: usize = 8;
+/// Upper bound on the requested depth.
+pub const MAX_DEPTH: usize = 100;
+
+/// Order of the replies to an item.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ThreadSort {
+    /// Lower bound of the Wilson score interval, as in the best algorithm.
+    #[default]
+    Best,
+    /// Submission time, without a score.
+    Newest,
+    /// Estimated upvote rate, as in quality news. Replies get no expected
+    /// upvotes from sampling, so each is expected to receive the upvotes of
+    /// its siblings in proportion to the time it has been visible.
+    Quality,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ThreadQuery {
+    pub sort: Option<ThreadSort>,
+    /// Levels of replies below the item to return.
+    pub depth: Option<usize>,
+    /// Replies returned per item.
+    pub limit: Option<usize>,
+}
+
+/// An item with its replies, ordered by the sort of the thread.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ThreadNode {
+    pub item_id: i32,
+    pub author_id: String,
+    pub created_at: i64,
+    pub upvotes: i32,
+    pub downvotes: i32,
+    /// The value the item is sorted by among its siblings: its Wilson score
+    /// lower bound or estimated upvote rate. Absent when sorting by newest,
+    /// and for the item itself when sorting by quality.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score: Option<f64>,
+    /// Number of replies below the item at any depth.
+    pub descendants: usize,
+    /// Number of those replies left out because they are below the depth or
+    /// reply limit here, including the replies to them. Replies left out
+    /// further down are counted by the returned replies.
+    pub collapsed: usize,
+    pub replies: Vec<ThreadNode>,
+}
+
+/// The item with the replies below it, up to `depth` levels and `limit`
+/// replies per item.
+pub async fn thread(
+    tx: &mut dyn StorageTransaction,
+    item_id: i32,
+    query: &ThreadQuery,
+    now: i64,
+) -> Result<ThreadNode, AppError> {
+    let depth = query.depth.unwrap_or(DEFAULT_DEPTH);
+    if depth > MAX_DEPTH {
+        return Err(AppError::Validation(format!(
+            "Depth must be at most {}: {}",
+            MAX_DEPTH, depth
+        )));
+    }
+
+    let items = tx.get_thread(item_id).await?;
+    if !items.iter().any(|item| item.item_id == item_id) {
+        return Err(AppError::NotFound(format!("Unknown item: {}", item_id)));
+    }
+    let item_ids: Vec<i32> = items.iter().map(|item| item.item_id).collect();
+    let aggregates: HashMap<i32, ItemAggregate> = tx
+        .get_item_aggregates(&item_ids)
+        .await?
+        .into_iter()
+        .map(|aggregate| (aggregate.item_id, aggregate))
+        .collect();
+
+    let tree = Tree::new(items, item_id);
+    let scores = tree.scores(&aggregates, query.sort.unwrap_or_default(), now);
+
+    Ok(tree.node(
+        item_id,
+        &aggregates,
+        &scores,
+        depth,
+        query.limit.unwrap_or(usize::MAX),
+    ))
+}
+
+struct Tree {
+    items: HashMap<i32, Item>,
+    replies: HashMap<i32, Vec<i32>>,
+    descendants: HashMap<i32, usize>,
+}
+
+impl Tree {
+    fn new(items: Vec<Item>, root: i32) -> Self {
+        let items: HashMap<i32, Item> =
+            items.into_iter().map(|item| (item.item_id, item)).collect();
+        let replies: HashMap<i32, Vec<i32>> = items
+            .values()
+            .filter(|item| item.item_id != root)
+            .filter_map(|item| Some((item.parent_id?, item.item_id)))
+            .into_group_map();
+
+        // Count descendants bottom-up, in reverse breadth-first order
+        let mut order = Vec::with_capacity(items.len());
+        let mut queue = VecDeque::from([root]);
+        while let Some(item_id) = queue.pop_front() {
+            order.push(item_id);
+            queue.extend(replies.get(&item_id).into_iter().flatten());
+        }
+        let mut descendants: HashMap<i32, usize> = HashMap::new();
+        for item_id in order.iter().rev() {
+            let count = replies
+                .get(item_id)
+                .into_iter()
+                .flatten()
+                .map(|reply| 1 + descendants[reply])
+                .sum();
+            descendants.insert(*item_id, count);
+        }
+
+        Self {
+            items,
+            replies,
+            descendants,
+        }
+    }
+
+    /// Scores of the replies, comparable among siblings. Newest has none, as
+    /// replies without a score are ordered by submission time.
+    fn scores(
+        &self,
+        aggregates: &HashMap<i32, ItemAggregate>,
+        sort: ThreadSort,
+        now: i64,
+    ) -> HashMap<i32, f64> {
+        let votes = |item_id: &i32| {
+            aggregates
+                .get(item_id)
+                .map_or((0, 0), |a| (a.upvotes, a.downvotes))
+        };
+
+        match sort {
+            ThreadSort::Best => {
+                let z = Best::default().z();
+                self.items
+                    .keys()
+                    .map(|item_id| {
+                        let (upvotes, downvotes) = votes(item_id);
+                        (*item_id, wilson_lower_bound(upvotes, downvotes, z))
+                    })
+                    .collect()
+            }
+            ThreadSort::Newest => HashMap::new(),
+            ThreadSort::Quality => {
+                let params = QualityNews::default();
+                let mut scores = HashMap::new();
+                for siblings in self.replies.values() {
+                    let upvotes: i32 = siblings.iter().map(|item_id| votes(item_id).0).sum();
+                    let visible = |item_id: &i32| (now - self.items[item_id].created_at).max(0);
+                    let total_visible: i64 = siblings.iter().map(visible).sum();
+                    let stats: Vec<QnStats> = siblings
+                        .iter()
+                        .map(|item_id| QnStats {
+                            item_id: *item_id,
+                            updated_at: now,
+                            sample_time: now,
+                            submission_time: self.items[item_id].created_at,
+                            cumulative_upvotes: votes(item_id).0,
+                            cumulative_expected_upvotes: if total_visible > 0 {
+                                upvotes as f32 * visible(item_id) as f32 / total_visible as f32
+                            } else {
+                                0.0
+                            },
+                        })
+                        .collect();
+                    let prior = UpvoteRatePrior::from_stats(&stats, params.prior_strength);
+                    scores.extend(
+                        stats
+                            .iter()
+                            .map(|stats| (stats.item_id, prior.posterior_mean(stats))),
+                    );
+                }
+                scores
+            }
+        }
+    }
+
+    fn node(
+        &self,
+        item_id: i32,
+        aggregates: &HashMap<i32, ItemAggregate>,
+        scores: &HashMap<i32, f64>,
+        depth: usize,
+        limit: usize,
+    ) -> ThreadNode {
+        let item = &self.items[&item_id];
+        let descendants = self.descendants[&item_id];
+        let replies: Vec<ThreadNode> = if depth == 0 {
+            Vec::new()
+        } else {
+            self.replies
+                .get(&item_id)
+                .into_iter()
+                .flatten()
+                .map(|reply| {
+                    (
+                        scores.get(reply).copied().unwrap_or(0.0),
+                        &self.items[reply],
+                    )
+                })
+                .sorted_by(|(a, a_item), (b, b_item)| {
+                    b.total_cmp(a)
+                        .then_with(|| b_item.created_at.cmp(&a_item.created_at))
+                        .then_with(|| b_item.item_id.cmp(&a_item.item_id))
+                })
+                .take(limit)
+                .map(|(_, reply)| self.node(reply.item_id, aggregates, scores, depth - 1, limit))
+                .collect()
+        };
+        let shown: usize = replies.iter().map(|reply| 1 + reply.descendants).sum();
+        let (upvotes, downvotes) = aggregates
+            .get(&item_id)
+            .map_or((0, 0), |a| (a.upvotes, a.downvotes));
+
+        ThreadNode {
+            item_id,
+            author_id: item.author_id.clone(),
+            created_at: item.created_at,
+            upvotes,
+            downvotes,
+            score: scores.get(&item_id).copied(),
+            descendants,
+            collapsed: descendants - shown,
+            replies,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::model::VoteEvent;
+    use crate::storage::memory::{
+        fixtures::{self, item, vote_event},
+        MemoryStorage,
+    };
+    use crate::storage::Storage;
+
+    const NOW: i64 = 1_700_000_000_000;
+    const HOUR: i64 = 60 * 60 * 1000;
+
+    fn reply(item_id: i32, parent_id: i32, created_at: i64) -> Item {
+        Item {
+            parent_id: Some(parent_id),
+            ..item(item_id, created_at)
+        }
+    }
+
+    /// Item 1 with replies 2, 3 and 4, where 2 has the chain of replies 5
+    /// and 6 below it. Best, newest and quality each order 2, 3 and 4
+    /// differently.
+    async fn storage() -> MemoryStorage {
+        let storage = MemoryStorage::new();
+        let items = [
+            item(1, NOW - 10 * HOUR),
+            reply(2, 1, NOW - 9 * HOUR),
+            reply(3, 1, NOW - 2 * HOUR),
+            reply(4, 1, NOW - HOUR),
+            reply(5, 2, NOW - 8 * HOUR),
+            reply(6, 5, NOW - 7 * HOUR),
+        ];
+        let votes = [
+            (2, "a", 1),
+            (2, "b", 1),
+            (2, "c", 1),
+            (3, "a", 1),
+            (4, "a", -1),
+        ];
+        let vote_events: Vec<VoteEvent> = votes
+            .into_iter()
+            .enumerate()
+            .map(|(i, (item_id, user_id, vote))| {
+                vote_event(i as i32 + 1, item_id, user_id, vote, NOW - HOUR / 2)
+            })
+            .collect();
+        fixtures::insert(&storage, &items, &vote_events).await;
+        storage
+    }
+
+    async fn get(storage: &MemoryStorage, item_id: i32, query: ThreadQuery) -> ThreadNode {
+        let mut tx = storage.begin().await.unwrap();
+        thread(&mut *tx, item_id, &query, NOW).await.unwrap()
+    }
+
+    fn reply_ids(node: &ThreadNode) -> Vec<i32> {
+        node.replies.iter().map(|reply| reply.item_id).collect()
+    }
+
+    #[tokio::test]
+    async fn each_sort_orders_the_replies() {
+        let storage = storage().await;
+        let sorted = |sort| ThreadQuery {
+            sort: Some(sort),
+            ..Default::default()
+        };
+
+        let best = get(&storage, 1, sorted(ThreadSort::Best)).await;
+        assert_eq!(reply_ids(&best), [2, 3, 4]);
+        assert_eq!((best.replies[0].upvotes, best.replies[2].downvotes), (3, 1));
+
+        let newest = get(&storage, 1, sorted(ThreadSort::Newest)).await;
+        assert_eq!(reply_ids(&newest), [4, 3, 2]);
+
+        // 3 got its upvote in two hours, 2 its upvotes in nine
+        let quality = get(&storage, 1, sorted(ThreadSort::Quality)).await;
+        assert_eq!(reply_ids(&quality), [3, 2, 4]);
+    }
+
+    #[tokio::test]
+    async fn scores_are_the_values_sorted_by() {
+        let storage = storage().await;
+        let sorted = |sort| ThreadQuery {
+            sort: Some(sort),
+            ..Default::default()
+        };
+
+        let best = get(&storage, 1, sorted(ThreadSort::Best)).await;
+        let z = Best::default().z();
+        assert_eq!(best.replies[0].score, Some(wilson_lower_bound(3, 0, z)));
+        assert_eq!(best.replies[2].score, Some(0.0));
+
+        let newest = get(&storage, 1, sorted(ThreadSort::Newest)).await;
+        assert!(newest.score.is_none());
+        assert!(newest.replies.iter().all(|reply| reply.score.is_none()));
+
+        let quality = get(&storage, 1, sorted(ThreadSort::Quality)).await;
+        assert!(quality.score.is_none());
+        assert!(quality.replies.iter().all(|reply| reply.score.is_some()));
+    }
+
+    #[tokio::test]
+    async fn depth_limits_the_levels_and_collapses_the_rest() {
+        let storage = storage().await;
+        let deep = get(&storage, 1, ThreadQuery::default()).await;
+        assert_eq!((deep.descendants, deep.collapsed), (5, 0));
+        assert_eq!(reply_ids(&deep.replies[0].replies[0]), [6]);
+
+        let shallow = get(
+            &storage,
+            1,
+            ThreadQuery {
+                depth: Some(1),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!((shallow.descendants, shallow.collapsed), (5, 0));
+        let first = &shallow.replies[0];
+        assert_eq!(first.item_id, 2);
+        assert!(first.replies.is_empty());
+        assert_eq!((first.descendants, first.collapsed), (2, 2));
+
+        let root_only = get(
+            &storage,
+            1,
+            ThreadQuery {
+                depth: Some(0),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert!(root_only.replies.is_empty());
+        assert_eq!(root_only.collapsed, 5);
+    }
+
+    #[tokio::test]
+    async fn limit_keeps_the_first_replies_of_each_item() {
+        let storage = storage().await;
+        let limited = get(
+            &storage,
+            1,
+            ThreadQuery {
+                limit: Some(1),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        assert_eq!(reply_ids(&limited), [2]);
+        // 3 and 4 are left out, while 5 and 6 are shown below 2
+        assert_eq!(limited.collapsed, 2);
+        assert_eq!(limited.replies[0].collapsed, 0);
+    }
+
+    #[tokio::test]
+    async fn a_reply_is_the_root_of_its_own_thread() {
+        let storage = storage().await;
+        let node = get(&storage, 5, ThreadQuery::default()).await;
+
+        assert_eq!((node.item_id, node.descendants), (5, 1));
+        assert_eq!(reply_ids(&node), [6]);
+    }
+
+    #[tokio::test]
+    async fn invalid_requests_are_rejected() {
+        let storage = storage().await;
+        let mut tx = storage.begin().await.unwrap();
+
+        let too_deep = ThreadQuery {
+            depth: Some(MAX_DEPTH + 1),
+            ..Default::default()
+        };
+        assert!(matches!(
+            thread(&mut *tx, 1, &too_deep, NOW).await,
+            Err(AppError::Validation(_))
+        ));
+        assert!(matches!(
+            thread(&mut *tx, 7, &ThreadQuery::default(), NOW).await,
+            Err(AppError::NotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,556 @@
+use crate::common::{error::AppError, time::parse_duration};
+use crate::storage::StorageTransaction;
+use anyhow::anyhow;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "sqlx")]
+use sqlx::FromRow;
+use std::env;
+use tracing::info;
+
+const MAX_ITERATIONS: usize = 100;
+const CONVERGENCE_TOLERANCE: f64 = 1e-9;
+
+/// How often the scheduler checks whether the model is due for retraining.
+pub const RETRAINING_CHECK_SCHEDULE: &str = "0 * * * * *";
+
+/// A retraining interval that applies for a number of retrainings, or
+/// indefinitely if `repetitions` is `None`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CadenceStage {
+    pub interval: i64,
+    pub repetitions: Option<i64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RetrainingConfig {
+    pub cadence: Vec<CadenceStage>,
+    pub training_window: i64,
+}
+
+impl Default for RetrainingConfig {
+    /// Hourly for the first day, then daily for a week, then weekly.
+    fn default() -> Self {
+        Self {
+            cadence: parse_cadence("1h:24,1d:7,1w").unwrap(),
+            training_window: parse_duration("7d").unwrap(),
+        }
+    }
+}
+
+impl RetrainingConfig {
+    /// Reads `UPVOTE_SHARE_MODEL_CADENCE` (e.g. `1h:24,1d:7,1w`) and
+    /// `UPVOTE_SHARE_MODEL_TRAINING_WINDOW` (e.g. `7d`), falling back to the
+    /// defaults for unset variables.
+    pub fn from_env() -> Result<Self, AppError> {
+        let default = Self::default();
+        let cadence = match env::var("UPVOTE_SHARE_MODEL_CADENCE") {
+            Ok(spec) => parse_cadence(&spec)?,
+            Err(_) => default.cadence,
+        };
+        let training_window = match env::var("UPVOTE_SHARE_MODEL_TRAINING_WINDOW") {
+            Ok(spec) => parse_duration(&spec)?,
+            Err(_) => default.training_window,
+        };
+
+        Ok(Self {
+            cadence,
+            training_window,
+        })
+    }
+
+    /// Retraining interval after `n_models` models have been trained.
+    fn interval(&self, n_models: i64) -> Option<i64> {
+        let mut remaining = n_models;
+        for stage in &self.cadence {
+            match stage.repetitions {
+                Some(repetitions) if remaining >= repetitions => remaining -= repetitions,
+                _ => return Some(stage.interval),
+            }
+        }
+        self.cadence.last().map(|stage| stage.interval)
+    }
+}
+
+/// Parses a comma separated list of `<duration>[:<repetitions>]` stages. Only
+/// the last stage may omit the repetitions, and it then applies indefinitely.
+fn parse_cadence(spec: &str) -> Result<Vec<CadenceStage>, AppError> {
+    let stages: Vec<CadenceStage> = spec
+        .split(',')
+        .map(|stage| {
+            let (interval, repetitions) = match stage.trim().split_once(':') {
+                Some((interval, repetitions)) => (
+                    interval,
+                    Some(
+                        repetitions
+                            .parse()
+                            .ok()
+                            .filter(|repetitions| *repetitions > 0)
+                            .ok_or_else(|| anyhow!("Invalid cadence stage: {}", stage))?,
+                    ),
+                ),
+                None => (stage.trim(), None),
+            };
+            let interval = parse_duration(interval)?;
+            if interval <= 0 {
+                return Err(anyhow!("Invalid cadence stage: {}", stage).into());
+            }
+
+            Ok(CadenceStage {
+                interval,
+                repetitions,
+            })
+        })
+        .collect::<Result<_, AppError>>()?;
+
+    let (_, earlier_stages) = stages.split_last().expect("split yields a stage");
+    if earlier_stages
+        .iter()
+        .any(|stage| stage.repetitions.is_none())
+    {
+        return Err(anyhow!(
+            "Only the last cadence stage may repeat indefinitely: {}",
+            spec
+        )
+        .into());
+    }
+
+    Ok(stages)
+}
+
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RankObservation {
+    pub page_id: String,
+    pub rank: i32,
+    pub upvotes: i64,
+    pub exposures: i64,
+}
+
+/// Coefficients of the Poisson regression
+/// `log(upvotes) = log(exposures) + intercept + log_rank_coefficient * log(rank)`
+/// for a single page, along with the deviance of the fit and of the
+/// intercept-only model as goodness-of-fit measures.
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PageCoefficients {
+    pub page_id: String,
+    pub intercept: f64,
+    pub log_rank_coefficient: f64,
+    pub n_observations: i64,
+    pub deviance: f64,
+    pub null_deviance: f64,
+}
+
+impl PageCoefficients {
+    /// Expected upvotes per sampling window for an item shown at `rank`.
+    pub fn upvote_rate(&self, rank: i32) -> f64 {
+        (self.intercept + self.log_rank_coefficient * (rank as f64).ln()).exp()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UpvoteShareModel {
+    pub model_version: i64,
+    pub created_at: i64,
+    pub training_start: i64,
+    pub training_end: i64,
+    pub coefficients: Vec<PageCoefficients>,
+}
+
+#[cfg_attr(feature = "sqlx", derive(FromRow))]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UpvoteShareModelVersion {
+    pub model_version: i64,
+    pub created_at: i64,
+    pub training_start: i64,
+    pub training_end: i64,
+    pub active: bool,
+}
+
+impl UpvoteShareModel {
+    pub fn page(&self, page_id: &str) -> Option<&PageCoefficients> {
+        self.coefficients.iter().find(|c| c.page_id == page_id)
+    }
+
+    /// Predicts each item's share of the upvotes from its rank exposures.
+    /// Exposures on pages without coefficients are ignored. Returns `None` if
+    /// the model covers none of the exposures or predicts no upvotes at all.
+    pub fn predict_upvote_shares(&self, exposures: &[Vec<RankExposure>]) -> Option<Vec<f32>> {
+        let rates: Vec<f64> = exposures
+            .iter()
+            .map(|item_exposures| {
+                item_exposures
+                    .iter()
+                    .filter_map(|e| Some(e.weight * self.page(e.page_id)?.upvote_rate(e.rank)))
+                    .sum()
+            })
+            .collect();
+
+        let total_rate: f64 = rates.iter().sum();
+        if total_rate <= 0.0 || !total_rate.is_finite() {
+            return None;
+        }
+
+        Some(rates.iter().map(|r| (r / total_rate) as f32).collect())
+    }
+}
+
+/// Fraction of an interval an item spent at a rank on a page.
+#[derive(Debug, Clone)]
+pub struct RankExposure<'a> {
+    pub page_id: &'a str,
+    pub rank: i32,
+    pub weight: f64,
+}
+
+/// Fits a new model from the rank observations in the training window ending
+/// at `now`, persists it and makes it the active model, replacing a pinned
+/// one. Returns `None` without persisting anything if no page has enough
+/// observations.
+pub async fn train(
+    tx: &mut dyn StorageTransaction,
+    training_window: i64,
+    now: i64,
+) -> Result<Option<UpvoteShareModel>, AppError> {
+    let model = fit(tx, training_window, now).await?;
+    if let Some(model) = &model {
+        tx.set_active_model(model.model_version, now, false).await?;
+    }
+
+    Ok(model)
+}
+
+/// Fits and persists a new model without activating it.
+async fn fit(
+    tx: &mut dyn StorageTransaction,
+    training_window: i64,
+    now: i64,
+) -> Result<Option<UpvoteShareModel>, AppError> {
+    let training_start = now - training_window;
+    let observations = tx.get_rank_observations(training_start, now).await?;
+
+    let coefficients: Vec<PageCoefficients> = observations
+        .iter()
+        .chunk_by(|o| o.page_id.clone())
+        .into_iter()
+        .filter_map(|(page_id, page_observations)| {
+            let page_observations: Vec<&RankObservation> = page_observations.collect();
+            let coefficients = fit_page(&page_id, &page_observations);
+            if coefficients.is_none() {
+                info!(
+                    "Not enough observations to fit upvote share model for page {}",
+                    page_id
+                );
+            }
+            coefficients
+        })
+        .collect();
+
+    if coefficients.is_empty() {
+        return Ok(None);
+    }
+
+    let model = tx
+        .insert_model(now, training_start, now, &coefficients)
+        .await?;
+
+    Ok(Some(model))
+}
+
+/// Retrains the model if the interval of the current cadence stage has passed
+/// since the latest model was trained. The new model is only activated if the
+/// active one wasn't activated by hand.
+pub async fn retrain_if_due(
+    tx: &mut dyn StorageTransaction,
+    config: &RetrainingConfig,
+    now: i64,
+) -> Result<Option<UpvoteShareModel>, AppError> {
+    let (n_models, latest_training) = tx.get_training_history().await?;
+    let is_due = match (latest_training, config.interval(n_models)) {
+        (None, _) => true,
+        (Some(latest), Some(interval)) => now - latest >= interval,
+        (Some(_), None) => false,
+    };
+    if !is_due {
+        return Ok(None);
+    }
+
+    info!("Retraining upvote share model at: {:?}", now);
+    let model = fit(tx, config.training_window, now).await?;
+    if let Some(model) = &model {
+        if tx.is_active_model_pinned().await? {
+            info!(
+                "Keeping the pinned upvote share model, not activating version {}",
+                model.model_version
+            );
+        } else {
+            tx.set_active_model(model.model_version, now, false).await?;
+        }
+    }
+
+    Ok(model)
+}
+
+/// Makes a previously trained model the active one, e.g. to roll back a
+/// retraining. The model is pinned, so scheduled retraining doesn't replace
+/// it until a model is trained by hand.
+pub async fn activate(
+    tx: &mut dyn StorageTransaction,
+    model_version: i64,
+    now: i64,
+) -> Result<UpvoteShareModel, AppError> {
+    let model = tx.get_model(model_version).await?.ok_or_else(|| {
+        AppError::NotFound(format!(
+            "Unknown upvote share model version: {}",
+            model_version
+        ))
+    })?;
+    tx.set_active_model(model_version, now, true).await?;
+
+    Ok(model)
+}
+
+/// Fits the Poisson regression for a single page with Newton-Raphson. Returns
+/// `None` if the page has no upvotes or fewer than two distinct ranks.
+fn fit_page(page_id: &str, observations: &[&RankObservation]) -> Option<PageCoefficients> {
+    let total_upvotes: i64 = observations.iter().map(|o| o.upvotes).sum();
+    let total_exposures: i64 = observations.iter().map(|o| o.exposures).sum();
+    if total_upvotes == 0 || observations.iter().map(|o| o.rank).all_equal() {
+        return None;
+    }
+
+    let mut intercept = (total_upvotes as f64 / total_exposures as f64).ln();
+    let mut slope = 0.0;
+
+    for _ in 0..MAX_ITERATIONS {
+        let (mut g0, mut g1, mut h00, mut h01, mut h11) = (0.0, 0.0, 0.0, 0.0, 0.0);
+        for o in observations {
+            let x = (o.rank as f64).ln();
+            let mu = o.exposures as f64 * (intercept + slope * x).exp();
+            let residual = o.upvotes as f64 - mu;
+            g0 += residual;
+            g1 += residual * x;
+            h00 += mu;
+            h01 += mu * x;
+            h11 += mu * x * x;
+        }
+
+        let det = h00 * h11 - h01 * h01;
+        if det.abs() < f64::EPSILON {
+            return None;
+        }
+        let step_intercept = (h11 * g0 - h01 * g1) / det;
+        let step_slope = (h00 * g1 - h01 * g0) / det;
+        intercept += step_intercept;
+        slope += step_slope;
+
+        if step_intercept.abs().max(step_slope.abs()) < CONVERGENCE_TOLERANCE {
+            break;
+        }
+    }
+
+    if !intercept.is_finite() || !slope.is_finite() {
+        return None;
+    }
+
+    let null_rate = total_upvotes as f64 / total_exposures as f64;
+    let (deviance, null_deviance) = observations
+        .iter()
+        .map(|o| {
+            let x = (o.rank as f64).ln();
+            let mu = o.exposures as f64 * (intercept + slope * x).exp();
+            let null_mu = o.exposures as f64 * null_rate;
+            (
+                poisson_deviance(o.upvotes, mu),
+                poisson_deviance(o.upvotes, null_mu),
+            )
+        })
+        .fold((0.0, 0.0), |(d, nd), (o_d, o_nd)| (d + o_d, nd + o_nd));
+
+    Some(PageCoefficients {
+        page_id: page_id.to_string(),
+        intercept,
+        log_rank_coefficient: slope,
+        n_observations: observations.len() as i64,
+        deviance,
+        null_deviance,
+    })
+}
+
+fn poisson_deviance(observed: i64, expected: f64) -> f64 {
+    let observed = observed as f64;
+    let log_ratio = if observed > 0.0 {
+        observed * (observed / expected).ln()
+    } else {
+        0.0
+    };
+    2.0 * (log_ratio - (observed - expected))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::model::VoteEvent;
+    use crate::storage::memory::{
+        fixtures::{self, item, vote_event},
+        MemoryStorage,
+    };
+    use crate::storage::Storage;
+
+    const NOW: i64 = 1_700_000_000_000;
+    const HOUR: i64 = 60 * 60 * 1000;
+
+    fn observation(rank: i32, upvotes: i64, exposures: i64) -> RankObservation {
+        RankObservation {
+            page_id: "page".to_string(),
+            rank,
+            upvotes,
+            exposures,
+        }
+    }
+
+    fn fit_observations(observations: &[RankObservation]) -> Option<PageCoefficients> {
+        fit_page("page", &observations.iter().collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn fit_recovers_known_coefficients() {
+        let (intercept, log_rank_coefficient) = (-2.0, -0.7);
+        let exposures = 1_000_000;
+        let observations: Vec<RankObservation> = (1..=30)
+            .map(|rank| {
+                let rate = (intercept + log_rank_coefficient * (rank as f64).ln()).exp();
+                observation(rank, (exposures as f64 * rate).round() as i64, exposures)
+            })
+            .collect();
+
+        let coefficients = fit_observations(&observations).unwrap();
+        assert!((coefficients.intercept - intercept).abs() < 1e-3);
+        assert!((coefficients.log_rank_coefficient - log_rank_coefficient).abs() < 1e-3);
+        assert_eq!(coefficients.n_observations, 30);
+        assert!(coefficients.deviance < 1.0);
+        assert!(coefficients.null_deviance > 1000.0 * coefficients.deviance);
+    }
+
+    #[test]
+    fn fit_needs_more_than_one_rank() {
+        assert!(fit_observations(&[observation(3, 10, 100)]).is_none());
+        assert!(fit_observations(&[observation(3, 10, 100), observation(3, 12, 100)]).is_none());
+    }
+
+    #[test]
+    fn fit_needs_upvotes() {
+        let observations = [observation(1, 0, 100), observation(2, 0, 100)];
+        assert!(fit_observations(&observations).is_none());
+    }
+
+    #[test]
+    fn fit_needs_observations() {
+        assert!(fit_observations(&[]).is_none());
+    }
+
+    #[test]
+    fn deviance_of_a_perfect_fit_is_zero() {
+        assert_eq!(poisson_deviance(5, 5.0), 0.0);
+        assert_eq!(poisson_deviance(0, 2.5), 5.0);
+        assert!(poisson_deviance(5, 2.5) > 0.0);
+    }
+
+    #[test]
+    fn cadence_stages_switch_after_their_repetitions() {
+        let config = RetrainingConfig::default();
+        let (hour, day, week) = (HOUR, 24 * HOUR, 7 * 24 * HOUR);
+
+        for (n_models, interval) in [
+            (0, hour),
+            (23, hour),
+            (24, day),
+            (30, day),
+            (31, week),
+            (1000, week),
+        ] {
+            assert_eq!(config.interval(n_models), Some(interval), "{}", n_models);
+        }
+    }
+
+    #[test]
+    fn invalid_cadences_are_rejected() {
+        for spec in [
+            "", "soon", "1h:x", "1h:0", "1h:-3", "0h", "1h:24,", "1h,1d:7",
+        ] {
+            assert!(parse_cadence(spec).is_err(), "{}", spec);
+        }
+    }
+
+    /// Upvotes on the newest page at ranks 1 and 2.
+    async fn storage_with_rank_upvotes() -> MemoryStorage {
+        let storage = MemoryStorage::new();
+        let vote_events: Vec<VoteEvent> = [1, 1, 1, 2]
+            .into_iter()
+            .enumerate()
+            .map(|(i, rank)| VoteEvent {
+                rank: Some(rank),
+                page: Some("newest".to_string()),
+                ..vote_event(i as i32 + 1, 1, &format!("user-{}", i), 1, NOW - HOUR)
+            })
+            .collect();
+        fixtures::insert(&storage, &[item(1, NOW - 2 * HOUR)], &vote_events).await;
+        storage
+    }
+
+    async fn active_version(tx: &mut dyn StorageTransaction) -> Option<i64> {
+        tx.get_active_model()
+            .await
+            .unwrap()
+            .map(|model| model.model_version)
+    }
+
+    #[tokio::test]
+    async fn retraining_follows_the_cadence() {
+        let storage = storage_with_rank_upvotes().await;
+        let mut tx = storage.begin().await.unwrap();
+        let config = RetrainingConfig {
+            cadence: parse_cadence("1h:2,1d").unwrap(),
+            ..RetrainingConfig::default()
+        };
+
+        for (now, retrained) in [
+            (NOW, true),
+            (NOW + HOUR - 1, false),
+            (NOW + HOUR, true),
+            (NOW + 2 * HOUR, false),
+            (NOW + 25 * HOUR, true),
+        ] {
+            let model = retrain_if_due(&mut *tx, &config, now).await.unwrap();
+            assert_eq!(model.is_some(), retrained, "{}", now - NOW);
+        }
+        assert_eq!(active_version(&mut *tx).await, Some(3));
+    }
+
+    #[tokio::test]
+    async fn pinned_models_stay_active_through_retraining() {
+        let storage = storage_with_rank_upvotes().await;
+        let mut tx = storage.begin().await.unwrap();
+        let config = RetrainingConfig {
+            cadence: parse_cadence("1h").unwrap(),
+            ..RetrainingConfig::default()
+        };
+
+        retrain_if_due(&mut *tx, &config, NOW).await.unwrap();
+        activate(&mut *tx, 1, NOW).await.unwrap();
+        let model = retrain_if_due(&mut *tx, &config, NOW + HOUR).await.unwrap();
+        assert_eq!(model.map(|model| model.model_version), Some(2));
+        assert_eq!(active_version(&mut *tx).await, Some(1));
+
+        // Training by hand replaces the pinned model
+        train(&mut *tx, config.training_window, NOW + HOUR)
+            .await
+            .unwrap();
+        assert_eq!(active_version(&mut *tx).await, Some(3));
+        retrain_if_due(&mut *tx, &config, NOW + 2 * HOUR)
+            .await
+            .unwrap();
+        assert_eq!(active_version(&mut *tx).await, Some(4));
+    }
+}